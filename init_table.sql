@@ -20,7 +20,8 @@ CREATE TABLE IF NOT EXISTS courses (
     tea_name VARCHAR(50) NOT NULL,
     intro TEXT NOT NULL,
     mailbox VARCHAR(200) NOT NULL,
-    term INTEGER NOT NULL
+    term INTEGER NOT NULL,
+    allowed_mime VARCHAR(200) NOT NULL DEFAULT ''
 );
 
 CREATE TABLE IF NOT EXISTS labrooms (
@@ -28,7 +29,8 @@ CREATE TABLE IF NOT EXISTS labrooms (
     room VARCHAR(10) NOT NULL,
     name VARCHAR(30) NOT NULL,
     manager VARCHAR(10) NOT NULL,
-    tea_id VARCHAR(10) NOT NULL
+    tea_id VARCHAR(10) NOT NULL,
+    allowed_ips VARCHAR(200) NOT NULL DEFAULT ''
 );
 
 CREATE TABLE IF NOT EXISTS subcourses (
@@ -59,11 +61,39 @@ CREATE TABLE IF NOT EXISTS course_schedules (
     course_id INTEGER NOT NULL REFERENCES courses (id)
 );
 
+CREATE TABLE IF NOT EXISTS course_folders (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    course_id INTEGER NOT NULL REFERENCES courses (id),
+    parent_id INTEGER NULL REFERENCES course_folders (id),
+    name VARCHAR(100) NOT NULL
+);
+
 CREATE TABLE IF NOT EXISTS course_files (
     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
     fname VARCHAR(100) NOT NULL,
     finfo VARCHAR(100) NOT NULL,
-    course_id INTEGER NOT NULL REFERENCES courses (id)
+    course_id INTEGER NOT NULL REFERENCES courses (id),
+    file_id INTEGER NULL REFERENCES stored_files (id),
+    folder_id INTEGER NULL REFERENCES course_folders (id),
+    version INTEGER NOT NULL DEFAULT 1,
+    visible_from datetime NULL,
+    visibility INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE IF NOT EXISTS course_file_versions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    course_file_id INTEGER NOT NULL REFERENCES course_files (id),
+    version INTEGER NOT NULL,
+    file_id INTEGER NOT NULL REFERENCES stored_files (id),
+    uploader VARCHAR(10) NOT NULL,
+    created_at datetime NOT NULL,
+    UNIQUE (course_file_id, version)
+);
+
+CREATE TABLE IF NOT EXISTS course_file_schedules (
+    course_file_id INTEGER NOT NULL REFERENCES course_files (id),
+    schedule_id INTEGER NOT NULL REFERENCES course_schedules (id),
+    PRIMARY KEY (course_file_id, schedule_id)
 );
 
 CREATE TABLE IF NOT EXISTS student_logs (
@@ -78,7 +108,8 @@ CREATE TABLE IF NOT EXISTS student_logs (
     tea_note VARCHAR(50) NOT NULL,
     tea_name VARCHAR(10) NOT NULL,
     fin_time datetime NOT NULL,
-    confirm INTEGER NOT NULL
+    confirm INTEGER NOT NULL,
+    session_id INTEGER NULL REFERENCES lab_sessions (id)
 );
 
 CREATE TABLE IF NOT EXISTS subschedules (
@@ -97,7 +128,52 @@ CREATE TABLE IF NOT EXISTS student_timelines (
     subcourse_id INTEGER NOT NULL REFERENCES subcourses (id),
     note VARCHAR(100) NOT NULL,
     notetype INTEGER NOT NULL,
-    timestamp datetime NOT NULL
+    timestamp datetime NOT NULL,
+    session_id INTEGER NULL REFERENCES lab_sessions (id),
+    subschedule_id INTEGER NULL REFERENCES subschedules (id),
+    file_id INTEGER NULL REFERENCES stored_files (id),
+    body TEXT NOT NULL DEFAULT '',
+    body_html TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS timeline_attachments (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    timeline_id INTEGER NOT NULL REFERENCES student_timelines (id),
+    position INTEGER NOT NULL,
+    file_id INTEGER NOT NULL REFERENCES stored_files (id),
+    UNIQUE (timeline_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS timeline_comments (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    timeline_id INTEGER NOT NULL REFERENCES student_timelines (id),
+    parent_id INTEGER NULL REFERENCES timeline_comments (id),
+    author_id VARCHAR(10) NOT NULL,
+    author_name VARCHAR(50) NOT NULL,
+    body TEXT NOT NULL,
+    body_html TEXT NOT NULL,
+    created_at datetime NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS timeline_feedbacks (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    timeline_id INTEGER NOT NULL REFERENCES student_timelines (id),
+    tea_id VARCHAR(10) NOT NULL,
+    tea_name VARCHAR(50) NOT NULL,
+    verdict INTEGER NOT NULL,
+    score INTEGER NULL,
+    comment TEXT NOT NULL,
+    created_at datetime NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id VARCHAR(10) NOT NULL,
+    kind VARCHAR(20) NOT NULL,
+    message VARCHAR(200) NOT NULL,
+    link VARCHAR(200) NOT NULL,
+    created_at datetime NOT NULL,
+    read_at datetime NULL
 );
 
 CREATE TABLE IF NOT EXISTS equipments (
@@ -111,6 +187,28 @@ CREATE TABLE IF NOT EXISTS equipments (
     owner_id VARCHAR(10) NOT NULL
 );
 
+CREATE VIRTUAL TABLE IF NOT EXISTS equipments_fts USING fts5 (
+    note,
+    content = 'equipments',
+    content_rowid = 'id'
+);
+
+CREATE TRIGGER IF NOT EXISTS equipments_fts_insert AFTER INSERT ON equipments BEGIN
+    INSERT INTO equipments_fts (rowid, note) VALUES (new.id, new.note);
+END;
+
+CREATE TRIGGER IF NOT EXISTS equipments_fts_delete AFTER DELETE ON equipments BEGIN
+    INSERT INTO equipments_fts (equipments_fts, rowid, note) VALUES ('delete', old.id, old.note);
+END;
+
+CREATE TRIGGER IF NOT EXISTS equipments_fts_update AFTER UPDATE OF note ON equipments BEGIN
+    INSERT INTO equipments_fts (equipments_fts, rowid, note) VALUES ('delete', old.id, old.note);
+    INSERT INTO equipments_fts (rowid, note) VALUES (new.id, new.note);
+END;
+
+-- Index the notes of equipment that existed before the triggers did
+INSERT INTO equipments_fts(equipments_fts) VALUES('rebuild');
+
 CREATE TABLE IF NOT EXISTS equipment_histories (
     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
     user VARCHAR(20) NOT NULL,
@@ -118,13 +216,98 @@ CREATE TABLE IF NOT EXISTS equipment_histories (
     telephone VARCHAR(20) NOT NULL,
     note VARCHAR(200) NOT NULL,
     returned_date DATETIME NULL,
-    item_id INTEGER NOT NULL REFERENCES equipments (id)
+    item_id INTEGER NOT NULL REFERENCES equipments (id),
+    borrower_id VARCHAR(10) NULL REFERENCES users (user_id),
+    state INTEGER NOT NULL DEFAULT 1,
+    due_date DATETIME NULL
+);
+
+CREATE TABLE IF NOT EXISTS stocktakes (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(100) NOT NULL,
+    created_by VARCHAR(10) NOT NULL,
+    created_at datetime NOT NULL,
+    closed_at datetime NULL
+);
+
+CREATE TABLE IF NOT EXISTS stocktake_positions (
+    stocktake_id INTEGER NOT NULL REFERENCES stocktakes (id),
+    position VARCHAR(20) NOT NULL,
+    PRIMARY KEY (stocktake_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS stocktake_items (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    stocktake_id INTEGER NOT NULL REFERENCES stocktakes (id),
+    serial VARCHAR(30) NOT NULL,
+    equipment_id INTEGER NULL REFERENCES equipments (id),
+    status INTEGER NOT NULL,
+    found_position VARCHAR(20) NULL,
+    checked_by VARCHAR(10) NOT NULL,
+    checked_at datetime NOT NULL,
+    UNIQUE (stocktake_id, serial)
+);
+
+CREATE TABLE IF NOT EXISTS equipment_maintenances (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    item_id INTEGER NOT NULL REFERENCES equipments (id),
+    kind INTEGER NOT NULL,
+    summary VARCHAR(200) NOT NULL,
+    cost INTEGER NOT NULL DEFAULT 0,
+    vendor VARCHAR(100) NOT NULL DEFAULT '',
+    certificate VARCHAR(100) NOT NULL DEFAULT '',
+    started_at datetime NOT NULL,
+    completed_at datetime NULL,
+    expires_at date NULL,
+    reminded_at datetime NULL,
+    created_by VARCHAR(10) NOT NULL,
+    created_at datetime NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS equipment_assets (
+    item_id INTEGER NOT NULL PRIMARY KEY REFERENCES equipments (id),
+    acquired_on date NOT NULL,
+    life_months INTEGER NOT NULL,
+    salvage_value INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS equipment_custodians (
+    item_id INTEGER NOT NULL REFERENCES equipments (id),
+    user_id VARCHAR(10) NOT NULL,
+    added_by VARCHAR(10) NOT NULL,
+    added_at datetime NOT NULL,
+    PRIMARY KEY (item_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS equipment_transfers (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    item_id INTEGER NOT NULL REFERENCES equipments (id),
+    from_id VARCHAR(10) NOT NULL,
+    to_id VARCHAR(10) NOT NULL,
+    note VARCHAR(200) NOT NULL DEFAULT '',
+    state INTEGER NOT NULL DEFAULT 0,
+    created_by VARCHAR(10) NOT NULL,
+    created_at datetime NOT NULL,
+    decided_at datetime NULL
+);
+
+CREATE TABLE IF NOT EXISTS equipment_custody_logs (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    item_id INTEGER NOT NULL REFERENCES equipments (id),
+    action VARCHAR(20) NOT NULL,
+    actor_id VARCHAR(10) NOT NULL,
+    user_id VARCHAR(10) NOT NULL,
+    note VARCHAR(200) NOT NULL DEFAULT '',
+    created_at datetime NOT NULL
 );
 
 CREATE TABLE IF NOT EXISTS meeting_rooms (
     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
     room VARCHAR(15) NOT NULL,
-    info VARCHAR(200) NOT NULL
+    info VARCHAR(200) NOT NULL,
+    capacity integer NOT NULL DEFAULT 0,
+    projector integer NOT NULL DEFAULT 0,
+    video_conferencing integer NOT NULL DEFAULT 0
 );
 
 CREATE TABLE IF NOT EXISTS meeting_agendas (
@@ -137,5 +320,115 @@ CREATE TABLE IF NOT EXISTS meeting_agendas (
     start_time time NOT NULL,
     end_time time NOT NULL,
     room_id integer NOT NULL REFERENCES meeting_rooms (id),
-    confirm integer NOT NULL
+    confirm integer NOT NULL,
+    repeat_interval integer NOT NULL DEFAULT 1,
+    repeat_until date,
+    repeat_count integer,
+    reason VARCHAR(200) NOT NULL DEFAULT '',
+    decided_by VARCHAR(10),
+    decided_at datetime,
+    created_at datetime
+);
+
+CREATE TABLE IF NOT EXISTS meeting_exceptions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    agenda_id integer NOT NULL REFERENCES meeting_agendas (id),
+    date date NOT NULL,
+    cancelled integer NOT NULL,
+    new_date date,
+    start_time time,
+    end_time time,
+    reason VARCHAR(200) NOT NULL,
+    UNIQUE (agenda_id, date)
+);
+
+CREATE TABLE IF NOT EXISTS calendar_tokens (
+    user_id VARCHAR(10) NOT NULL PRIMARY KEY,
+    token VARCHAR(40) NOT NULL UNIQUE,
+    created_at datetime NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS lab_sessions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    subcourse_id INTEGER NOT NULL REFERENCES subcourses (id),
+    schedule_id INTEGER NOT NULL REFERENCES course_schedules (id),
+    opened_at datetime NOT NULL,
+    closed_at datetime NULL,
+    opened_by VARCHAR(10) NOT NULL,
+    secret VARCHAR(32) NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS step_progresses (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    stu_id VARCHAR(10) NOT NULL,
+    subcourse_id INTEGER NOT NULL REFERENCES subcourses (id),
+    subschedule_id INTEGER NOT NULL REFERENCES subschedules (id),
+    timeline_id INTEGER NULL REFERENCES student_timelines (id),
+    status INTEGER NOT NULL,
+    tea_note VARCHAR(100) NOT NULL,
+    updated_at datetime NOT NULL,
+    UNIQUE (stu_id, subcourse_id, subschedule_id)
+);
+
+CREATE TABLE IF NOT EXISTS assignments (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    schedule_id INTEGER NOT NULL REFERENCES course_schedules (id),
+    title VARCHAR(50) NOT NULL,
+    due_days INTEGER NOT NULL,
+    allowed_types VARCHAR(100) NOT NULL,
+    max_size INTEGER NOT NULL,
+    allow_late INTEGER NOT NULL
 );
+
+CREATE TABLE IF NOT EXISTS submissions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    assignment_id INTEGER NOT NULL REFERENCES assignments (id),
+    stu_id VARCHAR(10) NOT NULL,
+    subcourse_id INTEGER NOT NULL REFERENCES subcourses (id),
+    version INTEGER NOT NULL,
+    fname VARCHAR(100) NOT NULL,
+    fsize INTEGER NOT NULL,
+    submitted_at datetime NOT NULL,
+    late INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    tea_comment VARCHAR(200) NOT NULL,
+    file_id INTEGER NULL REFERENCES stored_files (id)
+);
+
+CREATE TABLE IF NOT EXISTS stored_files (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    sha256 CHAR(64) NOT NULL,
+    fsize INTEGER NOT NULL,
+    mime VARCHAR(100) NOT NULL,
+    fname VARCHAR(100) NOT NULL,
+    uploader VARCHAR(10) NOT NULL,
+    created_at datetime NOT NULL,
+    scan_status INTEGER NOT NULL DEFAULT 0,
+    scan_result VARCHAR(200) NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS file_fingerprints (
+    sha256 CHAR(64) NOT NULL PRIMARY KEY,
+    kind INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    created_at datetime NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS stored_files_sha256 ON stored_files (sha256);
+CREATE INDEX IF NOT EXISTS stored_files_uploader ON stored_files (uploader);
+CREATE INDEX IF NOT EXISTS notifications_user ON notifications (user_id, read_at);
+-- Loans recorded before the state column defaulted to checked out; mark the returned ones (2) first
+UPDATE equipment_histories SET state = 2 WHERE returned_date IS NOT NULL AND state IN (0, 1);
+CREATE UNIQUE INDEX IF NOT EXISTS equipment_histories_open ON equipment_histories (item_id) WHERE state IN (0, 1);
+CREATE INDEX IF NOT EXISTS equipments_serial ON equipments (serial);
+CREATE UNIQUE INDEX IF NOT EXISTS equipment_transfers_pending ON equipment_transfers (item_id) WHERE state = 0;
+CREATE INDEX IF NOT EXISTS equipment_custodians_user ON equipment_custodians (user_id);
+CREATE INDEX IF NOT EXISTS equipment_custody_logs_item ON equipment_custody_logs (item_id, created_at);
+CREATE INDEX IF NOT EXISTS equipment_maintenances_item ON equipment_maintenances (item_id, kind);
+CREATE INDEX IF NOT EXISTS meeting_agendas_room ON meeting_agendas (room_id, date);
+CREATE INDEX IF NOT EXISTS meeting_agendas_state ON meeting_agendas (confirm, created_at);
+-- At most one open session per subcourse; close stray duplicates left by concurrent opens first
+UPDATE lab_sessions SET closed_at = opened_at
+WHERE closed_at IS NULL
+  AND id NOT IN (SELECT MAX(id) FROM lab_sessions WHERE closed_at IS NULL GROUP BY subcourse_id);
+CREATE UNIQUE INDEX IF NOT EXISTS lab_sessions_open ON lab_sessions (subcourse_id) WHERE closed_at IS NULL;
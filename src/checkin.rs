@@ -0,0 +1,79 @@
+// Rotating check-in codes for lab sessions, in the spirit of RFC 6238 (TOTP).
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::{distributions::Alphanumeric, Rng};
+
+pub const CODE_PERIOD: u64 = 30; // seconds each code stays on screen
+const CODE_DIGITS: u32 = 6;
+
+pub fn new_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn code_at(secret: &str, step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", bin % 10u32.pow(CODE_DIGITS), width = CODE_DIGITS as usize)
+}
+
+// Returns the code to display and the seconds left before it rotates.
+pub fn current_code(secret: &str) -> (String, u64) {
+    let now = now_secs();
+    (code_at(secret, now / CODE_PERIOD), CODE_PERIOD - now % CODE_PERIOD)
+}
+
+// The previous code is still accepted so students typing near a rotation aren't rejected.
+pub fn verify_code(secret: &str, code: &str) -> bool {
+    if secret.is_empty() {
+        return false;
+    }
+    let step = now_secs() / CODE_PERIOD;
+    let code = code.trim();
+    code == code_at(secret, step) || code == code_at(secret, step.saturating_sub(1))
+}
+
+// `ranges` is a comma separated list like "10.2.0.0/16, 162.105.1.1"; empty allows all.
+pub fn ip_allowed(ranges: &str, ip: &str) -> bool {
+    let ranges: Vec<&str> = ranges.split(',').map(str::trim).filter(|r| !r.is_empty()).collect();
+    if ranges.is_empty() {
+        return true;
+    }
+    let ip: IpAddr = match ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => return false,
+    };
+    ranges.iter().any(|range| in_range(range, ip))
+}
+
+fn in_range(range: &str, ip: IpAddr) -> bool {
+    let (net, bits) = match range.split_once('/') {
+        Some((net, bits)) => (net, bits.parse::<u32>().ok()),
+        None => (range, None),
+    };
+    match (net.parse::<IpAddr>(), ip) {
+        (Ok(IpAddr::V4(net)), IpAddr::V4(ip)) => {
+            let bits = bits.unwrap_or(32).min(32);
+            let mask = if bits == 0 { 0 } else { u32::MAX << (32 - bits) };
+            u32::from(net) & mask == u32::from(ip) & mask
+        }
+        (Ok(IpAddr::V6(net)), IpAddr::V6(ip)) => {
+            let bits = bits.unwrap_or(128).min(128);
+            let mask = if bits == 0 { 0 } else { u128::MAX << (128 - bits) };
+            u128::from(net) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
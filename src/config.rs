@@ -1,5 +1,6 @@
 use dotenv::dotenv;
 use std::env;
+use std::net::IpAddr;
 
 pub const PERMISSION_ADMIN: i64 = 0b0001;  // Admin: 1st bit
 pub const PERMISSION_TEACHER: i64 = 0b0010; // Teacher: 2nd bit
@@ -18,6 +19,24 @@ pub struct Config {
     pub iaaa_key: String,
     pub forge_url: String,
     pub forge_key: String,
+    pub attendance_threshold: f64,
+    pub storage_backend: String,
+    pub storage_root: String,
+    pub s3_endpoint: String,
+    pub s3_bucket: String,
+    pub s3_region: String,
+    pub s3_access_key: String,
+    pub s3_secret_key: String,
+    pub upload_tmp: String,
+    pub upload_limit_coursefile: i64, // bytes
+    pub upload_limit_timeline: i64,
+    pub upload_limit_submission: i64,
+    pub user_quota: i64,
+    pub scanner: String,
+    pub clamav_socket: String,
+    pub clamav_stream_max: i64, // Must not exceed clamd's StreamMaxLength
+    pub public_url: String, // Base of the deep links printed on equipment labels
+    pub trusted_proxies: Vec<IpAddr>, // Peers whose X-Forwarded-For is believed
 }
 
 impl Config {
@@ -38,6 +57,35 @@ impl Config {
             .expect("FORGE_URL must be set in .env file");
         let forge_key = env::var("FORGE_KEY")
             .expect("FORGE_KEY must be set in .env file");
+        let attendance_threshold = env::var("ATTENDANCE_THRESHOLD").ok()
+            .and_then(|v| v.parse().ok()).unwrap_or(0.8);
+        let storage_backend = env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".into());
+        let storage_root = env::var("STORAGE_ROOT").unwrap_or_else(|_| "uploads/store".into());
+        let s3_endpoint = env::var("S3_ENDPOINT").unwrap_or_else(|_| "http://127.0.0.1:9000".into());
+        let s3_bucket = env::var("S3_BUCKET").unwrap_or_else(|_| "laboxide".into());
+        let s3_region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".into());
+        let s3_access_key = env::var("S3_ACCESS_KEY").unwrap_or_default();
+        let s3_secret_key = env::var("S3_SECRET_KEY").unwrap_or_default();
+        let upload_tmp = env::var("UPLOAD_TMP").unwrap_or_else(|_| "uploads/tmp".into());
+        let bytes = |name: &str, default: i64| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+        let user_quota = bytes("USER_QUOTA", 1 << 30);
+        let scanner = env::var("SCANNER").unwrap_or_else(|_| "none".into());
+        let clamav_socket = env::var("CLAMAV_SOCKET").unwrap_or_else(|_| "/var/run/clamav/clamd.ctl".into());
+        // clamd refuses INSTREAM data beyond StreamMaxLength (25M by default), so
+        // while it scans uploads no upload limit may exceed it. Raise both
+        // StreamMaxLength in clamd.conf and CLAMAV_STREAM_MAX to accept larger files.
+        let clamav_stream_max = bytes("CLAMAV_STREAM_MAX", 25 << 20);
+        let scan_cap = |limit: i64| if scanner == "clamav" { limit.min(clamav_stream_max) } else { limit };
+        let upload_limit_coursefile = scan_cap(bytes("UPLOAD_LIMIT_COURSEFILE", 200 << 20));
+        let upload_limit_timeline = scan_cap(bytes("UPLOAD_LIMIT_TIMELINE", 20 << 20));
+        let upload_limit_submission = scan_cap(bytes("UPLOAD_LIMIT_SUBMISSION", 50 << 20));
+        let public_url = env::var("PUBLIC_URL").unwrap_or_default().trim_end_matches('/').to_string();
+        // The server binds to loopback behind a reverse proxy by default
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .unwrap_or_else(|_| "127.0.0.1,::1".into())
+            .split(',')
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
 
         Config {
             database_url,
@@ -48,6 +96,24 @@ impl Config {
             iaaa_key,
             forge_url,
             forge_key,
+            attendance_threshold,
+            storage_backend,
+            storage_root,
+            s3_endpoint,
+            s3_bucket,
+            s3_region,
+            s3_access_key,
+            s3_secret_key,
+            upload_tmp,
+            upload_limit_coursefile,
+            upload_limit_timeline,
+            upload_limit_submission,
+            user_quota,
+            scanner,
+            clamav_socket,
+            clamav_stream_max,
+            public_url,
+            trusted_proxies,
         }
     }
 }
@@ -1,11 +1,24 @@
 use actix_web::Result;
 use sqlx::{Pool, Sqlite, SqlitePool};
-use crate::models::{User, Semester, Course, Labroom, Equipment, EquipmentHistory};
+use crate::models::{User, Semester, Course, Labroom, Equipment, EquipmentHistory, EquipmentFilter, OverdueLoan};
+use crate::models::{LOAN_CHECKED_OUT, LOAN_RESERVED, LOAN_RETURNED};
+use crate::models::{InventoryRow, Stocktake, StocktakeItem, STOCK_MISSING};
+use crate::models::{CalibrationDue, DepreciationRow, EquipmentAsset, EquipmentMaintenance};
+use crate::models::{EQUIP_CALIBRATION_DUE, EQUIP_IN_SERVICE, EQUIP_RETIRED, EQUIP_UNDER_REPAIR, MAINT_CALIBRATION, MAINT_REPAIR};
+use crate::models::{CustodyLog, EquipmentCustodian, EquipmentTransfer, TRANSFER_ACCEPTED, TRANSFER_CANCELLED, TRANSFER_PENDING};
 use crate::config::Config;
 use crate::models::{SubCourse, SubCourseWithName, Student, CourseSchedule, CourseFile};
-use crate::models::{StudentLog, SubSchedule, StudentTimeline};
-use crate::models::{MeetingRoom, MeetingAgenda};
-use chrono::{Local, Duration, NaiveDateTime, Datelike};
+use crate::models::{CourseFolder, CourseFileVersion};
+use crate::models::{StudentLog, SubSchedule, StudentTimeline, LabSession};
+use crate::models::{TimelineAttachment, TimelineComment, TimelineFeedback, Notification};
+use crate::models::{MeetingRoom, MeetingAgenda, MeetingException, MeetingOccurrence, CalendarSession};
+use crate::models::{MEETING_APPROVED, MEETING_CANCELLED, MEETING_PENDING, MEETING_REJECTED};
+use crate::recurrence;
+use crate::models::{AttendanceStat, ScheduleDuration, TeacherBacklog, StepProgress};
+use crate::models::{STEP_DONE, STEP_VERIFIED};
+use crate::models::{Assignment, Submission, SUBMISSION_SUBMITTED, StoredFile, SCAN_CLEAN};
+use crate::models::{FileFingerprint, SimilarityCandidate};
+use chrono::{Local, NaiveDate, NaiveDateTime};
 
 pub async fn init_db(config: &Config) -> Result<SqlitePool, sqlx::Error> {
     let pool = SqlitePool::connect(&config.database_url).await?;
@@ -176,9 +189,9 @@ pub async fn add_course(pool: &SqlitePool, course: Course) -> Result<Course, sql
     let rec = sqlx::query_as!(
         Course,
         r#"
-        INSERT INTO courses (name, ename, code, tea_id, tea_name, intro, mailbox, term)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
-        RETURNING id, name, ename, code, tea_id, tea_name, intro, mailbox, term
+        INSERT INTO courses (name, ename, code, tea_id, tea_name, intro, mailbox, term, allowed_mime)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        RETURNING id, name, ename, code, tea_id, tea_name, intro, mailbox, term, allowed_mime
         "#,
         course.name,
         course.ename,
@@ -187,7 +200,8 @@ pub async fn add_course(pool: &SqlitePool, course: Course) -> Result<Course, sql
         course.tea_name,
         course.intro,
         course.mailbox,
-        course.term
+        course.term,
+        course.allowed_mime
     )
     .fetch_one(pool)
     .await?;
@@ -198,7 +212,7 @@ pub async fn add_course(pool: &SqlitePool, course: Course) -> Result<Course, sql
 pub async fn list_courses(pool: &SqlitePool) -> Result<Vec<Course>, sqlx::Error> {
     let courses = sqlx::query_as!(
         Course,
-        r#"SELECT id, name, ename, code, tea_id, tea_name, intro, mailbox, term FROM courses"#
+        r#"SELECT id, name, ename, code, tea_id, tea_name, intro, mailbox, term, allowed_mime FROM courses"#
     )
     .fetch_all(pool)
     .await?;
@@ -209,7 +223,7 @@ pub async fn list_courses(pool: &SqlitePool) -> Result<Vec<Course>, sqlx::Error>
 pub async fn get_course_by_id(pool: &SqlitePool, id: i64) -> Result<Course, sqlx::Error> {
     let course = sqlx::query_as!(
         Course,
-        r#"SELECT id, name, ename, code, tea_id, tea_name, intro, mailbox, term FROM courses WHERE id = ?"#,
+        r#"SELECT id, name, ename, code, tea_id, tea_name, intro, mailbox, term, allowed_mime FROM courses WHERE id = ?"#,
         id
     )
     .fetch_one(pool)
@@ -223,9 +237,10 @@ pub async fn update_course(pool: &SqlitePool, id: i64, course: Course) -> Result
         Course,
         r#"
         UPDATE courses
-        SET name = ?1, ename = ?2, code = ?3, tea_id = ?4, tea_name = ?5, intro = ?6, mailbox = ?7, term = ?8
-        WHERE id = ?9
-        RETURNING id, name, ename, code, tea_id, tea_name, intro, mailbox, term
+        SET name = ?1, ename = ?2, code = ?3, tea_id = ?4, tea_name = ?5, intro = ?6, mailbox = ?7, term = ?8,
+            allowed_mime = ?9
+        WHERE id = ?10
+        RETURNING id, name, ename, code, tea_id, tea_name, intro, mailbox, term, allowed_mime
         "#,
         course.name,
         course.ename,
@@ -235,6 +250,7 @@ pub async fn update_course(pool: &SqlitePool, id: i64, course: Course) -> Result
         course.intro,
         course.mailbox,
         course.term,
+        course.allowed_mime,
         id
     )
     .fetch_one(pool)
@@ -259,14 +275,15 @@ pub async fn add_labroom(pool: &SqlitePool, labroom: Labroom) -> Result<Labroom,
     let rec = sqlx::query_as!(
         Labroom,
         r#"
-        INSERT INTO labrooms (room, name, manager, tea_id)
-        VALUES (?1, ?2, ?3, ?4)
-        RETURNING id, room, name, manager, tea_id
+        INSERT INTO labrooms (room, name, manager, tea_id, allowed_ips)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        RETURNING id, room, name, manager, tea_id, allowed_ips
         "#,
         labroom.room,
         labroom.name,
         labroom.manager,
-        labroom.tea_id
+        labroom.tea_id,
+        labroom.allowed_ips
     )
     .fetch_one(pool)
     .await?;
@@ -277,7 +294,7 @@ pub async fn add_labroom(pool: &SqlitePool, labroom: Labroom) -> Result<Labroom,
 pub async fn list_labrooms(pool: &SqlitePool) -> Result<Vec<Labroom>, sqlx::Error> {
     let labrooms = sqlx::query_as!(
         Labroom,
-        r#"SELECT id, room, name, manager, tea_id FROM labrooms"#
+        r#"SELECT id, room, name, manager, tea_id, allowed_ips FROM labrooms"#
     )
     .fetch_all(pool)
     .await?;
@@ -288,7 +305,7 @@ pub async fn list_labrooms(pool: &SqlitePool) -> Result<Vec<Labroom>, sqlx::Erro
 pub async fn get_labroom_by_id(pool: &SqlitePool, id: i64) -> Result<Labroom, sqlx::Error> {
     let labroom = sqlx::query_as!(
         Labroom,
-        r#"SELECT id, room, name, manager, tea_id FROM labrooms WHERE id = ?"#,
+        r#"SELECT id, room, name, manager, tea_id, allowed_ips FROM labrooms WHERE id = ?"#,
         id
     )
     .fetch_one(pool)
@@ -302,14 +319,15 @@ pub async fn update_labroom(pool: &SqlitePool, id: i64, labroom: Labroom) -> Res
         Labroom,
         r#"
         UPDATE labrooms
-        SET room = ?1, name = ?2, manager = ?3, tea_id = ?4
-        WHERE id = ?5
-        RETURNING id, room, name, manager, tea_id
+        SET room = ?1, name = ?2, manager = ?3, tea_id = ?4, allowed_ips = ?5
+        WHERE id = ?6
+        RETURNING id, room, name, manager, tea_id, allowed_ips
         "#,
         labroom.room,
         labroom.name,
         labroom.manager,
         labroom.tea_id,
+        labroom.allowed_ips,
         id
     )
     .fetch_one(pool)
@@ -562,6 +580,35 @@ pub async fn get_student_seat(
     Ok(seat)
 }
 
+pub async fn is_enrolled_in_course(
+    pool: &SqlitePool,
+    stu_id: &str,
+    course_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) FROM students s JOIN subcourses c ON c.id = s.subcourse_id
+        WHERE s.stu_id = ?1 AND c.course_id = ?2"#,
+        stu_id, course_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count > 0)
+}
+
+pub async fn teaches_course(
+    pool: &SqlitePool,
+    tea_id: &str,
+    course_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM subcourses WHERE tea_id = ?1 AND course_id = ?2",
+        tea_id, course_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count > 0)
+}
+
 pub async fn get_student_name(
     pool: &SqlitePool,
     stu_id: &str,
@@ -771,658 +818,2794 @@ pub async fn delete_schedule(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::E
     Ok(result.rows_affected() > 0)
 }
 
-// Operations for coursefiles
-pub async fn add_course_file(
+// Operations for stored_files
+pub async fn add_stored_file(
     pool: &SqlitePool,
+    sha256: &str,
+    fsize: i64,
+    mime: &str,
     fname: &str,
-    finfo: &str,
-    course_id: i64,
-) -> Result<CourseFile, sqlx::Error> {
-    let rec = sqlx::query_as!(
-        CourseFile,
+    uploader: &str,
+) -> Result<StoredFile, sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query_as!(
+        StoredFile,
         r#"
-        INSERT INTO course_files (fname, finfo, course_id)
-        VALUES (?1, ?2, ?3)
-        RETURNING id, fname, finfo, course_id
+        INSERT INTO stored_files (sha256, fsize, mime, fname, uploader, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        RETURNING id, sha256, fsize, mime, fname, uploader, created_at, scan_status, scan_result
         "#,
+        sha256,
+        fsize,
+        mime,
         fname,
-        finfo,
-        course_id
+        uploader,
+        now
     )
     .fetch_one(pool)
-    .await?;
-
-    Ok(rec)
+    .await
 }
 
-pub async fn list_course_files(pool: &SqlitePool, id: i64) -> Result<Vec<CourseFile>, sqlx::Error> {
-    let files = sqlx::query_as!(
-        CourseFile,
-        r#"SELECT id, fname, finfo, course_id FROM course_files
-        WHERE course_id = ?"#,
+pub async fn get_stored_file_by_id(pool: &SqlitePool, id: i64) -> Result<StoredFile, sqlx::Error> {
+    sqlx::query_as!(
+        StoredFile,
+        r#"
+        SELECT id, sha256, fsize, mime, fname, uploader, created_at, scan_status, scan_result
+        FROM stored_files WHERE id = ?
+        "#,
         id
     )
-    .fetch_all(pool)
-    .await?;
-
-    Ok(files)
+    .fetch_one(pool)
+    .await
 }
 
-pub async fn get_course_file_by_id(pool: &SqlitePool, id: i64) -> Result<CourseFile, sqlx::Error> {
-    let file = sqlx::query_as!(
-        CourseFile,
-        r#"SELECT id, fname, finfo, course_id FROM course_files WHERE id = ?"#,
-        id
+pub async fn get_stored_file_by_sha(pool: &SqlitePool, sha256: &str) -> Result<Option<StoredFile>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredFile,
+        r#"
+        SELECT id, sha256, fsize, mime, fname, uploader, created_at, scan_status, scan_result
+        FROM stored_files WHERE sha256 = ? LIMIT 1
+        "#,
+        sha256
     )
-    .fetch_one(pool)
-    .await?;
-
-    Ok(file)
+    .fetch_optional(pool)
+    .await
 }
 
-pub async fn delete_course_file(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"DELETE FROM course_files WHERE id = ?"#,
-        id
+// The verdict belongs to the content, so every row sharing the blob gets it
+pub async fn set_scan_status(
+    pool: &SqlitePool,
+    sha256: &str,
+    scan_status: i64,
+    scan_result: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE stored_files SET scan_status = ?1, scan_result = ?2 WHERE sha256 = ?3",
+        scan_status,
+        scan_result,
+        sha256
     )
     .execute(pool)
     .await?;
-
-    Ok(result.rows_affected() > 0)
+    Ok(())
 }
 
-// Operations for student_logs
-pub async fn add_student_log(pool: &SqlitePool, log: StudentLog) -> Result<StudentLog, sqlx::Error> {
-    if let Some(_) = find_recent_student_log(pool, &log.stu_id, log.subcourse_id).await? {
-        return Err(sqlx::Error::Protocol("Recent log already exists".into()));
-    }
-    let now = Local::now().naive_local();
-    let rec = sqlx::query_as!(
-        StudentLog,
+pub async fn list_flagged_stored_files(pool: &SqlitePool) -> Result<Vec<StoredFile>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredFile,
         r#"
-        INSERT INTO student_logs (
-            stu_id, stu_name, subcourse_id, room_id, seat,
-            lab_name, note, tea_note, tea_name, fin_time, confirm
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
-        RETURNING *
+        SELECT id, sha256, fsize, mime, fname, uploader, created_at, scan_status, scan_result
+        FROM stored_files WHERE scan_status != ? ORDER BY created_at DESC
         "#,
-        log.stu_id, log.stu_name, log.subcourse_id, log.room_id, log.seat,
-        log.lab_name, log.note, log.tea_note, log.tea_name, now, log.confirm
+        SCAN_CLEAN
     )
-    .fetch_one(pool)
-    .await?;
-    Ok(rec)
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn get_student_log_by_id(pool: &SqlitePool, id: i64) -> Result<StudentLog, sqlx::Error> {
-    let log = sqlx::query_as!(
-        StudentLog,
-        "SELECT * FROM student_logs WHERE id = ?",
-        id
+pub async fn count_stored_files_by_sha(pool: &SqlitePool, sha256: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!("SELECT COUNT(*) FROM stored_files WHERE sha256 = ?", sha256)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn stored_bytes_by_uploader(pool: &SqlitePool, uploader: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT IFNULL(SUM(fsize), 0) AS "total!: i64" FROM stored_files WHERE uploader = ?"#,
+        uploader
     )
     .fetch_one(pool)
-    .await?;
-    Ok(log)
+    .await
 }
 
-pub async fn update_student_log(pool: &SqlitePool, id: i64, log: StudentLog) -> Result<(), sqlx::Error> {
+pub async fn delete_stored_file(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM stored_files WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Operations for coursefiles
+pub async fn add_course_file(
+    pool: &SqlitePool,
+    fname: &str,
+    finfo: &str,
+    course_id: i64,
+    folder_id: Option<i64>,
+    file_id: i64,
+    uploader: &str,
+) -> Result<CourseFile, sqlx::Error> {
     let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query_as!(
+        CourseFile,
+        r#"
+        INSERT INTO course_files (fname, finfo, course_id, file_id, folder_id, version)
+        VALUES (?1, ?2, ?3, ?4, ?5, 1)
+        RETURNING id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility
+        "#,
+        fname,
+        finfo,
+        course_id,
+        file_id,
+        folder_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
     sqlx::query!(
         r#"
-        UPDATE student_logs
-        SET seat = ?1, note = ?2, fin_time = ?3, lab_name = ?4, fin_time = ?5
-        WHERE id = ?6 AND confirm = 0
+        INSERT INTO course_file_versions (course_file_id, version, file_id, uploader, created_at)
+        VALUES (?1, 1, ?2, ?3, ?4)
         "#,
-        log.seat, log.note, log.fin_time, log.lab_name, now, id
+        rec.id,
+        file_id,
+        uploader,
+        now
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
-    Ok(())
+    tx.commit().await?;
+
+    Ok(rec)
 }
 
-pub async fn confirm_student_log(
+// Re-uploading under the same name in the same folder adds a version instead of a new file
+pub async fn find_course_file(
     pool: &SqlitePool,
-    id: i64,
-    tea_note: &str,
-    tea_name: &str,
-) -> Result<(), sqlx::Error> {
-    let now = Local::now().naive_local();
-    sqlx::query!(
-        "UPDATE student_logs SET tea_note = ?1, confirm = 1, fin_time = ?3, tea_name = ?4 WHERE id = ?2 ",
-        tea_note, id, now, tea_name
+    course_id: i64,
+    folder_id: Option<i64>,
+    fname: &str,
+) -> Result<Option<CourseFile>, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFile,
+        r#"SELECT id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility FROM course_files
+        WHERE course_id = ?1 AND folder_id IS ?2 AND fname = ?3"#,
+        course_id,
+        folder_id,
+        fname
     )
-    .execute(pool)
-    .await?;
-    Ok(())
+    .fetch_optional(pool)
+    .await
 }
 
-pub async fn list_recent_logs(
+pub async fn add_course_file_version(
     pool: &SqlitePool,
-    subcourse_id: i64,
-) -> Result<Vec<StudentLog>, sqlx::Error> {
+    id: i64,
+    file_id: i64,
+    uploader: &str,
+) -> Result<CourseFile, sqlx::Error> {
     let now = Local::now().naive_local();
-    let since = now - Duration::hours(5);
-    sqlx::query_as!(
-        StudentLog,
+    let mut tx = pool.begin().await?;
+    let version = sqlx::query_scalar!(
         r#"
-        SELECT * FROM student_logs
-        WHERE subcourse_id = ?1 AND fin_time >= ?2
-        ORDER BY seat
+        INSERT INTO course_file_versions (course_file_id, version, file_id, uploader, created_at)
+        SELECT ?1, IFNULL(MAX(version), 0) + 1, ?2, ?3, ?4
+        FROM course_file_versions WHERE course_file_id = ?1
+        RETURNING version
         "#,
-        subcourse_id,
-        since
+        id,
+        file_id,
+        uploader,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let rec = sqlx::query_as!(
+        CourseFile,
+        r#"
+        UPDATE course_files SET file_id = ?1, version = ?2 WHERE id = ?3
+        RETURNING id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility
+        "#,
+        file_id,
+        version,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(rec)
+}
+
+pub async fn list_course_file_versions(pool: &SqlitePool, id: i64) -> Result<Vec<CourseFileVersion>, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFileVersion,
+        r#"SELECT id, course_file_id, version, file_id, uploader, created_at FROM course_file_versions
+        WHERE course_file_id = ? ORDER BY version DESC"#,
+        id
     )
     .fetch_all(pool)
     .await
 }
 
-pub async fn get_student_log_by_schedule(
+pub async fn get_course_file_version(
     pool: &SqlitePool,
-    stu_id: &str,
-    schedule_id: i64,
-) -> Result<Option<StudentLog>, sqlx::Error> {
-    let result = sqlx::query_as!(
-        StudentLog,
-        r#"
-        SELECT sl.*
-        FROM student_logs sl
-        JOIN course_schedules cs ON sl.lab_name = cs.name
-        WHERE cs.id = ? AND sl.stu_id = ? LIMIT 1
-        "#,
-        schedule_id,
-        stu_id
+    id: i64,
+    version: i64,
+) -> Result<CourseFileVersion, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFileVersion,
+        r#"SELECT id, course_file_id, version, file_id, uploader, created_at FROM course_file_versions
+        WHERE course_file_id = ? AND version = ?"#,
+        id,
+        version
     )
-    .fetch_optional(pool)
-    .await?;
-
-    Ok(result)
+    .fetch_one(pool)
+    .await
 }
 
-pub async fn find_recent_student_log(
+// Rolling back only moves the pointer; the newer versions stay in the history
+pub async fn set_course_file_version(
     pool: &SqlitePool,
-    stu_id: &str,
-    subcourse_id: i64,
-) -> Result<Option<StudentLog>, sqlx::Error> {
-    let now = Local::now().naive_local();
-    let since = now - Duration::hours(5);
+    id: i64,
+    version: &CourseFileVersion,
+) -> Result<CourseFile, sqlx::Error> {
     sqlx::query_as!(
-        StudentLog,
+        CourseFile,
         r#"
-        SELECT id, stu_id, stu_name, subcourse_id, room_id, seat,
-               lab_name, note, tea_note, tea_name, fin_time, confirm
-        FROM student_logs
-        WHERE stu_id = ?1 AND subcourse_id = ?2 AND fin_time >= ?3
-        ORDER BY fin_time DESC LIMIT 1
+        UPDATE course_files SET file_id = ?1, version = ?2 WHERE id = ?3
+        RETURNING id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility
         "#,
-        stu_id,
-        subcourse_id,
-        since
+        version.file_id,
+        version.version,
+        id
     )
-    .fetch_optional(pool)
+    .fetch_one(pool)
     .await
 }
 
-pub async fn find_student_logs_by_room(
+pub async fn update_course_file(
     pool: &SqlitePool,
-    room_id: i64,
-    start_time: NaiveDateTime,
-    end_time: NaiveDateTime,
-) -> Result<Vec<StudentLog>, sqlx::Error> {
+    id: i64,
+    finfo: &str,
+    folder_id: Option<i64>,
+    visible_from: Option<NaiveDateTime>,
+    visibility: i64,
+) -> Result<CourseFile, sqlx::Error> {
     sqlx::query_as!(
-        StudentLog,
+        CourseFile,
         r#"
-        SELECT id, stu_id, stu_name, subcourse_id, room_id, seat,
-               lab_name, note, tea_note, tea_name, fin_time, confirm
-        FROM student_logs
-        WHERE room_id = ?1 AND fin_time >= ?2 AND fin_time <= ?3
-        ORDER BY fin_time DESC
+        UPDATE course_files SET finfo = ?1, folder_id = ?2, visible_from = ?3, visibility = ?4 WHERE id = ?5
+        RETURNING id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility
         "#,
-        room_id,
-        start_time,
-        end_time
+        finfo,
+        folder_id,
+        visible_from,
+        visibility,
+        id
     )
-    .fetch_all(pool)
+    .fetch_one(pool)
     .await
 }
 
-pub async fn get_default_log(
-    pool: &SqlitePool,
-    stu_id: &str,
-    subcourse_id: i64
-) -> Result<StudentLog, sqlx::Error> {
-    let today = Local::now().naive_local();
-    if let Some(existing_log) = find_recent_student_log(pool, stu_id, subcourse_id).await? {
-        return Ok(existing_log);
-    }
-    let mut log = StudentLog {
-        id: 0,
-        stu_id: stu_id.to_string(),
-        stu_name: String::new(),
-        subcourse_id,
-        room_id: 0,
-        seat: 0,
-        lab_name: String::new(),
-        note: String::new(),
-        tea_note: String::new(),
-        tea_name: String::new(),
-        fin_time: today,
-        confirm: 0,
-    };
-    let subcourse = get_subcourse_by_id(pool, subcourse_id).await?;
-    log.room_id = subcourse.room_id;
-    let semester_id = subcourse.year_id;
-    let semester = get_semester_by_id(pool, semester_id).await?;
-    let week = (today.date() - semester.start).num_weeks() + 1 + subcourse.lag_week;
-    log.seat = get_student_seat(pool, stu_id, subcourse_id).await?;
-    match get_schedule_by_week(pool, subcourse.course_id, week).await {
-        Ok(Some(sch)) => log.lab_name = sch.name,
-        Ok(None) => {},
-        Err(e) => return Err(e),
-    }
-    Ok(log)
+pub async fn list_course_files(pool: &SqlitePool, id: i64) -> Result<Vec<CourseFile>, sqlx::Error> {
+    let files = sqlx::query_as!(
+        CourseFile,
+        r#"SELECT id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility FROM course_files
+        WHERE course_id = ?"#,
+        id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(files)
 }
 
-pub async fn add_subschedule(pool: &SqlitePool, item: SubSchedule) -> Result<SubSchedule, sqlx::Error> {
-    let rec = sqlx::query_as!(
-        SubSchedule,
-        r#"
-        INSERT INTO subschedules (schedule_id, step, title)
-        VALUES (?1, ?2, ?3)
-        RETURNING id, schedule_id, step, title
-        "#,
-        item.schedule_id,
-        item.step,
-        item.title
+pub async fn get_course_file_by_id(pool: &SqlitePool, id: i64) -> Result<CourseFile, sqlx::Error> {
+    let file = sqlx::query_as!(
+        CourseFile,
+        r#"SELECT id, fname, finfo, course_id, file_id, folder_id, version, visible_from, visibility FROM course_files
+        WHERE id = ?"#,
+        id
     )
     .fetch_one(pool)
     .await?;
 
-    Ok(rec)
+    Ok(file)
 }
 
-pub async fn get_subschedule_by_id(pool: &SqlitePool, id: i64) -> Result<SubSchedule, sqlx::Error> {
-    let rec = sqlx::query_as!(
-        SubSchedule,
-        r#"SELECT id, schedule_id, step, title FROM subschedules WHERE id = ?"#,
+pub async fn delete_course_file(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM course_file_schedules WHERE course_file_id = ?", id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM course_file_versions WHERE course_file_id = ?", id)
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query!(
+        r#"DELETE FROM course_files WHERE id = ?"#,
         id
     )
-    .fetch_one(pool)
+    .execute(&mut *tx)
     .await?;
+    tx.commit().await?;
 
-    Ok(rec)
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn list_subschedules(pool: &SqlitePool, schedule_id: i64) -> Result<Vec<SubSchedule>, sqlx::Error> {
-    let recs = sqlx::query_as!(
-        SubSchedule,
-        r#"SELECT id, schedule_id, step, title FROM subschedules WHERE schedule_id = ?"#,
-        schedule_id
+pub async fn set_course_file_schedules(
+    pool: &SqlitePool,
+    id: i64,
+    schedule_ids: &[i64],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM course_file_schedules WHERE course_file_id = ?", id)
+        .execute(&mut *tx)
+        .await?;
+    for schedule_id in schedule_ids {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO course_file_schedules (course_file_id, schedule_id) VALUES (?, ?)",
+            id,
+            schedule_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn list_course_file_schedules(pool: &SqlitePool, id: i64) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT schedule_id FROM course_file_schedules WHERE course_file_id = ? ORDER BY schedule_id",
+        id
     )
     .fetch_all(pool)
-    .await?;
+    .await
+}
 
-    Ok(recs)
+pub async fn list_course_files_by_schedule(
+    pool: &SqlitePool,
+    schedule_id: i64,
+) -> Result<Vec<CourseFile>, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFile,
+        r#"SELECT f.id, f.fname, f.finfo, f.course_id, f.file_id, f.folder_id, f.version, f.visible_from, f.visibility
+        FROM course_files f
+        JOIN course_file_schedules s ON s.course_file_id = f.id
+        WHERE s.schedule_id = ?"#,
+        schedule_id
+    )
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn update_subschedule(pool: &SqlitePool, id: i64, item: SubSchedule) -> Result<SubSchedule, sqlx::Error> {
-    let rec = sqlx::query_as!(
-        SubSchedule,
+// Operations for course folders
+pub async fn add_course_folder(
+    pool: &SqlitePool,
+    course_id: i64,
+    parent_id: Option<i64>,
+    name: &str,
+) -> Result<CourseFolder, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFolder,
         r#"
-        UPDATE subschedules
-        SET schedule_id = ?1, step = ?2, title = ?3
-        WHERE id = ?4
-        RETURNING id, schedule_id, step, title
+        INSERT INTO course_folders (course_id, parent_id, name) VALUES (?1, ?2, ?3)
+        RETURNING id, course_id, parent_id, name
         "#,
-        item.schedule_id,
-        item.step,
-        item.title,
+        course_id,
+        parent_id,
+        name
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_course_folder_by_id(pool: &SqlitePool, id: i64) -> Result<CourseFolder, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFolder,
+        "SELECT id, course_id, parent_id, name FROM course_folders WHERE id = ?",
         id
     )
     .fetch_one(pool)
-    .await?;
+    .await
+}
 
-    Ok(rec)
+pub async fn list_course_folders(pool: &SqlitePool, course_id: i64) -> Result<Vec<CourseFolder>, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFolder,
+        "SELECT id, course_id, parent_id, name FROM course_folders WHERE course_id = ? ORDER BY name",
+        course_id
+    )
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn delete_subschedule(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
-    let result = sqlx::query!(
-        "DELETE FROM subschedules WHERE id = ?",
+pub async fn update_course_folder(
+    pool: &SqlitePool,
+    id: i64,
+    parent_id: Option<i64>,
+    name: &str,
+) -> Result<CourseFolder, sqlx::Error> {
+    sqlx::query_as!(
+        CourseFolder,
+        r#"
+        UPDATE course_folders SET parent_id = ?1, name = ?2 WHERE id = ?3
+        RETURNING id, course_id, parent_id, name
+        "#,
+        parent_id,
+        name,
         id
     )
-    .execute(pool)
+    .fetch_one(pool)
+    .await
+}
+
+// Only empty folders can go, so files are never orphaned
+pub async fn delete_course_folder(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let used = sqlx::query_scalar!(
+        r#"SELECT (SELECT COUNT(*) FROM course_files WHERE folder_id = ?1)
+                + (SELECT COUNT(*) FROM course_folders WHERE parent_id = ?1) AS "count!: i64""#,
+        id
+    )
+    .fetch_one(pool)
     .await?;
+    if used > 0 {
+        return Err(sqlx::Error::Protocol("Folder is not empty".into()));
+    }
+    let result = sqlx::query!("DELETE FROM course_folders WHERE id = ?", id)
+        .execute(pool)
+        .await?;
 
     Ok(result.rows_affected() > 0)
 }
 
-// Operations for StudentTimeline
-pub async fn add_student_timeline(
+// Operations for lab_sessions
+pub async fn add_lab_session(
     pool: &SqlitePool,
-    timeline: StudentTimeline,
-) -> Result<StudentTimeline, sqlx::Error> {
+    subcourse_id: i64,
+    schedule_id: i64,
+    opened_by: &str,
+    secret: &str,
+) -> Result<LabSession, sqlx::Error> {
     let now = Local::now().naive_local();
+    // The partial unique index on open sessions settles concurrent opens
     let rec = sqlx::query_as!(
-        StudentTimeline,
+        LabSession,
         r#"
-        INSERT INTO student_timelines
-        (stu_id, tea_id, schedule_id, subschedule, subcourse_id, note, notetype, timestamp)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
-        RETURNING *
+        INSERT INTO lab_sessions (subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret)
+        VALUES (?1, ?2, ?3, NULL, ?4, ?5)
+        RETURNING id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
         "#,
-        timeline.stu_id,
-        timeline.tea_id,
-        timeline.schedule_id,
-        timeline.subschedule,
-        timeline.subcourse_id,
-        timeline.note,
-        timeline.notetype,
-        now
+        subcourse_id,
+        schedule_id,
+        now,
+        opened_by,
+        secret
     )
     .fetch_one(pool)
-    .await?;
+    .await;
 
-    Ok(rec)
+    match rec {
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            Err(sqlx::Error::Protocol("An open session already exists".into()))
+        }
+        rec => rec,
+    }
 }
 
-pub async fn count_student_timeline_entries(
-    pool: &SqlitePool,
-    stu_id: &str,
-    schedule_id: i64,
-) -> Result<i64, sqlx::Error> {
-    let count = sqlx::query_scalar!(
+pub async fn close_lab_session(pool: &SqlitePool, id: i64) -> Result<LabSession, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let rec = sqlx::query_as!(
+        LabSession,
         r#"
-        SELECT COUNT(*) as count
-        FROM student_timelines
-        WHERE stu_id = ?1 AND schedule_id = ?2
+        UPDATE lab_sessions
+        SET closed_at = ?1
+        WHERE id = ?2 AND closed_at IS NULL
+        RETURNING id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
         "#,
-        stu_id,
-        schedule_id
+        now,
+        id
     )
     .fetch_one(pool)
     .await?;
 
-    Ok(count)
+    Ok(rec)
 }
 
-pub async fn delete_student_timeline(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
-    let result = sqlx::query!(
-        "DELETE FROM student_timelines WHERE id = ?1",
+pub async fn get_lab_session_by_id(pool: &SqlitePool, id: i64) -> Result<LabSession, sqlx::Error> {
+    sqlx::query_as!(
+        LabSession,
+        r#"
+        SELECT id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
+        FROM lab_sessions WHERE id = ?
+        "#,
         id
     )
-    .execute(pool)
-    .await?;
-
-    Ok(result.rows_affected() > 0)
+    .fetch_one(pool)
+    .await
 }
 
-pub async fn list_timelines_by_schedule(
+pub async fn get_open_lab_session(
     pool: &SqlitePool,
     subcourse_id: i64,
-    schedule_id: i64,
-) -> Result<Vec<StudentTimeline>, sqlx::Error> {
-    let recs = sqlx::query_as!(
-        StudentTimeline,
+) -> Result<Option<LabSession>, sqlx::Error> {
+    sqlx::query_as!(
+        LabSession,
         r#"
-        SELECT * FROM student_timelines
-        WHERE schedule_id = ?1 AND subcourse_id = ?2
+        SELECT id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
+        FROM lab_sessions
+        WHERE subcourse_id = ?1 AND closed_at IS NULL
+        ORDER BY opened_at DESC LIMIT 1
         "#,
-        schedule_id,
         subcourse_id
     )
-    .fetch_all(pool)
-    .await?;
-
-    Ok(recs)
+    .fetch_optional(pool)
+    .await
 }
 
-pub async fn list_timelines_by_student(
+// The open session if any, otherwise the last closed one, so logs can
+// still be confirmed after the teacher has closed the session.
+pub async fn get_latest_lab_session(
     pool: &SqlitePool,
     subcourse_id: i64,
-    stu_id: &str,
-    tea_id: &str,
-) -> Result<Vec<StudentTimeline>, sqlx::Error> {
-    let subcourse = get_subcourse_by_id(pool, subcourse_id).await?;
-    let recs = if tea_id == subcourse.tea_id {
-        sqlx::query_as!(
-            StudentTimeline,
-            r#"
-            SELECT * FROM student_timelines
-            WHERE stu_id = ?1 AND subcourse_id = ?2
-            "#,
-            stu_id,
-            subcourse_id
-        )
-        .fetch_all(pool)
-        .await?
-    } else {
-        sqlx::query_as!(
-            StudentTimeline,
-            r#"
-            SELECT * FROM student_timelines
-            WHERE stu_id = ?1 AND subcourse_id = ?2 AND tea_id = ?3
-            "#,
-            stu_id,
-            subcourse_id,
-            tea_id
-        )
-        .fetch_all(pool)
-        .await?
-    };
-    Ok(recs)
+) -> Result<Option<LabSession>, sqlx::Error> {
+    sqlx::query_as!(
+        LabSession,
+        r#"
+        SELECT id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
+        FROM lab_sessions
+        WHERE subcourse_id = ?1
+        ORDER BY closed_at IS NULL DESC, opened_at DESC LIMIT 1
+        "#,
+        subcourse_id
+    )
+    .fetch_optional(pool)
+    .await
 }
 
-pub async fn get_timeline_by_id(
+pub async fn list_lab_sessions(
     pool: &SqlitePool,
-    id: i64,
-) -> Result<StudentTimeline, sqlx::Error> {
-    let timeline = sqlx::query_as!(
-        StudentTimeline,
+    subcourse_id: i64,
+) -> Result<Vec<LabSession>, sqlx::Error> {
+    sqlx::query_as!(
+        LabSession,
+        r#"
+        SELECT id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
+        FROM lab_sessions
+        WHERE subcourse_id = ?
+        ORDER BY opened_at DESC
+        "#,
+        subcourse_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Operations for student_logs
+pub async fn add_student_log(pool: &SqlitePool, log: StudentLog) -> Result<StudentLog, sqlx::Error> {
+    let session = match get_open_lab_session(pool, log.subcourse_id).await? {
+        Some(session) => session,
+        None => return Err(sqlx::Error::Protocol("No open lab session".into())),
+    };
+    add_session_student_log(pool, log, &session).await
+}
+
+// Records a log against a given session, open or already closed
+pub async fn add_session_student_log(
+    pool: &SqlitePool,
+    log: StudentLog,
+    session: &LabSession,
+) -> Result<StudentLog, sqlx::Error> {
+    if find_session_student_log(pool, &log.stu_id, session.id).await?.is_some() {
+        return Err(sqlx::Error::Protocol("Log already exists in this session".into()));
+    }
+    let now = Local::now().naive_local();
+    let rec = sqlx::query_as!(
+        StudentLog,
+        r#"
+        INSERT INTO student_logs (
+            stu_id, stu_name, subcourse_id, room_id, seat,
+            lab_name, note, tea_note, tea_name, fin_time, confirm, session_id
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+        RETURNING *
+        "#,
+        log.stu_id, log.stu_name, log.subcourse_id, log.room_id, log.seat,
+        log.lab_name, log.note, log.tea_note, log.tea_name, now, log.confirm, session.id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec)
+}
+
+pub async fn get_student_log_by_id(pool: &SqlitePool, id: i64) -> Result<StudentLog, sqlx::Error> {
+    let log = sqlx::query_as!(
+        StudentLog,
+        "SELECT * FROM student_logs WHERE id = ?",
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(log)
+}
+
+pub async fn update_student_log(pool: &SqlitePool, id: i64, log: StudentLog) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query!(
+        r#"
+        UPDATE student_logs
+        SET seat = ?1, note = ?2, fin_time = ?3, lab_name = ?4, fin_time = ?5
+        WHERE id = ?6 AND confirm = 0
+        "#,
+        log.seat, log.note, log.fin_time, log.lab_name, now, id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn confirm_student_log(
+    pool: &SqlitePool,
+    id: i64,
+    tea_note: &str,
+    tea_name: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query!(
+        "UPDATE student_logs SET tea_note = ?1, confirm = 1, fin_time = ?3, tea_name = ?4 WHERE id = ?2 ",
+        tea_note, id, now, tea_name
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn list_recent_logs(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+) -> Result<Vec<StudentLog>, sqlx::Error> {
+    match get_latest_lab_session(pool, subcourse_id).await? {
+        Some(session) => list_logs_by_session(pool, session.id).await,
+        None => Ok(vec![]),
+    }
+}
+
+pub async fn list_logs_by_session(
+    pool: &SqlitePool,
+    session_id: i64,
+) -> Result<Vec<StudentLog>, sqlx::Error> {
+    sqlx::query_as!(
+        StudentLog,
+        r#"
+        SELECT * FROM student_logs
+        WHERE session_id = ?1
+        ORDER BY seat
+        "#,
+        session_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_student_log_by_schedule(
+    pool: &SqlitePool,
+    stu_id: &str,
+    schedule_id: i64,
+) -> Result<Option<StudentLog>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        StudentLog,
+        r#"
+        SELECT sl.*
+        FROM student_logs sl
+        JOIN course_schedules cs ON sl.lab_name = cs.name
+        WHERE cs.id = ? AND sl.stu_id = ? LIMIT 1
+        "#,
+        schedule_id,
+        stu_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+pub async fn find_session_student_log(
+    pool: &SqlitePool,
+    stu_id: &str,
+    session_id: i64,
+) -> Result<Option<StudentLog>, sqlx::Error> {
+    sqlx::query_as!(
+        StudentLog,
+        r#"
+        SELECT id, stu_id, stu_name, subcourse_id, room_id, seat,
+               lab_name, note, tea_note, tea_name, fin_time, confirm, session_id
+        FROM student_logs
+        WHERE stu_id = ?1 AND session_id = ?2
+        ORDER BY fin_time DESC LIMIT 1
+        "#,
+        stu_id,
+        session_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn find_student_logs_by_room(
+    pool: &SqlitePool,
+    room_id: i64,
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+) -> Result<Vec<StudentLog>, sqlx::Error> {
+    sqlx::query_as!(
+        StudentLog,
+        r#"
+        SELECT id, stu_id, stu_name, subcourse_id, room_id, seat,
+               lab_name, note, tea_note, tea_name, fin_time, confirm, session_id
+        FROM student_logs
+        WHERE room_id = ?1 AND fin_time >= ?2 AND fin_time <= ?3
+        ORDER BY fin_time DESC
+        "#,
+        room_id,
+        start_time,
+        end_time
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_default_log(
+    pool: &SqlitePool,
+    stu_id: &str,
+    subcourse_id: i64
+) -> Result<StudentLog, sqlx::Error> {
+    let session = match get_open_lab_session(pool, subcourse_id).await? {
+        Some(session) => session,
+        None => return Err(sqlx::Error::Protocol("No open lab session".into())),
+    };
+    get_session_default_log(pool, stu_id, &session).await
+}
+
+// The student's log for the session, or a prefilled one if there is none yet
+pub async fn get_session_default_log(
+    pool: &SqlitePool,
+    stu_id: &str,
+    session: &LabSession,
+) -> Result<StudentLog, sqlx::Error> {
+    let today = Local::now().naive_local();
+    let subcourse_id = session.subcourse_id;
+    if let Some(existing_log) = find_session_student_log(pool, stu_id, session.id).await? {
+        return Ok(existing_log);
+    }
+    let mut log = StudentLog {
+        id: 0,
+        stu_id: stu_id.to_string(),
+        stu_name: String::new(),
+        subcourse_id,
+        room_id: 0,
+        seat: 0,
+        lab_name: String::new(),
+        note: String::new(),
+        tea_note: String::new(),
+        tea_name: String::new(),
+        fin_time: today,
+        confirm: 0,
+        session_id: Some(session.id),
+    };
+    let subcourse = get_subcourse_by_id(pool, subcourse_id).await?;
+    log.room_id = subcourse.room_id;
+    log.seat = get_student_seat(pool, stu_id, subcourse_id).await?;
+    log.lab_name = get_schedule_by_id(pool, session.schedule_id).await?.name;
+    Ok(log)
+}
+
+// Analytics over student_logs and lab_sessions
+pub async fn attendance_by_subcourse(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+) -> Result<Vec<AttendanceStat>, sqlx::Error> {
+    sqlx::query_as!(
+        AttendanceStat,
+        r#"
+        SELECT st.stu_id, st.stu_name, st.subcourse_id,
+            (SELECT COUNT(DISTINCT sl.session_id) FROM student_logs sl
+             WHERE sl.stu_id = st.stu_id AND sl.subcourse_id = st.subcourse_id) AS "attended!: i64",
+            (SELECT COUNT(*) FROM lab_sessions ls
+             WHERE ls.subcourse_id = st.subcourse_id) AS "held!: i64"
+        FROM students st
+        WHERE st.subcourse_id = ?1
+        ORDER BY st.seat
+        "#,
+        subcourse_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn attendance_by_course(
+    pool: &SqlitePool,
+    course_id: i64,
+    semester_id: i64,
+) -> Result<Vec<AttendanceStat>, sqlx::Error> {
+    sqlx::query_as!(
+        AttendanceStat,
+        r#"
+        SELECT st.stu_id, st.stu_name, st.subcourse_id,
+            (SELECT COUNT(DISTINCT sl.session_id) FROM student_logs sl
+             WHERE sl.stu_id = st.stu_id AND sl.subcourse_id = st.subcourse_id) AS "attended!: i64",
+            (SELECT COUNT(*) FROM lab_sessions ls
+             WHERE ls.subcourse_id = st.subcourse_id) AS "held!: i64"
+        FROM students st
+        JOIN subcourses s ON st.subcourse_id = s.id
+        WHERE s.course_id = ?1 AND s.year_id = ?2
+        ORDER BY st.subcourse_id, st.seat
+        "#,
+        course_id,
+        semester_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Time from a student's first timeline entry to the fin_time of their log,
+// averaged per schedule.
+pub async fn schedule_durations(
+    pool: &SqlitePool,
+    course_id: i64,
+    semester_id: i64,
+) -> Result<Vec<ScheduleDuration>, sqlx::Error> {
+    sqlx::query_as!(
+        ScheduleDuration,
+        r#"
+        SELECT cs.id AS "schedule_id!: i64", cs.week, cs.name,
+            COUNT(d.minutes) AS "finished!: i64",
+            AVG(d.minutes) AS "avg_minutes: f64"
+        FROM course_schedules cs
+        LEFT JOIN (
+            SELECT ls.schedule_id,
+                (julianday(sl.fin_time) - julianday(MIN(tl.timestamp))) * 1440 AS minutes
+            FROM student_logs sl
+            JOIN lab_sessions ls ON sl.session_id = ls.id
+            JOIN subcourses s ON ls.subcourse_id = s.id
+            JOIN student_timelines tl
+                ON tl.stu_id = sl.stu_id AND tl.schedule_id = ls.schedule_id
+                AND tl.subcourse_id = sl.subcourse_id
+            WHERE s.course_id = ?1 AND s.year_id = ?2
+            GROUP BY sl.id
+        ) d ON d.schedule_id = cs.id
+        WHERE cs.course_id = ?1
+        GROUP BY cs.id
+        ORDER BY cs.week
+        "#,
+        course_id,
+        semester_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn unconfirmed_backlog(
+    pool: &SqlitePool,
+    course_id: i64,
+    semester_id: i64,
+) -> Result<Vec<TeacherBacklog>, sqlx::Error> {
+    sqlx::query_as!(
+        TeacherBacklog,
+        r#"
+        SELECT s.tea_id, s.tea_name, COUNT(sl.id) AS "unconfirmed!: i64"
+        FROM subcourses s
+        LEFT JOIN student_logs sl ON sl.subcourse_id = s.id AND sl.confirm = 0
+        WHERE s.course_id = ?1 AND s.year_id = ?2
+        GROUP BY s.tea_id, s.tea_name
+        ORDER BY 3 DESC
+        "#,
+        course_id,
+        semester_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn add_subschedule(pool: &SqlitePool, item: SubSchedule) -> Result<SubSchedule, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        SubSchedule,
+        r#"
+        INSERT INTO subschedules (schedule_id, step, title)
+        VALUES (?1, ?2, ?3)
+        RETURNING id, schedule_id, step, title
+        "#,
+        item.schedule_id,
+        item.step,
+        item.title
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn get_subschedule_by_id(pool: &SqlitePool, id: i64) -> Result<SubSchedule, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        SubSchedule,
+        r#"SELECT id, schedule_id, step, title FROM subschedules WHERE id = ?"#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn list_subschedules(pool: &SqlitePool, schedule_id: i64) -> Result<Vec<SubSchedule>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        SubSchedule,
+        r#"SELECT id, schedule_id, step, title FROM subschedules WHERE schedule_id = ? ORDER BY step"#,
+        schedule_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recs)
+}
+
+pub async fn update_subschedule(pool: &SqlitePool, id: i64, item: SubSchedule) -> Result<SubSchedule, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        SubSchedule,
+        r#"
+        UPDATE subschedules
+        SET schedule_id = ?1, step = ?2, title = ?3
+        WHERE id = ?4
+        RETURNING id, schedule_id, step, title
+        "#,
+        item.schedule_id,
+        item.step,
+        item.title,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn delete_subschedule(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM subschedules WHERE id = ?",
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Operations for StudentTimeline
+pub async fn add_student_timeline(
+    pool: &SqlitePool,
+    timeline: StudentTimeline,
+) -> Result<StudentTimeline, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let rec = sqlx::query_as!(
+        StudentTimeline,
+        r#"
+        INSERT INTO student_timelines
+        (stu_id, tea_id, schedule_id, subschedule, subcourse_id, note, notetype, timestamp,
+         session_id, subschedule_id, file_id, body, body_html)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+        RETURNING *
+        "#,
+        timeline.stu_id,
+        timeline.tea_id,
+        timeline.schedule_id,
+        timeline.subschedule,
+        timeline.subcourse_id,
+        timeline.note,
+        timeline.notetype,
+        now,
+        timeline.session_id,
+        timeline.subschedule_id,
+        timeline.file_id,
+        timeline.body,
+        timeline.body_html
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn count_student_timeline_entries(
+    pool: &SqlitePool,
+    stu_id: &str,
+    schedule_id: i64,
+) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as count
+        FROM student_timelines
+        WHERE stu_id = ?1 AND schedule_id = ?2
+        "#,
+        stu_id,
+        schedule_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+// Attachment files are released by the caller before the rows go
+pub async fn delete_student_timeline(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM timeline_comments WHERE timeline_id = ?1", id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM timeline_attachments WHERE timeline_id = ?1", id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM timeline_feedbacks WHERE timeline_id = ?1", id)
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query!(
+        "DELETE FROM student_timelines WHERE id = ?1",
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_timeline_body_html(pool: &SqlitePool, id: i64, body_html: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("UPDATE student_timelines SET body_html = ?1 WHERE id = ?2", body_html, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Positions start at 1 and follow upload order
+pub async fn add_timeline_attachments(pool: &SqlitePool, timeline_id: i64, file_ids: &[i64]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for (i, file_id) in file_ids.iter().enumerate() {
+        let position = i as i64 + 1;
+        sqlx::query!(
+            "INSERT INTO timeline_attachments (timeline_id, position, file_id) VALUES (?1, ?2, ?3)",
+            timeline_id,
+            position,
+            file_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn list_timeline_attachments(pool: &SqlitePool, timeline_id: i64) -> Result<Vec<TimelineAttachment>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        TimelineAttachment,
+        r#"
+        SELECT a.id, a.timeline_id, a.position, a.file_id, f.fname, f.mime, f.fsize
+        FROM timeline_attachments a JOIN stored_files f ON f.id = a.file_id
+        WHERE a.timeline_id = ?1
+        ORDER BY a.position
+        "#,
+        timeline_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+pub async fn get_timeline_attachment(pool: &SqlitePool, timeline_id: i64, position: i64) -> Result<TimelineAttachment, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        TimelineAttachment,
+        r#"
+        SELECT a.id, a.timeline_id, a.position, a.file_id, f.fname, f.mime, f.fsize
+        FROM timeline_attachments a JOIN stored_files f ON f.id = a.file_id
+        WHERE a.timeline_id = ?1 AND a.position = ?2
+        "#,
+        timeline_id,
+        position
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec)
+}
+
+pub async fn add_timeline_comment(pool: &SqlitePool, comment: TimelineComment) -> Result<TimelineComment, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let rec = sqlx::query_as!(
+        TimelineComment,
+        r#"
+        INSERT INTO timeline_comments (timeline_id, parent_id, author_id, author_name, body, body_html, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        RETURNING *
+        "#,
+        comment.timeline_id,
+        comment.parent_id,
+        comment.author_id,
+        comment.author_name,
+        comment.body,
+        comment.body_html,
+        now
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec)
+}
+
+// Flat and oldest first; clients nest replies by parent_id
+pub async fn list_timeline_comments(pool: &SqlitePool, timeline_id: i64) -> Result<Vec<TimelineComment>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        TimelineComment,
+        "SELECT * FROM timeline_comments WHERE timeline_id = ?1 ORDER BY created_at, id",
+        timeline_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+pub async fn get_timeline_comment(pool: &SqlitePool, id: i64) -> Result<TimelineComment, sqlx::Error> {
+    let rec = sqlx::query_as!(TimelineComment, "SELECT * FROM timeline_comments WHERE id = ?1", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(rec)
+}
+
+// A comment with replies stays so the thread below it keeps its parent
+pub async fn delete_timeline_comment(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let replies = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM timeline_comments WHERE parent_id = ?1"#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+    if replies > 0 {
+        return Err(sqlx::Error::Protocol("Comment has replies".into()));
+    }
+    let result = sqlx::query!("DELETE FROM timeline_comments WHERE id = ?1", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_timelines_by_schedule(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+    schedule_id: i64,
+) -> Result<Vec<StudentTimeline>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        StudentTimeline,
+        r#"
+        SELECT * FROM student_timelines
+        WHERE schedule_id = ?1 AND subcourse_id = ?2
+        "#,
+        schedule_id,
+        subcourse_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recs)
+}
+
+pub async fn list_timelines_by_student(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+    stu_id: &str,
+    tea_id: &str,
+) -> Result<Vec<StudentTimeline>, sqlx::Error> {
+    let subcourse = get_subcourse_by_id(pool, subcourse_id).await?;
+    let recs = if tea_id == subcourse.tea_id {
+        sqlx::query_as!(
+            StudentTimeline,
+            r#"
+            SELECT * FROM student_timelines
+            WHERE stu_id = ?1 AND subcourse_id = ?2
+            "#,
+            stu_id,
+            subcourse_id
+        )
+        .fetch_all(pool)
+        .await?
+    } else {
+        sqlx::query_as!(
+            StudentTimeline,
+            r#"
+            SELECT * FROM student_timelines
+            WHERE stu_id = ?1 AND subcourse_id = ?2 AND tea_id = ?3
+            "#,
+            stu_id,
+            subcourse_id,
+            tea_id
+        )
+        .fetch_all(pool)
+        .await?
+    };
+    Ok(recs)
+}
+
+pub async fn get_timeline_by_id(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<StudentTimeline, sqlx::Error> {
+    let timeline = sqlx::query_as!(
+        StudentTimeline,
         r#"
         SELECT id, stu_id, tea_id, schedule_id, subschedule, subcourse_id,
-               note, notetype, timestamp
+               note, notetype, timestamp, session_id, subschedule_id, file_id, body, body_html
         FROM student_timelines WHERE id = ?
         "#,
-        id
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(timeline)
+}
+
+pub async fn add_timeline_feedback(pool: &SqlitePool, feedback: TimelineFeedback) -> Result<TimelineFeedback, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let rec = sqlx::query_as!(
+        TimelineFeedback,
+        r#"
+        INSERT INTO timeline_feedbacks (timeline_id, tea_id, tea_name, verdict, score, comment, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        RETURNING *
+        "#,
+        feedback.timeline_id,
+        feedback.tea_id,
+        feedback.tea_name,
+        feedback.verdict,
+        feedback.score,
+        feedback.comment,
+        now
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec)
+}
+
+pub async fn get_timeline_feedback(pool: &SqlitePool, id: i64) -> Result<TimelineFeedback, sqlx::Error> {
+    let rec = sqlx::query_as!(TimelineFeedback, "SELECT * FROM timeline_feedbacks WHERE id = ?1", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(rec)
+}
+
+pub async fn delete_timeline_feedback(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM timeline_feedbacks WHERE id = ?1", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// All feedback on one student's entries in a subcourse, oldest first
+pub async fn list_feedback_by_student(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+    stu_id: &str,
+) -> Result<Vec<TimelineFeedback>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        TimelineFeedback,
+        r#"
+        SELECT f.id AS "id!", f.timeline_id, f.tea_id, f.tea_name, f.verdict, f.score, f.comment, f.created_at
+        FROM timeline_feedbacks f JOIN student_timelines t ON t.id = f.timeline_id
+        WHERE t.subcourse_id = ?1 AND t.stu_id = ?2
+        ORDER BY f.created_at, f.id
+        "#,
+        subcourse_id,
+        stu_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// Operations for step_progresses
+pub async fn mark_step_progress(
+    pool: &SqlitePool,
+    stu_id: &str,
+    subcourse_id: i64,
+    subschedule_id: i64,
+    timeline_id: Option<i64>,
+) -> Result<StepProgress, sqlx::Error> {
+    let now = Local::now().naive_local();
+    // A verified step stays verified; a rejected one goes back to the teacher.
+    sqlx::query_as!(
+        StepProgress,
+        r#"
+        INSERT INTO step_progresses
+        (stu_id, subcourse_id, subschedule_id, timeline_id, status, tea_note, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?6, '', ?5)
+        ON CONFLICT (stu_id, subcourse_id, subschedule_id) DO UPDATE
+        SET timeline_id = COALESCE(excluded.timeline_id, timeline_id),
+            status = ?6, updated_at = excluded.updated_at
+        WHERE status != ?7
+        RETURNING id, stu_id, subcourse_id, subschedule_id, timeline_id, status, tea_note, updated_at
+        "#,
+        stu_id,
+        subcourse_id,
+        subschedule_id,
+        timeline_id,
+        now,
+        STEP_DONE,
+        STEP_VERIFIED
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_step_progress_by_id(pool: &SqlitePool, id: i64) -> Result<StepProgress, sqlx::Error> {
+    sqlx::query_as!(
+        StepProgress,
+        r#"
+        SELECT id, stu_id, subcourse_id, subschedule_id, timeline_id, status, tea_note, updated_at
+        FROM step_progresses WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn review_step_progress(
+    pool: &SqlitePool,
+    id: i64,
+    status: i64,
+    tea_note: &str,
+) -> Result<StepProgress, sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query_as!(
+        StepProgress,
+        r#"
+        UPDATE step_progresses
+        SET status = ?1, tea_note = ?2, updated_at = ?3
+        WHERE id = ?4
+        RETURNING id, stu_id, subcourse_id, subschedule_id, timeline_id, status, tea_note, updated_at
+        "#,
+        status,
+        tea_note,
+        now,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_step_progress_by_schedule(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+    schedule_id: i64,
+) -> Result<Vec<StepProgress>, sqlx::Error> {
+    sqlx::query_as!(
+        StepProgress,
+        r#"
+        SELECT p.id, p.stu_id, p.subcourse_id, p.subschedule_id, p.timeline_id,
+               p.status, p.tea_note, p.updated_at
+        FROM step_progresses p
+        JOIN subschedules ss ON p.subschedule_id = ss.id
+        WHERE p.subcourse_id = ?1 AND ss.schedule_id = ?2
+        ORDER BY ss.step
+        "#,
+        subcourse_id,
+        schedule_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_student_step_progress(
+    pool: &SqlitePool,
+    stu_id: &str,
+    subcourse_id: i64,
+    schedule_id: i64,
+) -> Result<Vec<StepProgress>, sqlx::Error> {
+    sqlx::query_as!(
+        StepProgress,
+        r#"
+        SELECT p.id, p.stu_id, p.subcourse_id, p.subschedule_id, p.timeline_id,
+               p.status, p.tea_note, p.updated_at
+        FROM step_progresses p
+        JOIN subschedules ss ON p.subschedule_id = ss.id
+        WHERE p.stu_id = ?1 AND p.subcourse_id = ?2 AND ss.schedule_id = ?3
+        ORDER BY ss.step
+        "#,
+        stu_id,
+        subcourse_id,
+        schedule_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Operations for assignments
+pub async fn add_assignment(pool: &SqlitePool, item: Assignment) -> Result<Assignment, sqlx::Error> {
+    sqlx::query_as!(
+        Assignment,
+        r#"
+        INSERT INTO assignments (schedule_id, title, due_days, allowed_types, max_size, allow_late)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        RETURNING id, schedule_id, title, due_days, allowed_types, max_size, allow_late
+        "#,
+        item.schedule_id,
+        item.title,
+        item.due_days,
+        item.allowed_types,
+        item.max_size,
+        item.allow_late
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_assignment_by_id(pool: &SqlitePool, id: i64) -> Result<Assignment, sqlx::Error> {
+    sqlx::query_as!(
+        Assignment,
+        r#"
+        SELECT id, schedule_id, title, due_days, allowed_types, max_size, allow_late
+        FROM assignments WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_assignments(pool: &SqlitePool, schedule_id: i64) -> Result<Vec<Assignment>, sqlx::Error> {
+    sqlx::query_as!(
+        Assignment,
+        r#"
+        SELECT id, schedule_id, title, due_days, allowed_types, max_size, allow_late
+        FROM assignments WHERE schedule_id = ?
+        "#,
+        schedule_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn update_assignment(pool: &SqlitePool, id: i64, item: Assignment) -> Result<Assignment, sqlx::Error> {
+    sqlx::query_as!(
+        Assignment,
+        r#"
+        UPDATE assignments
+        SET title = ?1, due_days = ?2, allowed_types = ?3, max_size = ?4, allow_late = ?5
+        WHERE id = ?6
+        RETURNING id, schedule_id, title, due_days, allowed_types, max_size, allow_late
+        "#,
+        item.title,
+        item.due_days,
+        item.allowed_types,
+        item.max_size,
+        item.allow_late,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn delete_assignment(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM assignments WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_lab_session_by_schedule(
+    pool: &SqlitePool,
+    subcourse_id: i64,
+    schedule_id: i64,
+) -> Result<Option<LabSession>, sqlx::Error> {
+    sqlx::query_as!(
+        LabSession,
+        r#"
+        SELECT id, subcourse_id, schedule_id, opened_at, closed_at, opened_by, secret
+        FROM lab_sessions
+        WHERE subcourse_id = ?1 AND schedule_id = ?2
+        ORDER BY opened_at LIMIT 1
+        "#,
+        subcourse_id,
+        schedule_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Operations for submissions
+pub async fn add_submission(
+    pool: &SqlitePool,
+    assignment_id: i64,
+    stu_id: &str,
+    subcourse_id: i64,
+    file: &StoredFile,
+    late: i64,
+) -> Result<Submission, sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query_as!(
+        Submission,
+        r#"
+        INSERT INTO submissions
+        (assignment_id, stu_id, subcourse_id, version, fname, fsize, submitted_at, late, status,
+         tea_comment, file_id)
+        SELECT ?1, ?2, ?3, IFNULL(MAX(version), 0) + 1, ?4, ?5, ?6, ?7, ?8, '', ?9
+        FROM submissions
+        WHERE assignment_id = ?1 AND stu_id = ?2
+        RETURNING id, assignment_id, stu_id, subcourse_id, version, fname, fsize,
+                  submitted_at, late, status, tea_comment, file_id
+        "#,
+        assignment_id,
+        stu_id,
+        subcourse_id,
+        file.fname,
+        file.fsize,
+        now,
+        late,
+        SUBMISSION_SUBMITTED,
+        file.id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_submission_by_id(pool: &SqlitePool, id: i64) -> Result<Submission, sqlx::Error> {
+    sqlx::query_as!(
+        Submission,
+        r#"
+        SELECT id, assignment_id, stu_id, subcourse_id, version, fname, fsize,
+               submitted_at, late, status, tea_comment, file_id
+        FROM submissions WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_latest_submission(
+    pool: &SqlitePool,
+    assignment_id: i64,
+    stu_id: &str,
+) -> Result<Option<Submission>, sqlx::Error> {
+    sqlx::query_as!(
+        Submission,
+        r#"
+        SELECT id, assignment_id, stu_id, subcourse_id, version, fname, fsize,
+               submitted_at, late, status, tea_comment, file_id
+        FROM submissions
+        WHERE assignment_id = ?1 AND stu_id = ?2
+        ORDER BY version DESC LIMIT 1
+        "#,
+        assignment_id,
+        stu_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn list_student_submissions(
+    pool: &SqlitePool,
+    assignment_id: i64,
+    subcourse_id: i64,
+    stu_id: &str,
+) -> Result<Vec<Submission>, sqlx::Error> {
+    sqlx::query_as!(
+        Submission,
+        r#"
+        SELECT id, assignment_id, stu_id, subcourse_id, version, fname, fsize,
+               submitted_at, late, status, tea_comment, file_id
+        FROM submissions
+        WHERE assignment_id = ?1 AND subcourse_id = ?2 AND stu_id = ?3
+        ORDER BY version DESC
+        "#,
+        assignment_id,
+        subcourse_id,
+        stu_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_latest_submissions(
+    pool: &SqlitePool,
+    assignment_id: i64,
+    subcourse_id: i64,
+) -> Result<Vec<Submission>, sqlx::Error> {
+    sqlx::query_as!(
+        Submission,
+        r#"
+        SELECT id, assignment_id, stu_id, subcourse_id, version, fname, fsize,
+               submitted_at, late, status, tea_comment, file_id
+        FROM submissions s
+        WHERE assignment_id = ?1 AND subcourse_id = ?2
+          AND version = (SELECT MAX(version) FROM submissions
+                         WHERE assignment_id = s.assignment_id AND stu_id = s.stu_id)
+        ORDER BY stu_id
+        "#,
+        assignment_id,
+        subcourse_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn review_submission(
+    pool: &SqlitePool,
+    id: i64,
+    status: i64,
+    tea_comment: &str,
+) -> Result<Submission, sqlx::Error> {
+    sqlx::query_as!(
+        Submission,
+        r#"
+        UPDATE submissions
+        SET status = ?1, tea_comment = ?2
+        WHERE id = ?3
+        RETURNING id, assignment_id, stu_id, subcourse_id, version, fname, fsize,
+                  submitted_at, late, status, tea_comment, file_id
+        "#,
+        status,
+        tea_comment,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Equipment operations
+pub async fn add_equipment(pool: &SqlitePool, equipment: Equipment) -> Result<Equipment, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        Equipment,
+        r#"
+        INSERT INTO equipments (name, serial, value, position, status, note, owner_id)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        RETURNING id, name, serial, value, position, status, note, owner_id, TRUE AS "available!: bool"
+        "#,
+        equipment.name,
+        equipment.serial,
+        equipment.value,
+        equipment.position,
+        equipment.status,
+        equipment.note,
+        equipment.owner_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn list_equipments(
+    pool: &SqlitePool,
+    owner_id: &str,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<Equipment>, sqlx::Error> {
+    let equipments = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT id, name, serial, value, position, status, note, owner_id,
+               NOT EXISTS (SELECT 1 FROM equipment_histories h
+                           WHERE h.item_id = equipments.id AND h.state IN (0, 1)) AS "available!: bool"
+        FROM equipments
+        WHERE owner_id = ?1 OR id IN (SELECT item_id FROM equipment_custodians WHERE user_id = ?1)
+        ORDER BY id DESC
+        LIMIT ?2 OFFSET ?3
+        "#,
+        owner_id,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(equipments)
+}
+
+// Returns one page of matches and the total number of matches. Text filters
+// are substring matches and expect LIKE wildcards to be escaped with '\'.
+pub async fn search_equipments(
+    pool: &SqlitePool,
+    filter: &EquipmentFilter,
+    fts_query: Option<&str>,
+    sort: &str,
+    offset: i64,
+    limit: i64,
+) -> Result<(Vec<Equipment>, i64), sqlx::Error> {
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!: i64"
+        FROM equipments
+        WHERE (?1 IS NULL OR owner_id = ?1)
+          AND (?2 IS NULL OR name LIKE '%' || ?2 || '%' ESCAPE '\')
+          AND (?3 IS NULL OR serial LIKE '%' || ?3 || '%' ESCAPE '\')
+          AND (?4 IS NULL OR position LIKE '%' || ?4 || '%' ESCAPE '\')
+          AND (?5 IS NULL OR status = ?5)
+          AND (?6 IS NULL OR value >= ?6)
+          AND (?7 IS NULL OR value <= ?7)
+          AND (?8 IS NULL OR id IN (SELECT rowid FROM equipments_fts WHERE equipments_fts MATCH ?8))
+          AND (?9 IS NULL OR ?9 = NOT EXISTS (SELECT 1 FROM equipment_histories h
+                                              WHERE h.item_id = equipments.id AND h.state IN (0, 1)))
+        "#,
+        filter.owner_id,
+        filter.name,
+        filter.serial,
+        filter.position,
+        filter.status,
+        filter.min_value,
+        filter.max_value,
+        fts_query,
+        filter.available
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let items = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT id, name, serial, value, position, status, note, owner_id,
+               NOT EXISTS (SELECT 1 FROM equipment_histories h
+                           WHERE h.item_id = equipments.id AND h.state IN (0, 1)) AS "available!: bool"
+        FROM equipments
+        WHERE (?1 IS NULL OR owner_id = ?1)
+          AND (?2 IS NULL OR name LIKE '%' || ?2 || '%' ESCAPE '\')
+          AND (?3 IS NULL OR serial LIKE '%' || ?3 || '%' ESCAPE '\')
+          AND (?4 IS NULL OR position LIKE '%' || ?4 || '%' ESCAPE '\')
+          AND (?5 IS NULL OR status = ?5)
+          AND (?6 IS NULL OR value >= ?6)
+          AND (?7 IS NULL OR value <= ?7)
+          AND (?8 IS NULL OR id IN (SELECT rowid FROM equipments_fts WHERE equipments_fts MATCH ?8))
+          AND (?9 IS NULL OR ?9 = NOT EXISTS (SELECT 1 FROM equipment_histories h
+                                              WHERE h.item_id = equipments.id AND h.state IN (0, 1)))
+        ORDER BY
+          CASE WHEN ?11 THEN NULL ELSE
+            CASE ?10 WHEN 'name' THEN name WHEN 'serial' THEN serial WHEN 'value' THEN value
+                     WHEN 'position' THEN position WHEN 'status' THEN status ELSE id END
+          END ASC,
+          CASE WHEN ?11 THEN
+            CASE ?10 WHEN 'name' THEN name WHEN 'serial' THEN serial WHEN 'value' THEN value
+                     WHEN 'position' THEN position WHEN 'status' THEN status ELSE id END
+          END DESC,
+          id DESC
+        LIMIT ?12 OFFSET ?13
+        "#,
+        filter.owner_id,
+        filter.name,
+        filter.serial,
+        filter.position,
+        filter.status,
+        filter.min_value,
+        filter.max_value,
+        fts_query,
+        filter.available,
+        sort,
+        filter.desc,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((items, total))
+}
+
+pub async fn get_equipment_by_id(pool: &SqlitePool, id: i64) -> Result<Equipment, sqlx::Error> {
+    let equipment = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT id, name, serial, value, position, status, note, owner_id,
+               NOT EXISTS (SELECT 1 FROM equipment_histories h
+                           WHERE h.item_id = equipments.id AND h.state IN (0, 1)) AS "available!: bool"
+        FROM equipments
+        WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(equipment)
+}
+
+pub async fn update_equipment(pool: &SqlitePool, id: i64, equipment: Equipment) -> Result<Equipment, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        Equipment,
+        r#"
+        UPDATE equipments
+        SET name = ?1, serial = ?2, value = ?3, position = ?4, status = ?5, note = ?6
+        WHERE id = ?7 AND owner_id = ?8
+        RETURNING id, name, serial, value, position, status, note, owner_id,
+                  NOT EXISTS (SELECT 1 FROM equipment_histories h
+                              WHERE h.item_id = equipments.id AND h.state IN (0, 1)) AS "available!: bool"
+        "#,
+        equipment.name,
+        equipment.serial,
+        equipment.value,
+        equipment.position,
+        equipment.status,
+        equipment.note,
+        id,
+        equipment.owner_id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn delete_equipment(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM equipment_custodians WHERE item_id = ?", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM equipment_transfers WHERE item_id = ?", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM equipment_custody_logs WHERE item_id = ?", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM equipment_maintenances WHERE item_id = ?", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM equipment_assets WHERE item_id = ?", id).execute(&mut *tx).await?;
+    let result = sqlx::query!(
+        r#"DELETE FROM equipments WHERE id = ?"#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// An item carries at most one open (reserved or checked out) loan; the
+// equipment_histories_open index backs this check up against races.
+pub async fn add_equipment_history(
+    pool: &SqlitePool,
+    history: EquipmentHistory,
+) -> Result<EquipmentHistory, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let open = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM equipment_histories WHERE item_id = ?1 AND state IN (?2, ?3)"#,
+        history.item_id,
+        LOAN_RESERVED,
+        LOAN_CHECKED_OUT
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if open > 0 {
+        return Err(sqlx::Error::Protocol("Item is already reserved or checked out".into()));
+    }
+    let rec = sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        INSERT INTO equipment_histories
+        (user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        RETURNING id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        "#,
+        history.user,
+        history.borrowed_date,
+        history.telephone,
+        history.note,
+        history.returned_date,
+        history.item_id,
+        history.borrower_id,
+        history.state,
+        history.due_date
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(rec)
+}
+
+pub async fn list_equipment_histories_by_item(
+    pool: &SqlitePool,
+    item_id: i64,
+) -> Result<Vec<EquipmentHistory>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        SELECT id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        FROM equipment_histories
+        WHERE item_id = ?
+        ORDER BY borrowed_date DESC
+        "#,
+        item_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recs)
+}
+
+pub async fn get_equipment_history_by_id(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<EquipmentHistory, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        SELECT id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        FROM equipment_histories
+        WHERE id = ?
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+// Returns the item's checked-out loan
+pub async fn update_equipment_history(
+    pool: &SqlitePool,
+    item_id: i64,
+    returned_date: NaiveDateTime,
+) -> Result<EquipmentHistory, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        UPDATE equipment_histories
+        SET returned_date = ?1, state = ?2
+        WHERE item_id = ?3 AND state = ?4
+        RETURNING id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        "#,
+        returned_date,
+        LOAN_RETURNED,
+        item_id,
+        LOAN_CHECKED_OUT
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+// Moves a loan from `from` to `to`, stamping pickup or return time as it goes.
+// RowNotFound means the loan was not in `from`.
+pub async fn transition_loan(
+    pool: &SqlitePool,
+    id: i64,
+    from: i64,
+    to: i64,
+    now: NaiveDateTime,
+) -> Result<EquipmentHistory, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        UPDATE equipment_histories
+        SET state = ?1,
+            borrowed_date = CASE WHEN ?1 = ?2 THEN ?3 ELSE borrowed_date END,
+            returned_date = CASE WHEN ?1 = ?4 THEN ?3 ELSE returned_date END
+        WHERE id = ?5 AND state = ?6
+        RETURNING id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        "#,
+        to,
+        LOAN_CHECKED_OUT,
+        now,
+        LOAN_RETURNED,
+        id,
+        from
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+pub async fn set_loan_due_date(
+    pool: &SqlitePool,
+    id: i64,
+    due_date: NaiveDateTime,
+) -> Result<EquipmentHistory, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        UPDATE equipment_histories
+        SET due_date = ?1
+        WHERE id = ?2 AND state IN (?3, ?4)
+        RETURNING id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        "#,
+        due_date,
+        id,
+        LOAN_RESERVED,
+        LOAN_CHECKED_OUT
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rec)
+}
+
+// Checked-out loans past their due date on items the owner is responsible for
+pub async fn list_overdue_loans(
+    pool: &SqlitePool,
+    owner_id: &str,
+    now: NaiveDateTime,
+) -> Result<Vec<OverdueLoan>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        OverdueLoan,
+        r#"
+        SELECT h.id, h.item_id, e.name, e.serial, h.borrower_id, h.user, h.telephone, h.borrowed_date,
+               h.due_date AS "due_date!: NaiveDateTime",
+               CAST(julianday(?1) - julianday(h.due_date) AS INTEGER) AS "days_overdue!: i64"
+        FROM equipment_histories h JOIN equipments e ON e.id = h.item_id
+        WHERE e.owner_id = ?2 AND h.state = ?3 AND h.due_date < ?1
+        ORDER BY h.due_date
+        "#,
+        now,
+        owner_id,
+        LOAN_CHECKED_OUT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recs)
+}
+
+pub async fn delete_equipment_history(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM equipment_histories WHERE id = ?",
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Inserts new serials and updates existing ones inside one transaction. With
+// `restrict_owner` set, rows whose serial belongs to someone else are refused.
+// Returns (created, updated, [(row, error)]).
+pub async fn import_equipments(
+    pool: &SqlitePool,
+    rows: Vec<(usize, Equipment)>,
+    restrict_owner: Option<&str>,
+) -> Result<(i64, i64, Vec<(usize, String)>), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let (mut created, mut updated, mut errors) = (0, 0, Vec::new());
+    for (row, equipment) in rows {
+        let existing = sqlx::query!(
+            "SELECT id, owner_id FROM equipments WHERE serial = ?1 ORDER BY id LIMIT 1",
+            equipment.serial
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        match existing {
+            Some(found) if restrict_owner.is_some_and(|owner| owner != found.owner_id) => {
+                errors.push((row, format!("Serial {} belongs to {}", equipment.serial, found.owner_id)));
+            }
+            Some(found) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE equipments
+                    SET name = ?1, value = ?2, position = ?3, status = ?4, note = ?5
+                    WHERE id = ?6
+                    "#,
+                    equipment.name,
+                    equipment.value,
+                    equipment.position,
+                    equipment.status,
+                    equipment.note,
+                    found.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                updated += 1;
+            }
+            None => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO equipments (name, serial, value, position, status, note, owner_id)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                    "#,
+                    equipment.name,
+                    equipment.serial,
+                    equipment.value,
+                    equipment.position,
+                    equipment.status,
+                    equipment.note,
+                    equipment.owner_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                created += 1;
+            }
+        }
+    }
+    tx.commit().await?;
+    Ok((created, updated, errors))
+}
+
+pub async fn list_inventory(pool: &SqlitePool, owner_id: Option<&str>) -> Result<Vec<InventoryRow>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        InventoryRow,
+        r#"
+        SELECT e.id, e.name, e.serial, e.value, e.position, e.status, e.note, e.owner_id,
+               h.state AS loan_state, h.borrower_id, h.user AS borrower, h.due_date
+        FROM equipments e
+        LEFT JOIN equipment_histories h ON h.item_id = e.id AND h.state IN (?1, ?2)
+        WHERE ?3 IS NULL OR e.owner_id = ?3
+        ORDER BY e.position, e.serial
+        "#,
+        LOAN_RESERVED,
+        LOAN_CHECKED_OUT,
+        owner_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+pub async fn get_equipment_by_serial(pool: &SqlitePool, serial: &str) -> Result<Option<Equipment>, sqlx::Error> {
+    let rec = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT id, name, serial, value, position, status, note, owner_id,
+               NOT EXISTS (SELECT 1 FROM equipment_histories h
+                           WHERE h.item_id = equipments.id AND h.state IN (0, 1)) AS "available!: bool"
+        FROM equipments
+        WHERE serial = ?1
+        ORDER BY id
+        LIMIT 1
+        "#,
+        serial
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// The item's reservation or checkout, if any
+pub async fn get_open_loan(pool: &SqlitePool, item_id: i64) -> Result<Option<EquipmentHistory>, sqlx::Error> {
+    sqlx::query_as!(
+        EquipmentHistory,
+        r#"
+        SELECT id, user, borrowed_date, telephone, note, returned_date, item_id, borrower_id, state, due_date
+        FROM equipment_histories
+        WHERE item_id = ?1 AND state IN (?2, ?3)
+        "#,
+        item_id,
+        LOAN_RESERVED,
+        LOAN_CHECKED_OUT
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Operations for stocktakes
+pub async fn add_stocktake(
+    pool: &SqlitePool,
+    name: &str,
+    created_by: &str,
+    positions: &[String],
+) -> Result<Stocktake, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query_as!(
+        Stocktake,
+        "INSERT INTO stocktakes (name, created_by, created_at) VALUES (?1, ?2, ?3) RETURNING *",
+        name,
+        created_by,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    for position in positions {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO stocktake_positions (stocktake_id, position) VALUES (?1, ?2)",
+            rec.id,
+            position
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(rec)
+}
+
+pub async fn list_stocktakes(pool: &SqlitePool) -> Result<Vec<Stocktake>, sqlx::Error> {
+    sqlx::query_as!(Stocktake, "SELECT * FROM stocktakes ORDER BY created_at DESC")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_stocktake(pool: &SqlitePool, id: i64) -> Result<Stocktake, sqlx::Error> {
+    sqlx::query_as!(Stocktake, "SELECT * FROM stocktakes WHERE id = ?1", id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn list_stocktake_positions(pool: &SqlitePool, id: i64) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT position FROM stocktake_positions WHERE stocktake_id = ?1 ORDER BY position", id)
+        .fetch_all(pool)
+        .await
+}
+
+// Equipment registered in the stocktake's positions
+pub async fn list_stocktake_expected(pool: &SqlitePool, id: i64) -> Result<Vec<Equipment>, sqlx::Error> {
+    sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT e.id, e.name, e.serial, e.value, e.position, e.status, e.note, e.owner_id,
+               NOT EXISTS (SELECT 1 FROM equipment_histories h
+                           WHERE h.item_id = e.id AND h.state IN (0, 1)) AS "available!: bool"
+        FROM equipments e JOIN stocktake_positions p ON p.position = e.position
+        WHERE p.stocktake_id = ?1
+        ORDER BY e.position, e.serial
+        "#,
+        id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A later check of the same serial replaces the earlier one
+pub async fn record_stocktake_item(pool: &SqlitePool, item: StocktakeItem) -> Result<StocktakeItem, sqlx::Error> {
+    sqlx::query_as!(
+        StocktakeItem,
+        r#"
+        INSERT INTO stocktake_items (stocktake_id, serial, equipment_id, status, found_position, checked_by, checked_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (stocktake_id, serial) DO UPDATE SET
+            equipment_id = excluded.equipment_id, status = excluded.status, found_position = excluded.found_position,
+            checked_by = excluded.checked_by, checked_at = excluded.checked_at
+        RETURNING *
+        "#,
+        item.stocktake_id,
+        item.serial,
+        item.equipment_id,
+        item.status,
+        item.found_position,
+        item.checked_by,
+        item.checked_at
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_stocktake_items(pool: &SqlitePool, id: i64) -> Result<Vec<StocktakeItem>, sqlx::Error> {
+    sqlx::query_as!(StocktakeItem, "SELECT * FROM stocktake_items WHERE stocktake_id = ?1 ORDER BY serial", id)
+        .fetch_all(pool)
+        .await
+}
+
+// Everything expected but never checked is recorded as missing when the stocktake closes
+pub async fn close_stocktake(pool: &SqlitePool, id: i64, closed_by: &str) -> Result<Stocktake, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
+    let closed = sqlx::query_as!(
+        Stocktake,
+        "UPDATE stocktakes SET closed_at = ?1 WHERE id = ?2 AND closed_at IS NULL RETURNING *",
+        now,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(closed) = closed else {
+        return Err(sqlx::Error::Protocol("Stocktake is already closed".into()));
+    };
+    sqlx::query!(
+        r#"
+        INSERT OR IGNORE INTO stocktake_items (stocktake_id, serial, equipment_id, status, found_position, checked_by, checked_at)
+        SELECT ?1, e.serial, e.id, ?2, NULL, ?3, ?4
+        FROM equipments e JOIN stocktake_positions p ON p.position = e.position AND p.stocktake_id = ?1
+        "#,
+        id,
+        STOCK_MISSING,
+        closed_by,
+        now
     )
-    .fetch_one(pool)
+    .execute(&mut *tx)
     .await?;
-
-    Ok(timeline)
+    tx.commit().await?;
+    Ok(closed)
 }
 
-// Equipment operations
-pub async fn add_equipment(pool: &SqlitePool, equipment: Equipment) -> Result<Equipment, sqlx::Error> {
+// Operations for equipment maintenance
+
+// An open repair puts the item under repair; a fresh calibration clears calibration-due
+pub async fn add_equipment_maintenance(
+    pool: &SqlitePool,
+    record: EquipmentMaintenance,
+) -> Result<EquipmentMaintenance, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
     let rec = sqlx::query_as!(
-        Equipment,
+        EquipmentMaintenance,
         r#"
-        INSERT INTO equipments (name, serial, value, position, status, note, owner_id)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-        RETURNING id, name, serial, value, position, status, note, owner_id
+        INSERT INTO equipment_maintenances
+            (item_id, kind, summary, cost, vendor, certificate, started_at, completed_at, expires_at, created_by, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+        RETURNING *
         "#,
-        equipment.name,
-        equipment.serial,
-        equipment.value,
-        equipment.position,
-        equipment.status,
-        equipment.note,
-        equipment.owner_id,
+        record.item_id,
+        record.kind,
+        record.summary,
+        record.cost,
+        record.vendor,
+        record.certificate,
+        record.started_at,
+        record.completed_at,
+        record.expires_at,
+        record.created_by,
+        now
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
-
+    if rec.kind == MAINT_REPAIR && rec.completed_at.is_none() {
+        sqlx::query!(
+            "UPDATE equipments SET status = ?1 WHERE id = ?2 AND status != ?3",
+            EQUIP_UNDER_REPAIR,
+            rec.item_id,
+            EQUIP_RETIRED
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    if rec.kind == MAINT_CALIBRATION && rec.expires_at.is_some_and(|d| d >= now.date()) {
+        sqlx::query!(
+            "UPDATE equipments SET status = ?1 WHERE id = ?2 AND status = ?3",
+            EQUIP_IN_SERVICE,
+            rec.item_id,
+            EQUIP_CALIBRATION_DUE
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
     Ok(rec)
 }
 
-pub async fn list_equipments(
-    pool: &SqlitePool,
-    owner_id: &str,
-    offset: i64,
-    limit: i64,
-) -> Result<Vec<Equipment>, sqlx::Error> {
-    let equipments = sqlx::query_as!(
-        Equipment,
+pub async fn list_equipment_maintenances(pool: &SqlitePool, item_id: i64) -> Result<Vec<EquipmentMaintenance>, sqlx::Error> {
+    sqlx::query_as!(
+        EquipmentMaintenance,
+        "SELECT * FROM equipment_maintenances WHERE item_id = ?1 ORDER BY started_at DESC, id DESC",
+        item_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_equipment_maintenance(pool: &SqlitePool, id: i64) -> Result<EquipmentMaintenance, sqlx::Error> {
+    sqlx::query_as!(EquipmentMaintenance, "SELECT * FROM equipment_maintenances WHERE id = ?1", id)
+        .fetch_one(pool)
+        .await
+}
+
+// Puts an item back in service once it has no open repair left
+async fn release_repair_status(conn: &mut sqlx::SqliteConnection, item_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
         r#"
-        SELECT id, name, serial, value, position, status, note, owner_id
-        FROM equipments
-        WHERE owner_id = ?1
-        ORDER BY id DESC
-        LIMIT ?2 OFFSET ?3
+        UPDATE equipments SET status = ?1
+        WHERE id = ?2 AND status = ?3
+          AND NOT EXISTS (SELECT 1 FROM equipment_maintenances
+                          WHERE item_id = ?2 AND kind = ?4 AND completed_at IS NULL)
         "#,
-        owner_id,
-        limit,
-        offset
+        EQUIP_IN_SERVICE,
+        item_id,
+        EQUIP_UNDER_REPAIR,
+        MAINT_REPAIR
     )
-    .fetch_all(pool)
+    .execute(conn)
     .await?;
-
-    Ok(equipments)
+    Ok(())
 }
 
-pub async fn get_equipment_by_id(pool: &SqlitePool, id: i64) -> Result<Equipment, sqlx::Error> {
-    let equipment = sqlx::query_as!(
-        Equipment,
+pub async fn complete_equipment_maintenance(
+    pool: &SqlitePool,
+    id: i64,
+    completed_at: NaiveDateTime,
+    cost: Option<i64>,
+) -> Result<EquipmentMaintenance, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query_as!(
+        EquipmentMaintenance,
         r#"
-        SELECT id, name, serial, value, position, status, note, owner_id
-        FROM equipments
-        WHERE id = ?
+        UPDATE equipment_maintenances SET completed_at = ?1, cost = COALESCE(?2, cost)
+        WHERE id = ?3 AND completed_at IS NULL
+        RETURNING *
         "#,
+        completed_at,
+        cost,
         id
     )
-    .fetch_one(pool)
+    .fetch_optional(&mut *tx)
     .await?;
+    let Some(rec) = rec else {
+        return Err(sqlx::Error::Protocol("Maintenance record is already completed".into()));
+    };
+    release_repair_status(&mut tx, rec.item_id).await?;
+    tx.commit().await?;
+    Ok(rec)
+}
 
-    Ok(equipment)
+pub async fn delete_equipment_maintenance(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let item_id = sqlx::query_scalar!("DELETE FROM equipment_maintenances WHERE id = ?1 RETURNING item_id", id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if let Some(item_id) = item_id {
+        release_repair_status(&mut tx, item_id).await?;
+    }
+    tx.commit().await?;
+    Ok(item_id.is_some())
 }
 
-pub async fn update_equipment(pool: &SqlitePool, id: i64, equipment: Equipment) -> Result<Equipment, sqlx::Error> {
-    let rec = sqlx::query_as!(
-        Equipment,
+pub async fn set_equipment_asset(pool: &SqlitePool, asset: EquipmentAsset) -> Result<EquipmentAsset, sqlx::Error> {
+    sqlx::query_as!(
+        EquipmentAsset,
         r#"
-        UPDATE equipments
-        SET name = ?1, serial = ?2, value = ?3, position = ?4, status = ?5, note = ?6
-        WHERE id = ?7 AND owner_id = ?8
-        RETURNING id, name, serial, value, position, status, note, owner_id
+        INSERT INTO equipment_assets (item_id, acquired_on, life_months, salvage_value)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (item_id) DO UPDATE SET
+            acquired_on = excluded.acquired_on, life_months = excluded.life_months, salvage_value = excluded.salvage_value
+        RETURNING *
         "#,
-        equipment.name,
-        equipment.serial,
-        equipment.value,
-        equipment.position,
-        equipment.status,
-        equipment.note,
-        id,
-        equipment.owner_id,
+        asset.item_id,
+        asset.acquired_on,
+        asset.life_months,
+        asset.salvage_value
     )
     .fetch_one(pool)
-    .await?;
+    .await
+}
 
-    Ok(rec)
+// Each item's latest calibration expiring on or before `until`; retired items are left out
+pub async fn list_calibrations_due(
+    pool: &SqlitePool,
+    until: NaiveDate,
+    owner_id: Option<&str>,
+) -> Result<Vec<CalibrationDue>, sqlx::Error> {
+    sqlx::query_as!(
+        CalibrationDue,
+        r#"
+        SELECT e.id AS item_id, e.name, e.serial, e.position, e.owner_id,
+               m.id AS maintenance_id, m.certificate, m.expires_at AS "expires_at!: NaiveDate", m.reminded_at
+        FROM equipment_maintenances m JOIN equipments e ON e.id = m.item_id
+        WHERE m.kind = ?1 AND e.status != ?2
+          AND m.id = (SELECT id FROM equipment_maintenances
+                      WHERE item_id = m.item_id AND kind = ?1 AND expires_at IS NOT NULL
+                      ORDER BY expires_at DESC, id DESC LIMIT 1)
+          AND m.expires_at <= ?3
+          AND (?4 IS NULL OR e.owner_id = ?4)
+        ORDER BY m.expires_at
+        "#,
+        MAINT_CALIBRATION,
+        EQUIP_RETIRED,
+        until,
+        owner_id
+    )
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn delete_equipment(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"DELETE FROM equipments WHERE id = ?"#,
-        id
+pub async fn mark_calibration_reminded(pool: &SqlitePool, id: i64, now: NaiveDateTime) -> Result<(), sqlx::Error> {
+    sqlx::query!("UPDATE equipment_maintenances SET reminded_at = ?1 WHERE id = ?2", now, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// In-service items whose latest calibration expired before `today` become calibration-due
+pub async fn flag_expired_calibrations(pool: &SqlitePool, today: NaiveDate) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE equipments SET status = ?1
+        WHERE status = ?2
+          AND id IN (SELECT item_id FROM equipment_maintenances
+                     WHERE kind = ?3 AND expires_at IS NOT NULL
+                     GROUP BY item_id HAVING MAX(expires_at) < ?4)
+        RETURNING id
+        "#,
+        EQUIP_CALIBRATION_DUE,
+        EQUIP_IN_SERVICE,
+        MAINT_CALIBRATION,
+        today
     )
-    .execute(pool)
-    .await?;
+    .fetch_all(pool)
+    .await
+}
 
-    Ok(result.rows_affected() > 0)
+pub async fn list_depreciation_rows(pool: &SqlitePool, owner_id: Option<&str>) -> Result<Vec<DepreciationRow>, sqlx::Error> {
+    sqlx::query_as!(
+        DepreciationRow,
+        r#"
+        SELECT e.id, e.name, e.serial, e.position, e.status, e.owner_id, e.value,
+               a.acquired_on AS "acquired_on?: NaiveDate", a.life_months AS "life_months?: i64",
+               a.salvage_value AS "salvage_value?: i64",
+               (SELECT COALESCE(SUM(cost), 0) FROM equipment_maintenances m WHERE m.item_id = e.id) AS "maintenance_cost!: i64"
+        FROM equipments e LEFT JOIN equipment_assets a ON a.item_id = e.id
+        WHERE ?1 IS NULL OR e.owner_id = ?1
+        ORDER BY e.position, e.serial
+        "#,
+        owner_id
+    )
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn add_equipment_history(
-    pool: &SqlitePool,
-    history: EquipmentHistory,
-) -> Result<EquipmentHistory, sqlx::Error> {
-    let rec = sqlx::query_as!(
-        EquipmentHistory,
+// Operations for equipment custody
+
+async fn add_custody_log(
+    conn: &mut sqlx::SqliteConnection,
+    item_id: i64,
+    action: &str,
+    actor_id: &str,
+    user_id: &str,
+    note: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query!(
         r#"
-        INSERT INTO equipment_histories (user, borrowed_date, telephone, note, returned_date, item_id)
+        INSERT INTO equipment_custody_logs (item_id, action, actor_id, user_id, note, created_at)
         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-        RETURNING id, user, borrowed_date, telephone, note, returned_date, item_id
         "#,
-        history.user,
-        history.borrowed_date,
-        history.telephone,
-        history.note,
-        history.returned_date,
-        history.item_id
+        item_id,
+        action,
+        actor_id,
+        user_id,
+        note,
+        now
     )
-    .fetch_one(pool)
+    .execute(conn)
     .await?;
+    Ok(())
+}
 
-    Ok(rec)
+pub async fn is_equipment_custodian(pool: &SqlitePool, item_id: i64, user_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM equipment_custodians WHERE item_id = ?1 AND user_id = ?2) AS "found!: bool""#,
+        item_id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
 }
 
-pub async fn list_equipment_histories_by_item(
-    pool: &SqlitePool,
-    item_id: i64,
-) -> Result<Vec<EquipmentHistory>, sqlx::Error> {
-    let recs = sqlx::query_as!(
-        EquipmentHistory,
+// A position belongs to a lab room when it is the room itself or starts with
+// the room followed by a space or dash, e.g. "2101" or "2101-cabinet 3"
+pub async fn manages_equipment_room(pool: &SqlitePool, position: &str, user_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
         r#"
-        SELECT id, user, borrowed_date, telephone, note, returned_date, item_id
-        FROM equipment_histories
-        WHERE item_id = ?
-        ORDER BY borrowed_date DESC
+        SELECT EXISTS (
+            SELECT 1 FROM labrooms l
+            WHERE l.manager = ?2
+              AND (?1 = l.room OR substr(?1, 1, length(l.room) + 1) IN (l.room || '-', l.room || ' '))
+        ) AS "found!: bool"
         "#,
+        position,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_equipment_custodians(pool: &SqlitePool, item_id: i64) -> Result<Vec<EquipmentCustodian>, sqlx::Error> {
+    sqlx::query_as!(
+        EquipmentCustodian,
+        "SELECT * FROM equipment_custodians WHERE item_id = ?1 ORDER BY added_at",
         item_id
     )
     .fetch_all(pool)
+    .await
+}
+
+pub async fn add_equipment_custodian(
+    pool: &SqlitePool,
+    item_id: i64,
+    user_id: &str,
+    added_by: &str,
+) -> Result<EquipmentCustodian, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query_as!(
+        EquipmentCustodian,
+        "INSERT INTO equipment_custodians (item_id, user_id, added_by, added_at) VALUES (?1, ?2, ?3, ?4) RETURNING *",
+        item_id,
+        user_id,
+        added_by,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    add_custody_log(&mut tx, item_id, "custodian_added", added_by, user_id, "").await?;
+    tx.commit().await?;
+    Ok(rec)
+}
+
+pub async fn remove_equipment_custodian(
+    pool: &SqlitePool,
+    item_id: i64,
+    user_id: &str,
+    removed_by: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        "DELETE FROM equipment_custodians WHERE item_id = ?1 AND user_id = ?2",
+        item_id,
+        user_id
+    )
+    .execute(&mut *tx)
     .await?;
+    if result.rows_affected() > 0 {
+        add_custody_log(&mut tx, item_id, "custodian_removed", removed_by, user_id, "").await?;
+    }
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
 
-    Ok(recs)
+pub async fn list_custody_logs(pool: &SqlitePool, item_id: i64) -> Result<Vec<CustodyLog>, sqlx::Error> {
+    sqlx::query_as!(
+        CustodyLog,
+        "SELECT * FROM equipment_custody_logs WHERE item_id = ?1 ORDER BY created_at DESC, id DESC",
+        item_id
+    )
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn get_equipment_history_by_id(
+// An item has at most one pending transfer
+pub async fn add_equipment_transfer(
     pool: &SqlitePool,
-    id: i64,
-) -> Result<EquipmentHistory, sqlx::Error> {
+    item_id: i64,
+    from_id: &str,
+    to_id: &str,
+    note: &str,
+    created_by: &str,
+) -> Result<EquipmentTransfer, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
     let rec = sqlx::query_as!(
-        EquipmentHistory,
+        EquipmentTransfer,
         r#"
-        SELECT id, user, borrowed_date, telephone, note, returned_date, item_id
-        FROM equipment_histories
-        WHERE id = ?
+        INSERT INTO equipment_transfers (item_id, from_id, to_id, note, state, created_by, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        RETURNING *
         "#,
-        id
+        item_id,
+        from_id,
+        to_id,
+        note,
+        TRANSFER_PENDING,
+        created_by,
+        now
     )
-    .fetch_one(pool)
-    .await?;
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref d) if d.is_unique_violation() => {
+            sqlx::Error::Protocol("A transfer is already pending for this item".into())
+        }
+        e => e,
+    })?;
+    add_custody_log(&mut tx, item_id, "transfer_requested", created_by, to_id, note).await?;
+    tx.commit().await?;
+    Ok(rec)
+}
+
+pub async fn get_equipment_transfer(pool: &SqlitePool, id: i64) -> Result<EquipmentTransfer, sqlx::Error> {
+    sqlx::query_as!(EquipmentTransfer, "SELECT * FROM equipment_transfers WHERE id = ?1", id)
+        .fetch_one(pool)
+        .await
+}
+
+// Pending transfers the user is giving away or receiving
+pub async fn list_pending_transfers(pool: &SqlitePool, user_id: &str) -> Result<Vec<EquipmentTransfer>, sqlx::Error> {
+    sqlx::query_as!(
+        EquipmentTransfer,
+        "SELECT * FROM equipment_transfers WHERE state = ?1 AND (from_id = ?2 OR to_id = ?2) ORDER BY created_at",
+        TRANSFER_PENDING,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
 
+// Accepting moves ownership, provided nobody else changed the owner in the meantime
+pub async fn decide_equipment_transfer(
+    pool: &SqlitePool,
+    id: i64,
+    state: i64,
+    actor_id: &str,
+) -> Result<EquipmentTransfer, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query_as!(
+        EquipmentTransfer,
+        "UPDATE equipment_transfers SET state = ?1, decided_at = ?2 WHERE id = ?3 AND state = ?4 RETURNING *",
+        state,
+        now,
+        id,
+        TRANSFER_PENDING
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(rec) = rec else {
+        return Err(sqlx::Error::Protocol("Transfer is no longer pending".into()));
+    };
+    let action = match state {
+        TRANSFER_ACCEPTED => {
+            let moved = sqlx::query!(
+                "UPDATE equipments SET owner_id = ?1 WHERE id = ?2 AND owner_id = ?3",
+                rec.to_id,
+                rec.item_id,
+                rec.from_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            if moved.rows_affected() == 0 {
+                return Err(sqlx::Error::Protocol("The item's owner has changed since the transfer was requested".into()));
+            }
+            sqlx::query!(
+                "DELETE FROM equipment_custodians WHERE item_id = ?1 AND user_id = ?2",
+                rec.item_id,
+                rec.to_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            "transfer_accepted"
+        }
+        TRANSFER_CANCELLED => "transfer_cancelled",
+        _ => "transfer_declined",
+    };
+    add_custody_log(&mut tx, rec.item_id, action, actor_id, &rec.to_id, &rec.note).await?;
+    tx.commit().await?;
     Ok(rec)
 }
 
-pub async fn update_equipment_history(
+// Lab manager or admin override: ownership moves at once and any pending transfer is cancelled
+pub async fn reassign_equipment(
     pool: &SqlitePool,
     item_id: i64,
-    returned_date: NaiveDateTime,
-) -> Result<EquipmentHistory, sqlx::Error> {
+    to_id: &str,
+    actor_id: &str,
+    note: &str,
+) -> Result<Equipment, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let mut tx = pool.begin().await?;
+    let from_id = sqlx::query_scalar!("SELECT owner_id FROM equipments WHERE id = ?1", item_id)
+        .fetch_one(&mut *tx)
+        .await?;
     let rec = sqlx::query_as!(
-        EquipmentHistory,
+        Equipment,
         r#"
-        UPDATE equipment_histories
-        SET returned_date = ?1
-        WHERE item_id = ?2 and returned_date = NULL
-        RETURNING id, user, borrowed_date, telephone, note, returned_date, item_id
+        UPDATE equipments SET owner_id = ?1 WHERE id = ?2
+        RETURNING id, name, serial, value, position, status, note, owner_id,
+                  NOT EXISTS (SELECT 1 FROM equipment_histories h
+                              WHERE h.item_id = equipments.id AND h.state IN (0, 1)) AS "available!: bool"
         "#,
-        returned_date,
+        to_id,
         item_id
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
-
-    Ok(rec)
-}
-
-pub async fn delete_equipment_history(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
-    let result = sqlx::query!(
-        "DELETE FROM equipment_histories WHERE id = ?",
-        id
+    sqlx::query!(
+        "UPDATE equipment_transfers SET state = ?1, decided_at = ?2 WHERE item_id = ?3 AND state = ?4",
+        TRANSFER_CANCELLED,
+        now,
+        item_id,
+        TRANSFER_PENDING
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
-
-    Ok(result.rows_affected() > 0)
+    sqlx::query!("DELETE FROM equipment_custodians WHERE item_id = ?1 AND user_id = ?2", item_id, to_id)
+        .execute(&mut *tx)
+        .await?;
+    let note = format!("from {}{}{}", from_id, if note.is_empty() { "" } else { ": " }, note);
+    add_custody_log(&mut tx, item_id, "override_transfer", actor_id, to_id, &note).await?;
+    tx.commit().await?;
+    Ok(rec)
 }
 
 // ========== Meeting Room ==========
@@ -1431,12 +3614,15 @@ pub async fn add_meeting_room(pool: &SqlitePool, room: MeetingRoom) -> Result<Me
     let rec = sqlx::query_as!(
         MeetingRoom,
         r#"
-        INSERT INTO meeting_rooms (room, info)
-        VALUES (?1, ?2)
-        RETURNING id, room, info
+        INSERT INTO meeting_rooms (room, info, capacity, projector, video_conferencing)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        RETURNING id, room, info, capacity, projector, video_conferencing
         "#,
         room.room,
-        room.info
+        room.info,
+        room.capacity,
+        room.projector,
+        room.video_conferencing
     )
     .fetch_one(pool)
     .await?;
@@ -1445,7 +3631,7 @@ pub async fn add_meeting_room(pool: &SqlitePool, room: MeetingRoom) -> Result<Me
 }
 
 pub async fn list_meeting_rooms(pool: &SqlitePool) -> Result<Vec<MeetingRoom>, sqlx::Error> {
-    sqlx::query_as!(MeetingRoom, r#"SELECT id, room, info FROM meeting_rooms"#)
+    sqlx::query_as!(MeetingRoom, r#"SELECT id, room, info, capacity, projector, video_conferencing FROM meeting_rooms"#)
         .fetch_all(pool)
         .await
 }
@@ -1456,12 +3642,15 @@ pub async fn update_meeting_room(pool: &SqlitePool, id: i64, room: MeetingRoom)
         MeetingRoom,
         r#"
         UPDATE meeting_rooms
-        SET room = ?1, info = ?2
-        WHERE id = ?3
-        RETURNING id, room, info
+        SET room = ?1, info = ?2, capacity = ?3, projector = ?4, video_conferencing = ?5
+        WHERE id = ?6
+        RETURNING id, room, info, capacity, projector, video_conferencing
         "#,
         room.room,
         room.info,
+        room.capacity,
+        room.projector,
+        room.video_conferencing,
         id
     )
     .fetch_one(pool)
@@ -1481,13 +3670,15 @@ pub async fn delete_meeting_room(pool: &SqlitePool, id: i64) -> Result<bool, sql
 // ========== Meeting Agenda ==========
 
 pub async fn add_meeting_agenda(pool: &SqlitePool, agenda: MeetingAgenda) -> Result<MeetingAgenda, sqlx::Error> {
+    let now = Local::now().naive_local();
     let rec = sqlx::query_as_unchecked!(
         MeetingAgenda,
         r#"
         INSERT INTO meeting_agendas
-        (title, userid, username, repeat, date, start_time, end_time, room_id, confirm)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm
+        (title, userid, username, repeat, date, start_time, end_time, room_id, confirm,
+         repeat_interval, repeat_until, repeat_count, decided_by, decided_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at
         "#,
         agenda.title,
         agenda.userid,
@@ -1497,7 +3688,13 @@ pub async fn add_meeting_agenda(pool: &SqlitePool, agenda: MeetingAgenda) -> Res
         agenda.start_time,
         agenda.end_time,
         agenda.room_id,
-        agenda.confirm
+        agenda.confirm,
+        agenda.repeat_interval,
+        agenda.repeat_until,
+        agenda.repeat_count,
+        agenda.decided_by,
+        agenda.decided_at,
+        now
     )
     .fetch_one(pool)
     .await?;
@@ -1505,48 +3702,50 @@ pub async fn add_meeting_agenda(pool: &SqlitePool, agenda: MeetingAgenda) -> Res
     Ok(rec)
 }
 
+// First occurrence of another agenda in the room overlapping one of `occurrences`,
+// together with the agenda it belongs to. Approved bookings always count;
+// pending requests only when `include_pending` is set.
+pub async fn find_meeting_conflict(
+    pool: &SqlitePool,
+    room_id: i64,
+    exclude_id: i64,
+    occurrences: &[MeetingOccurrence],
+    include_pending: bool,
+) -> Result<Option<(MeetingAgenda, MeetingOccurrence)>, sqlx::Error> {
+    let (Some(from), Some(to)) = (
+        occurrences.iter().map(|o| o.date).min(),
+        occurrences.iter().map(|o| o.date).max(),
+    ) else {
+        return Ok(None);
+    };
+    let agendas = list_meeting_agendas(pool, room_id).await?;
+    let exceptions = list_room_meeting_exceptions(pool, room_id).await?;
+    for agenda in agendas.into_iter().filter(|a| a.id != Some(exclude_id) && recurrence::blocks(a, include_pending)) {
+        let existing = recurrence::expand(&agenda, &exceptions, from, to);
+        let hit = existing
+            .into_iter()
+            .find(|e| occurrences.iter().any(|o| recurrence::overlaps(o, e)));
+        if let Some(hit) = hit {
+            return Ok(Some((agenda, hit)));
+        }
+    }
+    Ok(None)
+}
+
 pub async fn check_meeting_conflict(
     pool: &SqlitePool,
     agenda: &MeetingAgenda,
-) -> Result<Option<MeetingAgenda>, sqlx::Error> {
-    let new_weekday = agenda.date.weekday().num_days_from_sunday().to_string();
-    let new_date = agenda.date;
-    let new_start = agenda.start_time;
-    let new_end = agenda.end_time;
-    let current_id = agenda.id.unwrap_or(-1);
-
-    let conflict = sqlx::query_as_unchecked!(
-        MeetingAgenda,
-        r#"
-        SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm
-        FROM meeting_agendas
-        WHERE room_id = ?1
-          AND id != ?2
-          AND (
-            (repeat = 0 AND date = ?3)
-            OR
-            (repeat = 1 AND strftime('%w', date) = ?4)
-          )
-          AND (?5 < end_time AND ?6 > start_time)
-        LIMIT 1
-        "#,
-        agenda.room_id,
-        current_id,
-        new_date,
-        new_weekday,
-        new_start,
-        new_end,
-    )
-    .fetch_optional(pool)
-    .await?;
-
-    Ok(conflict)
+    exceptions: &[MeetingException],
+    include_pending: bool,
+) -> Result<Option<(MeetingAgenda, MeetingOccurrence)>, sqlx::Error> {
+    let occurrences = recurrence::series(agenda, exceptions);
+    find_meeting_conflict(pool, agenda.room_id, agenda.id.unwrap_or(-1), &occurrences, include_pending).await
 }
 
 pub async fn list_meeting_agendas(pool: &SqlitePool, id: i64) -> Result<Vec<MeetingAgenda>, sqlx::Error> {
     sqlx::query_as_unchecked!(
         MeetingAgenda,
-        r#"SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm FROM meeting_agendas where room_id=?"#, id
+        r#"SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at FROM meeting_agendas where room_id=?"#, id
     )
     .fetch_all(pool)
     .await
@@ -1556,7 +3755,7 @@ pub async fn get_meeting_agenda_by_id(pool: &SqlitePool, id: i64) -> Result<Meet
     sqlx::query_as_unchecked!(
         MeetingAgenda,
         r#"
-        SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm
+        SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at
         FROM meeting_agendas WHERE id = ?
         "#,
         id
@@ -1571,9 +3770,10 @@ pub async fn update_meeting_agenda(pool: &SqlitePool, id: i64, agenda: MeetingAg
         r#"
         UPDATE meeting_agendas
         SET title = ?1, userid = ?2, username = ?3, repeat = ?4, date = ?5,
-            start_time = ?6, end_time = ?7, room_id = ?8, confirm = ?8
-        WHERE id = ?10
-        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm
+            start_time = ?6, end_time = ?7, room_id = ?8, confirm = ?9,
+            repeat_interval = ?10, repeat_until = ?11, repeat_count = ?12
+        WHERE id = ?13
+        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at
         "#,
         agenda.title,
         agenda.userid,
@@ -1584,6 +3784,9 @@ pub async fn update_meeting_agenda(pool: &SqlitePool, id: i64, agenda: MeetingAg
         agenda.end_time,
         agenda.room_id,
         agenda.confirm,
+        agenda.repeat_interval,
+        agenda.repeat_until,
+        agenda.repeat_count,
         id
     )
     .fetch_one(pool)
@@ -1593,26 +3796,423 @@ pub async fn update_meeting_agenda(pool: &SqlitePool, id: i64, agenda: MeetingAg
 }
 
 pub async fn delete_meeting_agenda(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM meeting_exceptions WHERE agenda_id = ?", id)
+        .execute(&mut *tx)
+        .await?;
     let result = sqlx::query!("DELETE FROM meeting_agendas WHERE id = ?", id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
 
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn confirm_meeting_agenda(pool: &SqlitePool, id: i64) -> Result<MeetingAgenda, sqlx::Error> {
+// Moves a pending request to approved or rejected, or withdraws a pending
+// or approved booking. Fails if the agenda already left those states.
+pub async fn decide_meeting_agenda(
+    pool: &SqlitePool,
+    id: i64,
+    state: i64,
+    reason: &str,
+    actor_id: &str,
+) -> Result<MeetingAgenda, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let also_from = if state == MEETING_CANCELLED { MEETING_APPROVED } else { MEETING_PENDING };
     let rec = sqlx::query_as_unchecked!(
         MeetingAgenda,
         r#"
         UPDATE meeting_agendas
-        SET confirm = 1
-        WHERE id = ?1
-        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm
+        SET confirm = ?1, reason = ?2, decided_by = ?3, decided_at = ?4
+        WHERE id = ?5 AND confirm IN (?6, ?7)
+        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at
         "#,
-        id
+        state,
+        reason,
+        actor_id,
+        now,
+        id,
+        MEETING_PENDING,
+        also_from
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    rec.ok_or_else(|| sqlx::Error::Protocol("Meeting agenda can no longer be changed".into()))
+}
+
+// Rejects the pending requests that clash with a newly approved booking
+pub async fn reject_overlapping_requests(
+    pool: &SqlitePool,
+    agenda: &MeetingAgenda,
+    actor_id: &str,
+) -> Result<Vec<MeetingAgenda>, sqlx::Error> {
+    let exceptions = list_room_meeting_exceptions(pool, agenda.room_id).await?;
+    let booked = recurrence::series(agenda, &exceptions);
+    let (Some(from), Some(to)) = (booked.first().map(|o| o.date), booked.iter().map(|o| o.date).max()) else {
+        return Ok(Vec::new());
+    };
+    let mut rejected = Vec::new();
+    for other in list_meeting_agendas(pool, agenda.room_id).await? {
+        if other.confirm != MEETING_PENDING || other.id == agenda.id {
+            continue;
+        }
+        let clash = recurrence::expand(&other, &exceptions, from, to)
+            .iter()
+            .any(|o| booked.iter().any(|b| recurrence::overlaps(o, b)));
+        if !clash {
+            continue;
+        }
+        let reason = format!("Overlaps the approved booking \"{}\"", agenda.title);
+        match decide_meeting_agenda(pool, other.id.unwrap_or_default(), MEETING_REJECTED, &reason, actor_id).await {
+            Ok(rec) => rejected.push(rec),
+            Err(sqlx::Error::Protocol(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(rejected)
+}
+
+// The manager queue across all rooms, soonest first
+pub async fn list_pending_meeting_agendas(pool: &SqlitePool) -> Result<Vec<MeetingAgenda>, sqlx::Error> {
+    sqlx::query_as_unchecked!(
+        MeetingAgenda,
+        r#"
+        SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at
+        FROM meeting_agendas WHERE confirm = ?1 ORDER BY date, start_time
+        "#,
+        MEETING_PENDING
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Rejects requests nobody decided on before `created_before`, or whose first
+// meeting has already passed
+pub async fn expire_pending_meetings(
+    pool: &SqlitePool,
+    created_before: NaiveDateTime,
+    today: NaiveDate,
+) -> Result<Vec<MeetingAgenda>, sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query_as_unchecked!(
+        MeetingAgenda,
+        r#"
+        UPDATE meeting_agendas
+        SET confirm = ?1, reason = 'Expired without a decision', decided_at = ?2
+        WHERE confirm = ?3 AND (created_at < ?4 OR date < ?5)
+        RETURNING id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at
+        "#,
+        MEETING_REJECTED,
+        now,
+        MEETING_PENDING,
+        created_before,
+        today
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_user_ids_with_permission(pool: &SqlitePool, permission: i64) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT user_id FROM users WHERE permission & ?1 != 0", permission)
+        .fetch_all(pool)
+        .await
+}
+
+// Semester whose dates cover `date`, used to bound repeating agendas
+pub async fn get_semester_by_date(pool: &SqlitePool, date: NaiveDate) -> Result<Option<Semester>, sqlx::Error> {
+    sqlx::query_as!(
+        Semester,
+        r#"SELECT id, name, start, end FROM semesters WHERE DATE(start) <= DATE(?1) AND DATE(end) >= DATE(?1) ORDER BY start LIMIT 1"#,
+        date
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn list_meeting_exceptions(pool: &SqlitePool, agenda_id: i64) -> Result<Vec<MeetingException>, sqlx::Error> {
+    sqlx::query_as_unchecked!(
+        MeetingException,
+        r#"
+        SELECT id, agenda_id, date, cancelled, new_date, start_time, end_time, reason
+        FROM meeting_exceptions WHERE agenda_id = ?1 ORDER BY date
+        "#,
+        agenda_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_room_meeting_exceptions(pool: &SqlitePool, room_id: i64) -> Result<Vec<MeetingException>, sqlx::Error> {
+    sqlx::query_as_unchecked!(
+        MeetingException,
+        r#"
+        SELECT e.id, e.agenda_id, e.date, e.cancelled, e.new_date, e.start_time, e.end_time, e.reason
+        FROM meeting_exceptions e
+        JOIN meeting_agendas a ON a.id = e.agenda_id
+        WHERE a.room_id = ?1
+        "#,
+        room_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_user_meeting_exceptions(pool: &SqlitePool, userid: &str) -> Result<Vec<MeetingException>, sqlx::Error> {
+    sqlx::query_as_unchecked!(
+        MeetingException,
+        r#"
+        SELECT e.id, e.agenda_id, e.date, e.cancelled, e.new_date, e.start_time, e.end_time, e.reason
+        FROM meeting_exceptions e
+        JOIN meeting_agendas a ON a.id = e.agenda_id
+        WHERE a.userid = ?1
+        "#,
+        userid
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Replaces any earlier exception for the same occurrence
+pub async fn set_meeting_exception(pool: &SqlitePool, exception: MeetingException) -> Result<MeetingException, sqlx::Error> {
+    sqlx::query_as_unchecked!(
+        MeetingException,
+        r#"
+        INSERT INTO meeting_exceptions (agenda_id, date, cancelled, new_date, start_time, end_time, reason)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (agenda_id, date) DO UPDATE SET
+            cancelled = excluded.cancelled, new_date = excluded.new_date,
+            start_time = excluded.start_time, end_time = excluded.end_time, reason = excluded.reason
+        RETURNING id, agenda_id, date, cancelled, new_date, start_time, end_time, reason
+        "#,
+        exception.agenda_id,
+        exception.date,
+        exception.cancelled,
+        exception.new_date,
+        exception.start_time,
+        exception.end_time,
+        exception.reason
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn delete_meeting_exception(pool: &SqlitePool, agenda_id: i64, date: NaiveDate) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM meeting_exceptions WHERE agenda_id = ?1 AND date = ?2",
+        agenda_id,
+        date
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Operations for notifications
+pub async fn add_notification(
+    pool: &SqlitePool,
+    user_id: &str,
+    kind: &str,
+    message: &str,
+    link: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query!(
+        "INSERT INTO notifications (user_id, kind, message, link, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        user_id,
+        kind,
+        message,
+        link,
+        now
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn list_notifications(pool: &SqlitePool, user_id: &str, unread_only: bool) -> Result<Vec<Notification>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        Notification,
+        r#"
+        SELECT * FROM notifications
+        WHERE user_id = ?1 AND (?2 = 0 OR read_at IS NULL)
+        ORDER BY created_at DESC, id DESC
+        LIMIT 100
+        "#,
+        user_id,
+        unread_only
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+pub async fn count_unread_notifications(pool: &SqlitePool, user_id: &str) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM notifications WHERE user_id = ?1 AND read_at IS NULL"#,
+        user_id
     )
     .fetch_one(pool)
     .await?;
+    Ok(count)
+}
+
+// Scoped by user so nobody can mark another user's notifications
+pub async fn mark_notification_read(pool: &SqlitePool, id: i64, user_id: &str) -> Result<bool, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let result = sqlx::query!(
+        "UPDATE notifications SET read_at = ?1 WHERE id = ?2 AND user_id = ?3 AND read_at IS NULL",
+        now,
+        id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn mark_all_notifications_read(pool: &SqlitePool, user_id: &str) -> Result<u64, sqlx::Error> {
+    let now = Local::now().naive_local();
+    let result = sqlx::query!(
+        "UPDATE notifications SET read_at = ?1 WHERE user_id = ?2 AND read_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
 
+// Operations for file_fingerprints
+pub async fn get_fingerprint(pool: &SqlitePool, sha256: &str) -> Result<Option<FileFingerprint>, sqlx::Error> {
+    let rec = sqlx::query_as!(FileFingerprint, "SELECT * FROM file_fingerprints WHERE sha256 = ?1", sha256)
+        .fetch_optional(pool)
+        .await?;
     Ok(rec)
 }
+
+pub async fn set_fingerprint(pool: &SqlitePool, sha256: &str, kind: i64, data: &[u8]) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query!(
+        r#"
+        INSERT INTO file_fingerprints (sha256, kind, data, created_at) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (sha256) DO UPDATE SET kind = excluded.kind, data = excluded.data, created_at = excluded.created_at
+        "#,
+        sha256,
+        kind,
+        data,
+        now
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_fingerprint(pool: &SqlitePool, sha256: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM file_fingerprints WHERE sha256 = ?1", sha256)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Timeline files and attachments plus each student's latest submission for the
+// schedule's assignments, across every subcourse of the course
+pub async fn list_similarity_candidates(pool: &SqlitePool, schedule_id: i64) -> Result<Vec<SimilarityCandidate>, sqlx::Error> {
+    let recs = sqlx::query_as!(
+        SimilarityCandidate,
+        r#"
+        SELECT f.id AS "file_id!: i64", f.sha256 AS "sha256!", f.fname AS "fname!", t.stu_id AS "stu_id!",
+               t.subcourse_id AS "subcourse_id!: i64", 'timeline' AS "source!: String", t.id AS "source_id!: i64"
+        FROM student_timelines t JOIN stored_files f ON f.id = t.file_id
+        WHERE t.schedule_id = ?1 AND f.scan_status = ?2
+        UNION ALL
+        SELECT f.id, f.sha256, f.fname, t.stu_id, t.subcourse_id, 'timeline', t.id
+        FROM timeline_attachments a
+        JOIN student_timelines t ON t.id = a.timeline_id
+        JOIN stored_files f ON f.id = a.file_id
+        WHERE t.schedule_id = ?1 AND f.scan_status = ?2
+        UNION ALL
+        SELECT f.id, f.sha256, f.fname, s.stu_id, s.subcourse_id, 'submission', s.id
+        FROM submissions s
+        JOIN assignments a ON a.id = s.assignment_id
+        JOIN stored_files f ON f.id = s.file_id
+        WHERE a.schedule_id = ?1 AND f.scan_status = ?2
+          AND s.version = (SELECT MAX(version) FROM submissions s2
+                           WHERE s2.assignment_id = s.assignment_id AND s2.stu_id = s.stu_id)
+        "#,
+        schedule_id,
+        SCAN_CLEAN
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs)
+}
+
+// Operations for calendar feeds
+
+pub async fn get_calendar_token(pool: &SqlitePool, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT token FROM calendar_tokens WHERE user_id = ?1", user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+// Replaces any earlier token, so old feed URLs stop working
+pub async fn set_calendar_token(pool: &SqlitePool, user_id: &str, token: &str) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    sqlx::query!(
+        r#"
+        INSERT INTO calendar_tokens (user_id, token, created_at) VALUES (?1, ?2, ?3)
+        ON CONFLICT (user_id) DO UPDATE SET token = excluded.token, created_at = excluded.created_at
+        "#,
+        user_id,
+        token,
+        now
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_calendar_user(pool: &SqlitePool, token: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT user_id FROM calendar_tokens WHERE token = ?1", token)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn list_user_meeting_agendas(pool: &SqlitePool, userid: &str) -> Result<Vec<MeetingAgenda>, sqlx::Error> {
+    sqlx::query_as_unchecked!(
+        MeetingAgenda,
+        r#"SELECT id, title, userid, username, repeat, date, start_time, end_time, room_id, confirm, repeat_interval, repeat_until, repeat_count, reason, decided_by, decided_at, created_at FROM meeting_agendas WHERE userid = ?"#,
+        userid
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Every schedule week of the subcourses a user teaches or is enrolled in
+pub async fn list_calendar_sessions(pool: &SqlitePool, user_id: &str) -> Result<Vec<CalendarSession>, sqlx::Error> {
+    sqlx::query_as!(
+        CalendarSession,
+        r#"
+        SELECT s.id AS "subcourse_id!", c.name AS "course_name!", r.room AS "room!", s.tea_name AS "tea_name!",
+               s.weekday AS "weekday!", s.lag_week AS "lag_week!", y.start AS "semester_start!: NaiveDate",
+               cs.id AS "schedule_id!", cs.week AS "week!", cs.name AS "schedule_name!",
+               ls.opened_at AS "opened_at?: NaiveDateTime", ls.closed_at AS "closed_at?: NaiveDateTime"
+        FROM subcourses s
+        JOIN courses c ON c.id = s.course_id
+        JOIN labrooms r ON r.id = s.room_id
+        JOIN semesters y ON y.id = s.year_id
+        JOIN course_schedules cs ON cs.course_id = s.course_id
+        LEFT JOIN lab_sessions ls ON ls.id = (
+            SELECT id FROM lab_sessions WHERE subcourse_id = s.id AND schedule_id = cs.id
+            ORDER BY opened_at DESC LIMIT 1
+        )
+        WHERE s.tea_id = ?1 OR s.id IN (SELECT subcourse_id FROM students WHERE stu_id = ?1)
+        ORDER BY s.id, cs.week
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
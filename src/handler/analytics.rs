@@ -0,0 +1,146 @@
+use actix_session::Session;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::config::Config;
+use crate::db;
+use crate::models::AttendanceStat;
+use crate::utils::{check_course_perm, check_subcourse_perm};
+
+#[derive(Deserialize)]
+pub struct AnalyticsQuery {
+    pub semester_id: Option<i64>, // Defaults to the current semester
+    pub threshold: Option<f64>,   // Attendance rate below which a student is flagged
+}
+
+#[derive(Serialize)]
+pub struct AttendanceReport {
+    pub threshold: f64,
+    pub students: Vec<AttendanceRow>,
+    pub flagged: Vec<AttendanceRow>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AttendanceRow {
+    #[serde(flatten)]
+    pub stat: AttendanceStat,
+    pub missed: i64,
+    pub rate: f64,
+}
+
+fn build_report(stats: Vec<AttendanceStat>, threshold: f64) -> AttendanceReport {
+    let students: Vec<AttendanceRow> = stats
+        .into_iter()
+        .map(|stat| {
+            let rate = if stat.held > 0 { stat.attended as f64 / stat.held as f64 } else { 1.0 };
+            let missed = (stat.held - stat.attended).max(0);
+            AttendanceRow { stat, missed, rate }
+        })
+        .collect();
+    let flagged = students.iter().filter(|r| r.rate < threshold).cloned().collect();
+    AttendanceReport { threshold, students, flagged }
+}
+
+async fn resolve_semester(db_pool: &SqlitePool, semester_id: Option<i64>) -> Result<i64, HttpResponse> {
+    if let Some(id) = semester_id {
+        return Ok(id);
+    }
+    match db::get_current_semester(db_pool).await {
+        Ok(Some(semester)) => Ok(semester.id),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "error": "No active semester" }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[get("/analytics/course/{course_id}/attendance")]
+pub async fn course_attendance(
+    db_pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+    path: web::Path<i64>,
+    query: web::Query<AnalyticsQuery>,
+    session: Session,
+) -> impl Responder {
+    let course_id = path.into_inner();
+    if let Err(err) = check_course_perm(&db_pool, &session, course_id).await {
+        return err;
+    }
+    let semester_id = match resolve_semester(&db_pool, query.semester_id).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let threshold = query.threshold.unwrap_or(config.attendance_threshold);
+    match db::attendance_by_course(&db_pool, course_id, semester_id).await {
+        Ok(stats) => HttpResponse::Ok().json(build_report(stats, threshold)),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/analytics/subcourse/{subcourse_id}/attendance")]
+pub async fn subcourse_attendance(
+    db_pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+    path: web::Path<i64>,
+    query: web::Query<AnalyticsQuery>,
+    session: Session,
+) -> impl Responder {
+    let subcourse_id = path.into_inner();
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+        return err;
+    }
+    let threshold = query.threshold.unwrap_or(config.attendance_threshold);
+    match db::attendance_by_subcourse(&db_pool, subcourse_id).await {
+        Ok(stats) => HttpResponse::Ok().json(build_report(stats, threshold)),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/analytics/course/{course_id}/schedules")]
+pub async fn course_schedule_durations(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    query: web::Query<AnalyticsQuery>,
+    session: Session,
+) -> impl Responder {
+    let course_id = path.into_inner();
+    if let Err(err) = check_course_perm(&db_pool, &session, course_id).await {
+        return err;
+    }
+    let semester_id = match resolve_semester(&db_pool, query.semester_id).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    match db::schedule_durations(&db_pool, course_id, semester_id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/analytics/course/{course_id}/backlog")]
+pub async fn course_log_backlog(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    query: web::Query<AnalyticsQuery>,
+    session: Session,
+) -> impl Responder {
+    let course_id = path.into_inner();
+    if let Err(err) = check_course_perm(&db_pool, &session, course_id).await {
+        return err;
+    }
+    let semester_id = match resolve_semester(&db_pool, query.semester_id).await {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    match db::unconfirmed_backlog(&db_pool, course_id, semester_id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub fn init_analytics_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(course_attendance)
+        .service(subcourse_attendance)
+        .service(course_schedule_durations)
+        .service(course_log_backlog);
+}
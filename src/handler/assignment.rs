@@ -0,0 +1,454 @@
+use actix_multipart::Multipart;
+use actix_session::Session;
+use actix_files::NamedFile;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, PERMISSION_TEACHER};
+use crate::db;
+use crate::models::{Assignment, SUBMISSION_ACCEPTED, SUBMISSION_RETURNED};
+use crate::scanner::Scanner;
+use crate::similarity;
+use crate::storage::{self, Blob, Storage};
+use crate::utils::{
+    attachment, check_course_mime, check_scan, check_course_perm, check_quota, check_subcourse_perm, lab_date,
+    serve_stored_file, upload_error,
+};
+
+async fn check_assignment_perm(
+    db_pool: &web::Data<SqlitePool>,
+    session: &Session,
+    schedule_id: i64,
+) -> Result<(), HttpResponse> {
+    match db::get_schedule_by_id(db_pool, schedule_id).await {
+        Ok(schedule) => check_course_perm(db_pool, session, schedule.course_id).await,
+        Err(_) => Err(HttpResponse::NotFound().json(json!({ "error": "Schedule not found" }))),
+    }
+}
+
+#[post("/assignment")]
+pub async fn create_assignment(
+    db_pool: web::Data<SqlitePool>,
+    item: web::Json<Assignment>,
+    session: Session,
+) -> impl Responder {
+    let item = item.into_inner();
+    if let Err(err) = check_assignment_perm(&db_pool, &session, item.schedule_id).await {
+        return err;
+    }
+    match db::add_assignment(&db_pool, item).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/assignment/{id}")]
+pub async fn update_assignment(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<Assignment>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    match db::get_assignment_by_id(&db_pool, id).await {
+        Ok(existing) => {
+            if let Err(err) = check_assignment_perm(&db_pool, &session, existing.schedule_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::update_assignment(&db_pool, id, item.into_inner()).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/assignment/{id}")]
+pub async fn delete_assignment(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    match db::get_assignment_by_id(&db_pool, id).await {
+        Ok(existing) => {
+            if let Err(err) = check_assignment_perm(&db_pool, &session, existing.schedule_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::delete_assignment(&db_pool, id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Assignment deleted" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Assignment not found" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/assignment/schedule/{schedule_id}")]
+pub async fn list_assignments(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    match db::list_assignments(&db_pool, path.into_inner()).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// The deadline counts from the day the subcourse actually held the lab,
+// falling back to the timetable when no session was opened.
+async fn due_time(
+    db_pool: &SqlitePool,
+    assignment: &Assignment,
+    subcourse_id: i64,
+) -> Result<NaiveDateTime, sqlx::Error> {
+    let day = match db::get_lab_session_by_schedule(db_pool, subcourse_id, assignment.schedule_id).await? {
+        Some(session) => session.opened_at.date(),
+        None => {
+            let subcourse = db::get_subcourse_by_id(db_pool, subcourse_id).await?;
+            let semester = db::get_semester_by_id(db_pool, subcourse.year_id).await?;
+            let schedule = db::get_schedule_by_id(db_pool, assignment.schedule_id).await?;
+            lab_date(&semester, &subcourse, schedule.week)
+        }
+    };
+    let end_of_day = NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default();
+    Ok((day + Duration::days(assignment.due_days)).and_time(end_of_day))
+}
+
+fn type_allowed(allowed_types: &str, fname: &str) -> bool {
+    let allowed: Vec<String> = allowed_types
+        .split(',')
+        .map(|t| t.trim().trim_start_matches('.').to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if allowed.is_empty() {
+        return true;
+    }
+    let ext = Path::new(fname).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    allowed.contains(&ext)
+}
+
+#[post("/submission")]
+pub async fn create_submission(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    scanner: web::Data<dyn Scanner>,
+    config: web::Data<Config>,
+    mut payload: Multipart,
+    session: Session,
+) -> impl Responder {
+    let mut assignment_id = None;
+    let mut subcourse_id = None;
+    let mut upload = None;
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition.get_name().unwrap_or_default();
+
+        match name {
+            "file" => {
+                let fname = content_disposition.get_filename().unwrap_or("unnamed").to_string();
+                match storage::receive_upload(&mut field, &config.upload_tmp, &fname, config.upload_limit_submission).await {
+                    Ok(received) => upload = Some(received),
+                    Err(e) => return upload_error(e),
+                }
+            }
+            "assignment_id" => {
+                if let Ok(Some(data)) = field.try_next().await {
+                    assignment_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+                }
+            }
+            "subcourse_id" => {
+                if let Ok(Some(data)) = field.try_next().await {
+                    subcourse_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (assignment_id, subcourse_id, upload) = match (assignment_id, subcourse_id, upload) {
+        (Some(a), Some(s), Some(f)) => (a, s, f),
+        _ => return HttpResponse::BadRequest().json(json!({ "error": "Missing required fields" })),
+    };
+    if db::get_student_seat(&db_pool, &user_id, subcourse_id).await.is_err() {
+        return HttpResponse::Forbidden().json(json!({ "error": "Not enrolled in this subcourse" }));
+    }
+    let course_id = match db::get_subcourse_by_id(&db_pool, subcourse_id).await {
+        Ok(subcourse) => subcourse.course_id,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let assignment = match db::get_assignment_by_id(&db_pool, assignment_id).await {
+        Ok(a) => a,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::get_schedule_by_id(&db_pool, assignment.schedule_id).await {
+        Ok(schedule) if schedule.course_id != course_id => {
+            return HttpResponse::BadRequest().json(json!({ "error": "Assignment does not belong to this subcourse" }));
+        }
+        Ok(_) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    if !type_allowed(&assignment.allowed_types, &upload.fname) {
+        return HttpResponse::BadRequest().json(json!({ "error": "File type not allowed" }));
+    }
+    if let Err(err) = check_course_mime(&db_pool, course_id, &upload).await {
+        return err;
+    }
+    if upload.fsize > assignment.max_size {
+        return HttpResponse::PayloadTooLarge().json(json!({ "error": "File too large" }));
+    }
+    if let Ok(Some(latest)) = db::get_latest_submission(&db_pool, assignment_id, &user_id).await {
+        if latest.status == SUBMISSION_ACCEPTED {
+            return HttpResponse::BadRequest().json(json!({ "error": "Submission already accepted" }));
+        }
+    }
+    let late = match due_time(&db_pool, &assignment, subcourse_id).await {
+        Ok(due) => chrono::Local::now().naive_local() > due,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if late && assignment.allow_late == 0 {
+        return HttpResponse::Forbidden().json(json!({ "error": "The deadline has passed" }));
+    }
+    if let Err(err) = check_quota(&db_pool, &config, &user_id, &upload).await {
+        return err;
+    }
+
+    let stored = match storage::store_upload(storage.as_ref(), scanner.as_ref(), &db_pool, upload, &user_id).await {
+        Ok(stored) => stored,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_scan(&stored) {
+        return *err;
+    }
+    let sub = match db::add_submission(
+        &db_pool, assignment_id, &user_id, subcourse_id, &stored, late as i64,
+    ).await {
+        Ok(sub) => sub,
+        Err(e) => {
+            let _ = storage::release_file(storage.as_ref(), &db_pool, stored.id).await;
+            return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+        }
+    };
+    similarity::fingerprint_later(storage.into_inner(), db_pool.get_ref().clone(), vec![stored.id]);
+    HttpResponse::Ok().json(sub)
+}
+
+async fn check_submission_access(
+    db_pool: &web::Data<SqlitePool>,
+    session: &Session,
+    stu_id: &str,
+    subcourse_id: i64,
+) -> Result<(), HttpResponse> {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    if permission & PERMISSION_TEACHER != 0 {
+        return check_subcourse_perm(db_pool, session, subcourse_id).await;
+    }
+    if user_id != stu_id {
+        return Err(HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" })));
+    }
+    Ok(())
+}
+
+// Students may only be looked up through a subcourse they are enrolled in
+async fn check_student_enrolled(
+    db_pool: &web::Data<SqlitePool>,
+    stu_id: &str,
+    subcourse_id: i64,
+) -> Result<(), HttpResponse> {
+    match db::get_student_seat(db_pool, stu_id, subcourse_id).await {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::RowNotFound) => {
+            Err(HttpResponse::NotFound().json(json!({ "error": "Student not enrolled in this subcourse" })))
+        }
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[get("/submission/{assignment_id}/{subcourse_id}/{stu_id}")]
+pub async fn list_student_submissions(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<(i64, i64, String)>,
+    session: Session,
+) -> impl Responder {
+    let (assignment_id, subcourse_id, stu_id) = path.into_inner();
+    if let Err(err) = check_submission_access(&db_pool, &session, &stu_id, subcourse_id).await {
+        return err;
+    }
+    if let Err(err) = check_student_enrolled(&db_pool, &stu_id, subcourse_id).await {
+        return err;
+    }
+    match db::list_student_submissions(&db_pool, assignment_id, subcourse_id, &stu_id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/submission/file/{id}")]
+pub async fn download_submission(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<i64>,
+    req: HttpRequest,
+    session: Session,
+) -> impl Responder {
+    let sub = match db::get_submission_by_id(&db_pool, path.into_inner()).await {
+        Ok(sub) => sub,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_submission_access(&db_pool, &session, &sub.stu_id, sub.subcourse_id).await {
+        return err;
+    }
+    match sub.file_id {
+        Some(file_id) => serve_stored_file(&req, storage.as_ref(), &db_pool, file_id).await,
+        None => HttpResponse::NotFound().json(json!({ "error": "File not found" })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReviewSubmissionRequest {
+    pub accepted: bool, // false returns it to the student for resubmission
+    pub tea_comment: String,
+}
+
+#[put("/submission/{id}/review")]
+pub async fn review_submission(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<ReviewSubmissionRequest>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    match db::get_submission_by_id(&db_pool, id).await {
+        Ok(sub) => {
+            if let Err(err) = check_subcourse_perm(&db_pool, &session, sub.subcourse_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    let status = if item.accepted { SUBMISSION_ACCEPTED } else { SUBMISSION_RETURNED };
+    match db::review_submission(&db_pool, id, status, &item.tea_comment).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/submission/latest/{assignment_id}/{subcourse_id}")]
+pub async fn list_latest_submissions(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<(i64, i64)>,
+    session: Session,
+) -> impl Responder {
+    let (assignment_id, subcourse_id) = path.into_inner();
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+        return err;
+    }
+    match db::list_latest_submissions(&db_pool, assignment_id, subcourse_id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Archives are assembled in a temp file, so memory use does not grow with the class size
+const MAX_ZIP_BYTES: i64 = 2 << 30;
+
+fn build_zip(dest: &Path, entries: &[(String, PathBuf)]) -> zip::result::ZipResult<()> {
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(dest)?);
+    let options = zip::write::SimpleFileOptions::default();
+    for (name, path) in entries {
+        writer.start_file(name.as_str(), options)?;
+        std::io::copy(&mut std::fs::File::open(path)?, &mut writer)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[get("/submission/zip/{assignment_id}/{subcourse_id}")]
+pub async fn download_submissions_zip(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    config: web::Data<Config>,
+    path: web::Path<(i64, i64)>,
+    req: HttpRequest,
+    session: Session,
+) -> impl Responder {
+    let (assignment_id, subcourse_id) = path.into_inner();
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+        return err;
+    }
+    let subs = match db::list_latest_submissions(&db_pool, assignment_id, subcourse_id).await {
+        Ok(subs) => subs,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if subs.iter().filter(|sub| sub.file_id.is_some()).map(|sub| sub.fsize).sum::<i64>() > MAX_ZIP_BYTES {
+        return HttpResponse::PayloadTooLarge().json(json!({ "error": "Submissions are too large to archive together" }));
+    }
+    if let Err(e) = tokio::fs::create_dir_all(&config.upload_tmp).await {
+        return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+    }
+    let mut staged = Vec::new(); // Local copies of remote blobs, removed once the archive is built
+    let mut entries = Vec::new();
+    for sub in subs {
+        let Some(file_id) = sub.file_id else { continue };
+        // Missing blobs are skipped rather than failing the archive
+        let path = match storage::open_file(storage.as_ref(), &db_pool, file_id).await {
+            Ok((_, Blob::Path(path))) => path,
+            Ok((_, blob)) => {
+                let tmp = storage::temp_path(&config.upload_tmp, "part");
+                staged.push(tmp.clone());
+                if storage::save_blob(blob, &tmp).await.is_err() {
+                    continue;
+                }
+                tmp
+            }
+            Err(_) => continue,
+        };
+        let entry = format!("{}_v{}_{}", sub.stu_id, sub.version, sub.fname);
+        entries.push((storage::sanitize_filename(&entry), path));
+    }
+
+    let zip_path = storage::temp_path(&config.upload_tmp, "zip");
+    let dest = zip_path.clone();
+    let built = web::block(move || build_zip(&dest, &entries)).await;
+    for tmp in staged {
+        let _ = tokio::fs::remove_file(tmp).await;
+    }
+    let response = match built {
+        Ok(Ok(())) => match NamedFile::open_async(&zip_path).await {
+            Ok(file) => file
+                .set_content_disposition(attachment(&format!("submissions_{}_{}.zip", assignment_id, subcourse_id)))
+                .into_response(&req),
+            Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        },
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    // The open handle keeps the archive readable once its name is gone
+    let _ = tokio::fs::remove_file(&zip_path).await;
+    response
+}
+
+pub fn init_assignment_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_assignment)
+        .service(update_assignment)
+        .service(delete_assignment)
+        .service(review_submission)
+        .service(list_latest_submissions)
+        .service(download_submissions_zip);
+}
+
+pub fn init_submission_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_assignments)
+        .service(list_student_submissions)
+        .service(download_submission);
+}
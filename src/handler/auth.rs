@@ -6,6 +6,7 @@ use serde::Deserialize;
 use crate::db;
 use crate::config::{PERMISSION_STUDENT, PERMISSION_TEACHER, Config};
 use crate::models::User;
+use crate::utils::client_ip;
 use log::error;
 use std::env;
 
@@ -82,16 +83,7 @@ pub async fn iaaa_callback(
 
     }
 
-    // Get client IP, respecting X-Forwarded-For header
-    let ip = req.peer_addr()
-        .map(|addr| addr.ip().to_string())
-        .or_else(|| {
-            req.headers()
-                .get("X-Forwarded-For")
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.split(',').next().unwrap().trim().to_string())
-        })
-        .unwrap_or_else(|| "0.0.0.0".to_string());
+    let ip = client_ip(&req, &config);
 
     log::info!("Validating token for IP: {}", ip);
 
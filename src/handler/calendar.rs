@@ -0,0 +1,238 @@
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{Duration, NaiveDateTime};
+use rand::{distributions::Alphanumeric, Rng};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::db;
+use crate::handler::meeting::is_active;
+use crate::ical::{self, Event, EventTime};
+use crate::models::{CalendarSession, MeetingAgenda, MeetingException, MeetingRoom};
+use crate::recurrence;
+use crate::utils::week_date;
+
+const TOKEN_LEN: usize = 32;
+
+fn new_token() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(TOKEN_LEN).map(char::from).collect()
+}
+
+async fn feed_urls(db_pool: &SqlitePool, config: &Config, token: &str) -> Result<serde_json::Value, sqlx::Error> {
+    let rooms = db::list_meeting_rooms(db_pool).await?;
+    let base = format!("{}/calendar/{}", config.public_url, token);
+    Ok(json!({
+        "personal": format!("{}/personal.ics", base),
+        "rooms": rooms
+            .iter()
+            .filter_map(|r| r.id.map(|id| json!({ "id": id, "room": r.room, "url": format!("{}/room/{}.ics", base, id) })))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+// Feed URLs for the logged-in user; the secret token is created on first use
+#[get("/calendar/feeds")]
+pub async fn get_calendar_feeds(db_pool: web::Data<SqlitePool>, config: web::Data<Config>, session: Session) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    if user_id.is_empty() {
+        return HttpResponse::Unauthorized().json(json!({ "error": "User not logged in" }));
+    }
+    // Calendar apps need absolute URLs, so no feed is issued until PUBLIC_URL is set
+    if config.public_url.is_empty() {
+        return HttpResponse::ServiceUnavailable().json(json!({ "error": "Calendar feeds need PUBLIC_URL to be configured" }));
+    }
+    let token = match db::get_calendar_token(&db_pool, &user_id).await {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            let token = new_token();
+            if let Err(e) = db::set_calendar_token(&db_pool, &user_id, &token).await {
+                return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+            }
+            token
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match feed_urls(&db_pool, &config, &token).await {
+        Ok(urls) => HttpResponse::Ok().json(urls),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Issues a new token, revoking every URL handed out before
+#[post("/calendar/feeds/reset")]
+pub async fn reset_calendar_feeds(db_pool: web::Data<SqlitePool>, config: web::Data<Config>, session: Session) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    if user_id.is_empty() {
+        return HttpResponse::Unauthorized().json(json!({ "error": "User not logged in" }));
+    }
+    if config.public_url.is_empty() {
+        return HttpResponse::ServiceUnavailable().json(json!({ "error": "Calendar feeds need PUBLIC_URL to be configured" }));
+    }
+    let token = new_token();
+    if let Err(e) = db::set_calendar_token(&db_pool, &user_id, &token).await {
+        return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+    }
+    match feed_urls(&db_pool, &config, &token).await {
+        Ok(urls) => HttpResponse::Ok().json(urls),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// The series itself plus one override per moved occurrence; cancelled
+// occurrences become EXDATEs
+fn meeting_events(agenda: &MeetingAgenda, exceptions: &[MeetingException], rooms: &HashMap<i64, String>) -> Vec<Event> {
+    if !is_active(agenda) {
+        return Vec::new();
+    }
+    let at = |date, time| EventTime::Local(NaiveDateTime::new(date, time));
+    let own: Vec<&MeetingException> = exceptions
+        .iter()
+        .filter(|e| Some(e.agenda_id) == agenda.id && recurrence::is_occurrence(agenda, e.date))
+        .collect();
+    let event = |start, end| Event {
+        uid: format!("meeting-{}@laboxide", agenda.id.unwrap_or_default()),
+        summary: agenda.title.clone(),
+        location: rooms.get(&agenda.room_id).cloned().unwrap_or_default(),
+        description: format!("Booked by {}", agenda.username),
+        start,
+        end,
+        rrule: None,
+        exdates: Vec::new(),
+        recurrence_id: None,
+        tentative: agenda.confirm == 0,
+    };
+    let overrides = own.iter().filter_map(|e| recurrence::apply_exception(agenda, e).map(|occ| (e.date, occ)));
+
+    if agenda.repeat != 1 {
+        // A single meeting is simply replaced by its exception, if any
+        return match own.first() {
+            None => vec![event(at(agenda.date, agenda.start_time), at(agenda.date, agenda.end_time))],
+            Some(_) => overrides
+                .map(|(_, occ)| event(at(occ.date, occ.start_time), at(occ.date, occ.end_time)))
+                .collect(),
+        };
+    }
+    let mut series = event(at(agenda.date, agenda.start_time), at(agenda.date, agenda.end_time));
+    series.rrule = recurrence::rrule(agenda);
+    series.exdates = own
+        .iter()
+        .filter(|e| e.cancelled != 0)
+        .map(|e| at(e.date, agenda.start_time))
+        .collect();
+    let mut events = vec![series];
+    events.extend(overrides.map(|(original, occ)| {
+        let mut moved = event(at(occ.date, occ.start_time), at(occ.date, occ.end_time));
+        moved.recurrence_id = Some(at(original, agenda.start_time));
+        moved
+    }));
+    events
+}
+
+// Subcourses carry no time of day, so a lab is an all-day event unless a
+// lab session was actually opened and closed for it
+fn session_event(s: &CalendarSession) -> Event {
+    let (start, end) = match (s.opened_at, s.closed_at) {
+        (Some(opened), Some(closed)) if closed > opened => (EventTime::Local(opened), EventTime::Local(closed)),
+        _ => {
+            let date = week_date(s.semester_start, s.weekday, s.lag_week, s.week);
+            (EventTime::Date(date), EventTime::Date(date + Duration::days(1)))
+        }
+    };
+    Event {
+        uid: format!("lab-{}-{}@laboxide", s.subcourse_id, s.schedule_id),
+        summary: format!("{}: {}", s.course_name, s.schedule_name),
+        location: s.room.clone(),
+        description: format!("Week {}, {}", s.week, s.tea_name),
+        start,
+        end,
+        rrule: None,
+        exdates: Vec::new(),
+        recurrence_id: None,
+        tentative: false,
+    }
+}
+
+fn room_names(rooms: Vec<MeetingRoom>) -> HashMap<i64, String> {
+    rooms.into_iter().filter_map(|r| r.id.map(|id| (id, r.room))).collect()
+}
+
+fn ics(name: &str, events: &[Event]) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(ical::calendar(name, events))
+}
+
+async fn token_user(db_pool: &SqlitePool, token: &str) -> Result<String, HttpResponse> {
+    match db::get_calendar_user(db_pool, token).await {
+        Ok(Some(user_id)) => Ok(user_id),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "error": "Unknown calendar feed" }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+// The user's meeting bookings and the labs they teach or attend
+#[get("/calendar/{token}/personal.ics")]
+pub async fn personal_feed(db_pool: web::Data<SqlitePool>, path: web::Path<String>) -> impl Responder {
+    let user_id = match token_user(&db_pool, &path.into_inner()).await {
+        Ok(user_id) => user_id,
+        Err(err) => return err,
+    };
+    let rooms = match db::list_meeting_rooms(&db_pool).await {
+        Ok(rooms) => room_names(rooms),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let agendas = match db::list_user_meeting_agendas(&db_pool, &user_id).await {
+        Ok(agendas) => agendas,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let exceptions = match db::list_user_meeting_exceptions(&db_pool, &user_id).await {
+        Ok(exceptions) => exceptions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let sessions = match db::list_calendar_sessions(&db_pool, &user_id).await {
+        Ok(sessions) => sessions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let events: Vec<Event> = agendas
+        .iter()
+        .flat_map(|a| meeting_events(a, &exceptions, &rooms))
+        .chain(sessions.iter().map(session_event))
+        .collect();
+    ics(&format!("Laboxide {}", user_id), &events)
+}
+
+#[get("/calendar/{token}/room/{id}.ics")]
+pub async fn room_feed(db_pool: web::Data<SqlitePool>, path: web::Path<(String, i64)>) -> impl Responder {
+    let (token, room_id) = path.into_inner();
+    if let Err(err) = token_user(&db_pool, &token).await {
+        return err;
+    }
+    let rooms = match db::list_meeting_rooms(&db_pool).await {
+        Ok(rooms) => room_names(rooms),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let Some(name) = rooms.get(&room_id).cloned() else {
+        return HttpResponse::NotFound().json(json!({ "error": "Meeting room not found" }));
+    };
+    let exceptions = match db::list_room_meeting_exceptions(&db_pool, room_id).await {
+        Ok(exceptions) => exceptions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::list_meeting_agendas(&db_pool, room_id).await {
+        Ok(agendas) => {
+            let events: Vec<Event> = agendas.iter().flat_map(|a| meeting_events(a, &exceptions, &rooms)).collect();
+            ics(&name, &events)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Feeds authenticate by token alone so calendar apps can subscribe without a session
+pub fn init_calendar_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_calendar_feeds)
+        .service(reset_calendar_feeds)
+        .service(personal_feed)
+        .service(room_feed);
+}
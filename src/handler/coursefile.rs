@@ -1,30 +1,80 @@
 use actix_multipart::Multipart;
-use actix_web::{get, post, web, delete, HttpResponse, Responder, HttpRequest};
+use actix_web::{get, post, put, web, delete, HttpResponse, Responder, HttpRequest};
 use actix_session::Session;
 use actix_files::NamedFile;
 use futures_util::TryStreamExt;
 use serde_json::json;
 use sqlx::SqlitePool;
-use std::fs::{File, metadata};
-use std::io::Write;
-use chrono::{DateTime, Utc};
-use serde::Serialize;
+use std::fs::metadata;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-use crate::utils::check_course_perm;
+use crate::config::{Config, PERMISSION_STUDENT, PERMISSION_TEACHER};
+use crate::utils::{attachment, check_course_perm, check_quota, check_scan, serve_stored_file, upload_error};
 use crate::db;
+use crate::models::{CourseFile, VISIBILITY_ENROLLED, VISIBILITY_PUBLIC, VISIBILITY_TEACHERS};
+use crate::scanner::Scanner;
+use crate::storage::{self, Storage};
+
+// What the current session may see of one course's files
+struct Viewer {
+    course_teacher: bool,
+    enrolled: bool,
+}
+
+async fn viewer_for(db_pool: &web::Data<SqlitePool>, session: &Session, course_id: i64) -> Viewer {
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let course_teacher = check_course_perm(db_pool, session, course_id).await.is_ok()
+        || (permission & PERMISSION_TEACHER != 0
+            && db::teaches_course(db_pool, &user_id, course_id).await.unwrap_or(false));
+    let enrolled = permission & PERMISSION_STUDENT != 0
+        && db::is_enrolled_in_course(db_pool, &user_id, course_id).await.unwrap_or(false);
+    Viewer { course_teacher, enrolled }
+}
+
+// Course teachers see everything; others are limited by the file's visibility and release date
+fn can_access(viewer: &Viewer, file: &CourseFile) -> bool {
+    if viewer.course_teacher {
+        return true;
+    }
+    let now = Local::now().naive_local();
+    file.visible_from.is_none_or(|from| from <= now)
+        && match file.visibility {
+            VISIBILITY_PUBLIC => true,
+            VISIBILITY_ENROLLED => viewer.enrolled,
+            _ => false,
+        }
+}
+
+fn valid_visibility(visibility: i64) -> bool {
+    matches!(visibility, VISIBILITY_PUBLIC | VISIBILITY_ENROLLED | VISIBILITY_TEACHERS)
+}
+
+async fn check_folder(db_pool: &SqlitePool, folder_id: Option<i64>, course_id: i64) -> Result<(), HttpResponse> {
+    let Some(folder_id) = folder_id else { return Ok(()) };
+    match db::get_course_folder_by_id(db_pool, folder_id).await {
+        Ok(folder) if folder.course_id == course_id => Ok(()),
+        Ok(_) => Err(HttpResponse::BadRequest().json(json!({ "error": "Folder belongs to another course" }))),
+        Err(_) => Err(HttpResponse::NotFound().json(json!({ "error": "Folder not found" }))),
+    }
+}
 
 #[post("/coursefile/upload")]
 pub async fn upload_course_file(
     db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    scanner: web::Data<dyn Scanner>,
+    config: web::Data<Config>,
     mut payload: Multipart,
     session: Session,
 ) -> impl Responder {
-    use std::fs;
-
-    let mut fname = None;
     let mut finfo = None;
     let mut course_id = None;
-    let mut file_bytes = vec![];
+    let mut folder_id = None;
+    let mut visible_from = None;
+    let mut visibility = None;
+    let mut upload = None;
 
     while let Ok(Some(mut field)) = payload.try_next().await {
         let content_disposition = field.content_disposition();
@@ -32,69 +82,184 @@ pub async fn upload_course_file(
 
         if name == "file" {
             let original_filename = content_disposition.get_filename().unwrap_or("unnamed").to_string();
-            fname = Some(original_filename.clone());
-
-            while let Some(chunk) = field.try_next().await.unwrap() {
-                file_bytes.extend_from_slice(&chunk);
+            match storage::receive_upload(&mut field, &config.upload_tmp, &original_filename, config.upload_limit_coursefile).await {
+                Ok(received) => upload = Some(received),
+                Err(e) => return upload_error(e),
             }
         } else if name == "finfo" {
-            let data = field.try_next().await.unwrap().unwrap();
-            finfo = Some(String::from_utf8_lossy(&data).to_string());
+            if let Ok(Some(data)) = field.try_next().await {
+                finfo = Some(String::from_utf8_lossy(&data).to_string());
+            }
         } else if name == "course_id" {
-            let data = field.try_next().await.unwrap().unwrap();
-            course_id = Some(String::from_utf8_lossy(&data).parse::<i64>().unwrap());
+            if let Ok(Some(data)) = field.try_next().await {
+                course_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+            }
+        } else if name == "folder_id" {
+            if let Ok(Some(data)) = field.try_next().await {
+                folder_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+            }
+        } else if name == "visibility" {
+            if let Ok(Some(data)) = field.try_next().await {
+                visibility = String::from_utf8_lossy(&data).parse::<i64>().ok();
+            }
+        } else if name == "visible_from" {
+            if let Ok(Some(data)) = field.try_next().await {
+                visible_from = String::from_utf8_lossy(&data).parse::<NaiveDateTime>().ok();
+            }
         }
     }
 
-    if let Some(cid) = course_id {
-        if let Err(err) = check_course_perm(&db_pool, &session, cid).await {
-            return err;
-        }
+    let (upload, course_id) = match (upload, course_id) {
+        (Some(upload), Some(course_id)) => (upload, course_id),
+        _ => return HttpResponse::BadRequest().json(json!({ "error": "Missing required fields" })),
+    };
+    if let Err(err) = check_course_perm(&db_pool, &session, course_id).await {
+        return err;
+    }
+    if let Err(err) = check_folder(&db_pool, folder_id, course_id).await {
+        return err;
+    }
+    if visibility.is_some_and(|v| !valid_visibility(v)) {
+        return HttpResponse::BadRequest().json(json!({ "error": "Invalid visibility" }));
+    }
+    let existing = match db::find_course_file(&db_pool, course_id, folder_id, &upload.fname).await {
+        Ok(existing) => existing,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if existing.is_none() && finfo.is_none() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Missing required fields" }));
     }
 
-    match (fname, finfo, course_id) {
-        (Some(fname), Some(finfo), Some(course_id)) => {
-            // Save file to ./uploads/courses/<course_id>/<fname>
-            let upload_dir = format!("uploads/courses/{}", course_id);
-            fs::create_dir_all(&upload_dir).unwrap();
-
-            let file_path = format!("{}/{}", upload_dir, fname);
-            let mut f = File::create(&file_path).unwrap();
-            f.write_all(&file_bytes).unwrap();
-
-            // Save metadata to DB
-            match db::add_course_file(&db_pool, &fname, &finfo, course_id).await {
-                Ok(record) => HttpResponse::Ok().json(record),
-                Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    if let Err(err) = check_quota(&db_pool, &config, &user_id, &upload).await {
+        return err;
+    }
+    let stored = match storage::store_upload(storage.as_ref(), scanner.as_ref(), &db_pool, upload, &user_id).await {
+        Ok(stored) => stored,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_scan(&stored) {
+        return *err;
+    }
+    let result = match existing {
+        // Same name in the same folder: a new version of that handout
+        Some(existing) => db::add_course_file_version(&db_pool, existing.id, stored.id, &user_id).await,
+        None => {
+            let finfo = finfo.unwrap_or_default();
+            match db::add_course_file(&db_pool, &stored.fname, &finfo, course_id, folder_id, stored.id, &user_id).await {
+                Ok(file) if visible_from.is_some() || visibility.is_some() => {
+                    let visibility = visibility.unwrap_or(file.visibility);
+                    db::update_course_file(&db_pool, file.id, &file.finfo, file.folder_id, visible_from, visibility).await
+                }
+                other => other,
             }
         }
-        _ => HttpResponse::BadRequest().json(json!({ "error": "Missing required fields" })),
+    };
+    match result {
+        Ok(record) => HttpResponse::Ok().json(record),
+        Err(e) => {
+            let _ = storage::release_file(storage.as_ref(), &db_pool, stored.id).await;
+            HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))
+        }
     }
 }
 
 #[get("/coursefile/download/{id}")]
 pub async fn download_course_file(
     db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<i64>,
+    session: Session,
     req: HttpRequest,
 ) -> impl Responder {
     let id = path.into_inner();
 
+    let file = match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => file,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if !can_access(&viewer_for(&db_pool, &session, file.course_id).await, &file) {
+        return HttpResponse::Forbidden().json(json!({ "error": "You do not have access to this file" }));
+    }
+    serve_course_file(&db_pool, storage.as_ref(), file, &req).await
+}
+
+async fn serve_course_file(
+    db_pool: &SqlitePool,
+    storage: &dyn Storage,
+    file: CourseFile,
+    req: &HttpRequest,
+) -> HttpResponse {
+    if let Some(file_id) = file.file_id {
+        return serve_stored_file(req, storage, db_pool, file_id).await;
+    }
+    let Some(file_path) = storage::legacy_course_path(file.course_id, &file.fname) else {
+        return HttpResponse::BadRequest().json(json!({ "error": "Invalid file name" }));
+    };
+    match NamedFile::open_async(file_path).await {
+        Ok(named_file) => named_file
+            .set_content_disposition(attachment(&file.fname))
+            .into_response(req),
+        Err(_) => HttpResponse::NotFound().json(json!({ "error": "File not found on disk" })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ShareLinkQuery {
+    pub expires_in: Option<i64>, // seconds, default one day
+}
+
+const MAX_LINK_SECONDS: i64 = 30 * 24 * 3600;
+
+#[get("/coursefile/{id}/link")]
+pub async fn create_share_link(
+    db_pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+    path: web::Path<i64>,
+    query: web::Query<ShareLinkQuery>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
     match db::get_course_file_by_id(&db_pool, id).await {
-        Ok(course_file) => {
-            let file_path = format!("uploads/courses/{}/{}", course_file.course_id, course_file.fname);
-            match NamedFile::open_async(file_path).await {
-                Ok(named_file) => named_file
-                    .set_content_disposition(actix_web::http::header::ContentDisposition {
-                        disposition: actix_web::http::header::DispositionType::Attachment,
-                        parameters: vec![actix_web::http::header::DispositionParam::Filename(
-                            course_file.fname.clone(),
-                        )],
-                    })
-                    .into_response(&req),
-                Err(_) => HttpResponse::NotFound().json(json!({ "error": "File not found on disk" })),
+        Ok(file) => {
+            if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
+                return err;
             }
         }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    let expires_in = query.expires_in.unwrap_or(24 * 3600).clamp(60, MAX_LINK_SECONDS);
+    let expires = Utc::now().timestamp() + expires_in;
+    let sig = storage::sign_link(&config.secret, id, expires);
+    HttpResponse::Ok().json(json!({
+        "url": format!("/coursefile/shared/{}?expires={}&sig={}", id, expires, sig),
+        "expires": expires,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SharedFileQuery {
+    pub expires: i64,
+    pub sig: String,
+}
+
+// Needs no session: the signature and expiry are the credential
+#[get("/coursefile/shared/{id}")]
+pub async fn download_shared_file(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    config: web::Data<Config>,
+    path: web::Path<i64>,
+    query: web::Query<SharedFileQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let id = path.into_inner();
+    if !storage::verify_link(&config.secret, id, query.expires, &query.sig) {
+        return HttpResponse::Forbidden().json(json!({ "error": "Link is invalid or has expired" }));
+    }
+    match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => serve_course_file(&db_pool, storage.as_ref(), file, &req).await,
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(json!({ "error": "File not found" })),
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -105,59 +270,237 @@ pub struct CourseFileResponse {
     pub fname: String,
     pub finfo: String,
     pub course_id: i64,
+    pub folder_id: Option<i64>,
+    pub version: i64,
+    pub visible_from: Option<NaiveDateTime>,
+    pub visibility: i64,
+    pub schedule_ids: Vec<i64>,
     pub modified_time: Option<String>,
 }
 
+async fn file_responses(db_pool: &SqlitePool, files: Vec<CourseFile>) -> Vec<CourseFileResponse> {
+    let mut response_list = Vec::new();
+
+    for file_record in files {
+        let file_path = storage::legacy_course_path(file_record.course_id, &file_record.fname);
+
+        // Stored files carry their upload time, legacy ones use the disk mtime
+        let stored = match file_record.file_id {
+            Some(file_id) => db::get_stored_file_by_id(db_pool, file_id).await.ok(),
+            None => None,
+        };
+        let modified_time = match (stored, file_path.map(metadata)) {
+            (Some(stored), _) => Some(stored.created_at.and_utc().to_rfc3339()),
+            (None, Some(Ok(metadata))) => {
+                // If metadata is found, get the modified time
+                metadata.modified().map_or(None, |sys_time| {
+                    // Convert SystemTime to a chrono DateTime object
+                    let datetime: DateTime<Utc> = sys_time.into();
+                    // Format it as an ISO 8601 string
+                    Some(datetime.to_rfc3339())
+                })
+            }
+            (None, _) => None, // If metadata fails (e.g., file not found), return None
+        };
+        let schedule_ids = db::list_course_file_schedules(db_pool, file_record.id).await.unwrap_or_default();
+
+        response_list.push(CourseFileResponse {
+            id: file_record.id,
+            fname: file_record.fname,
+            finfo: file_record.finfo,
+            course_id: file_record.course_id,
+            folder_id: file_record.folder_id,
+            version: file_record.version,
+            visible_from: file_record.visible_from,
+            visibility: file_record.visibility,
+            schedule_ids,
+            modified_time,
+        });
+    }
+    response_list
+}
+
 #[get("/coursefile/list/{id}")]
 pub async fn list_course_files(
     db_pool: web::Data<SqlitePool>,
     path: web::Path<i64>,
+    session: Session,
 ) -> impl Responder {
     let course_id = path.into_inner();
+    let viewer = viewer_for(&db_pool, &session, course_id).await;
     match db::list_course_files(&db_pool, course_id).await {
-        Ok(files_from_db) => {
-            let mut response_list = Vec::new();
-
-            // Iterate over the files retrieved from the database
-            for file_record in files_from_db {
-                let file_path = format!(
-                    "uploads/courses/{}/{}",
-                    file_record.course_id, file_record.fname
-                );
-
-                // Try to get file metadata and its modified time
-                let modified_time = match metadata(&file_path) {
-                    Ok(metadata) => {
-                        // If metadata is found, get the modified time
-                        metadata.modified().map_or(None, |sys_time| {
-                            // Convert SystemTime to a chrono DateTime object
-                            let datetime: DateTime<Utc> = sys_time.into();
-                            // Format it as an ISO 8601 string
-                            Some(datetime.to_rfc3339())
-                        })
-                    }
-                    Err(_) => None, // If metadata fails (e.g., file not found), return None
-                };
-
-                // Build the new response object
-                response_list.push(CourseFileResponse {
-                    id: file_record.id,
-                    fname: file_record.fname,
-                    finfo: file_record.finfo,
-                    course_id: file_record.course_id,
-                    modified_time, // Add the modified time here
-                });
-            }
-
-            HttpResponse::Ok().json(response_list)
+        Ok(files) => {
+            let files = files.into_iter().filter(|f| can_access(&viewer, f)).collect();
+            HttpResponse::Ok().json(file_responses(&db_pool, files).await)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Handouts attached to one week of the course schedule
+#[get("/coursefile/schedule/{schedule_id}")]
+pub async fn list_schedule_files(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let schedule_id = path.into_inner();
+    let viewer = match db::get_schedule_by_id(&db_pool, schedule_id).await {
+        Ok(schedule) => viewer_for(&db_pool, &session, schedule.course_id).await,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::list_course_files_by_schedule(&db_pool, schedule_id).await {
+        Ok(files) => {
+            let files = files.into_iter().filter(|f| can_access(&viewer, f)).collect();
+            HttpResponse::Ok().json(file_responses(&db_pool, files).await)
         }
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
 
+#[derive(Deserialize)]
+pub struct UpdateCourseFileRequest {
+    pub finfo: String,
+    pub folder_id: Option<i64>,
+    pub visible_from: Option<NaiveDateTime>,
+    pub visibility: Option<i64>, // Unchanged when omitted
+}
+
+#[put("/coursefile/{id}")]
+pub async fn update_course_file(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<UpdateCourseFileRequest>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let file = match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => file,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
+        return err;
+    }
+    if let Err(err) = check_folder(&db_pool, item.folder_id, file.course_id).await {
+        return err;
+    }
+    let visibility = item.visibility.unwrap_or(file.visibility);
+    if !valid_visibility(visibility) {
+        return HttpResponse::BadRequest().json(json!({ "error": "Invalid visibility" }));
+    }
+    match db::update_course_file(&db_pool, id, &item.finfo, item.folder_id, item.visible_from, visibility).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/coursefile/{id}/versions")]
+pub async fn list_course_file_versions(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => {
+            if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::list_course_file_versions(&db_pool, id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/coursefile/{id}/version/{version}")]
+pub async fn download_course_file_version(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(i64, i64)>,
+    req: HttpRequest,
+    session: Session,
+) -> impl Responder {
+    let (id, version) = path.into_inner();
+    match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => {
+            if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::get_course_file_version(&db_pool, id, version).await {
+        Ok(rec) => serve_stored_file(&req, storage.as_ref(), &db_pool, rec.file_id).await,
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(json!({ "error": "Version not found" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/coursefile/{id}/rollback/{version}")]
+pub async fn rollback_course_file(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<(i64, i64)>,
+    session: Session,
+) -> impl Responder {
+    let (id, version) = path.into_inner();
+    match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => {
+            if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    let version = match db::get_course_file_version(&db_pool, id, version).await {
+        Ok(rec) => rec,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Version not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::set_course_file_version(&db_pool, id, &version).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CourseFileSchedulesRequest {
+    pub schedule_ids: Vec<i64>,
+}
+
+#[put("/coursefile/{id}/schedules")]
+pub async fn set_course_file_schedules(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<CourseFileSchedulesRequest>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let file = match db::get_course_file_by_id(&db_pool, id).await {
+        Ok(file) => file,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
+        return err;
+    }
+    for schedule_id in &item.schedule_ids {
+        match db::get_schedule_by_id(&db_pool, *schedule_id).await {
+            Ok(schedule) if schedule.course_id == file.course_id => {}
+            _ => return HttpResponse::BadRequest().json(json!({ "error": format!("Invalid schedule {}", schedule_id) })),
+        }
+    }
+    match db::set_course_file_schedules(&db_pool, id, &item.schedule_ids).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "schedule_ids": item.schedule_ids })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
 #[delete("/coursefile/{id}")]
 pub async fn delete_course_file(
     db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<i64>,
     session: Session,
 ) -> impl Responder {
@@ -168,8 +511,18 @@ pub async fn delete_course_file(
             if let Err(err) = check_course_perm(&db_pool, &session, file.course_id).await {
                 return err;
             }
-            let file_path = format!("uploads/courses/{}/{}", file.course_id, file.fname);
-            let _ = std::fs::remove_file(&file_path); // Ignore error if file doesn't exist
+            let versions = match db::list_course_file_versions(&db_pool, id).await {
+                Ok(versions) => versions,
+                Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+            };
+            for version in versions {
+                let _ = storage::release_file(storage.as_ref(), &db_pool, version.file_id).await;
+            }
+            if file.file_id.is_none() {
+                if let Some(file_path) = storage::legacy_course_path(file.course_id, &file.fname) {
+                    let _ = std::fs::remove_file(&file_path); // Ignore error if file doesn't exist
+                }
+            }
 
             match db::delete_course_file(&db_pool, id).await {
                 Ok(true) => HttpResponse::Ok().json(json!({ "message": "Course file deleted" })),
@@ -181,7 +534,118 @@ pub async fn delete_course_file(
     }
 }
 
+#[derive(Deserialize)]
+pub struct CourseFolderRequest {
+    pub course_id: i64,
+    pub parent_id: Option<i64>,
+    pub name: String,
+}
+
+#[post("/coursefolder")]
+pub async fn create_course_folder(
+    db_pool: web::Data<SqlitePool>,
+    item: web::Json<CourseFolderRequest>,
+    session: Session,
+) -> impl Responder {
+    if let Err(err) = check_course_perm(&db_pool, &session, item.course_id).await {
+        return err;
+    }
+    if let Err(err) = check_folder(&db_pool, item.parent_id, item.course_id).await {
+        return err;
+    }
+    let name = item.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Folder name is required" }));
+    }
+    match db::add_course_folder(&db_pool, item.course_id, item.parent_id, name).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/coursefolder/{id}")]
+pub async fn update_course_folder(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<CourseFolderRequest>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let folder = match db::get_course_folder_by_id(&db_pool, id).await {
+        Ok(folder) => folder,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_course_perm(&db_pool, &session, folder.course_id).await {
+        return err;
+    }
+    if let Err(err) = check_folder(&db_pool, item.parent_id, folder.course_id).await {
+        return err;
+    }
+    // Walk up from the new parent so a folder is never moved into itself
+    let mut ancestor = item.parent_id;
+    while let Some(ancestor_id) = ancestor {
+        if ancestor_id == id {
+            return HttpResponse::BadRequest().json(json!({ "error": "Cannot move a folder into itself" }));
+        }
+        ancestor = match db::get_course_folder_by_id(&db_pool, ancestor_id).await {
+            Ok(parent) => parent.parent_id,
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        };
+    }
+    let name = item.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Folder name is required" }));
+    }
+    match db::update_course_folder(&db_pool, id, item.parent_id, name).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/coursefolder/{id}")]
+pub async fn delete_course_folder(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    match db::get_course_folder_by_id(&db_pool, id).await {
+        Ok(folder) => {
+            if let Err(err) = check_course_perm(&db_pool, &session, folder.course_id).await {
+                return err;
+            }
+        }
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::delete_course_folder(&db_pool, id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Folder deleted" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Folder not found" })),
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::BadRequest().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/coursefolder/list/{course_id}")]
+pub async fn list_course_folders(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    match db::list_course_folders(&db_pool, path.into_inner()).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
 pub fn init_course_file_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(upload_course_file)
-        .service(delete_course_file);
+        .service(update_course_file)
+        .service(list_course_file_versions)
+        .service(download_course_file_version)
+        .service(rollback_course_file)
+        .service(set_course_file_schedules)
+        .service(create_share_link)
+        .service(delete_course_file)
+        .service(create_course_folder)
+        .service(update_course_folder)
+        .service(delete_course_folder);
 }
@@ -0,0 +1,327 @@
+use actix_session::Session;
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::config::{PERMISSION_ADMIN, PERMISSION_LAB_MANAGER};
+use crate::db;
+use crate::handler::equipment::check_equip_perm;
+use crate::models::{Equipment, TRANSFER_ACCEPTED, TRANSFER_CANCELLED, TRANSFER_DECLINED};
+
+#[derive(Deserialize)]
+pub struct TransferRequest {
+    pub to_id: String,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub force: bool, // Lab managers and admins may reassign without waiting for acceptance
+}
+
+#[derive(Deserialize)]
+pub struct HandoverRequest {
+    pub from_id: String,
+    pub to_id: String,
+    #[serde(default)]
+    pub note: String,
+}
+
+#[derive(Deserialize)]
+pub struct CustodianRequest {
+    pub user_id: String,
+}
+
+// Who may give an item away or change its custodians: the owner, or as an
+// override the manager of the item's lab room or an admin. Returns whether
+// the caller is acting as an override.
+async fn check_custody_perm(
+    db_pool: &SqlitePool,
+    session: &Session,
+    item_id: i64,
+) -> Result<(Equipment, bool), HttpResponse> {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let equip = match db::get_equipment_by_id(db_pool, item_id).await {
+        Ok(equip) => equip,
+        Err(sqlx::Error::RowNotFound) => return Err(HttpResponse::NotFound().json(json!({ "error": "Equipment not found" }))),
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    };
+    if equip.owner_id == user_id {
+        return Ok((equip, false));
+    }
+    if permission & PERMISSION_ADMIN != 0 {
+        return Ok((equip, true));
+    }
+    if permission & PERMISSION_LAB_MANAGER != 0 {
+        match db::manages_equipment_room(db_pool, &equip.position, &user_id).await {
+            Ok(true) => return Ok((equip, true)),
+            Ok(false) => {}
+            Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+        }
+    }
+    Err(HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" })))
+}
+
+async fn check_user_exists(db_pool: &SqlitePool, user_id: &str) -> Result<(), HttpResponse> {
+    match db::get_user_by_id(db_pool, user_id).await {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::BadRequest().json(json!({ "error": format!("Unknown user {}", user_id) }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[post("/equipment/{id}/transfer")]
+pub async fn transfer_equipment(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<TransferRequest>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let (equip, is_override) = match check_custody_perm(&db_pool, &session, path.into_inner()).await {
+        Ok(found) => found,
+        Err(err) => return err,
+    };
+    let to_id = body.to_id.trim();
+    if to_id == equip.owner_id {
+        return HttpResponse::BadRequest().json(json!({ "error": "The recipient already owns this item" }));
+    }
+    if let Err(err) = check_user_exists(&db_pool, to_id).await {
+        return err;
+    }
+
+    if body.force {
+        if !is_override {
+            return HttpResponse::Unauthorized().json(json!({ "error": "Only lab managers and admins can reassign directly" }));
+        }
+        return match db::reassign_equipment(&db_pool, equip.id, to_id, &user_id, body.note.trim()).await {
+            Ok(updated) => {
+                let message = format!("{} ({}) has been reassigned to you", updated.name, updated.serial);
+                let _ = db::add_notification(&db_pool, to_id, "equipment_transfer", &message, "/equipment/transfers").await;
+                let message = format!("{} ({}) has been reassigned to {}", updated.name, updated.serial, to_id);
+                let _ = db::add_notification(&db_pool, &equip.owner_id, "equipment_transfer", &message, "/equipment/transfers").await;
+                HttpResponse::Ok().json(updated)
+            }
+            Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        };
+    }
+
+    match db::add_equipment_transfer(&db_pool, equip.id, &equip.owner_id, to_id, body.note.trim(), &user_id).await {
+        Ok(transfer) => {
+            let message = format!("{} offers you {} ({})", equip.owner_id, equip.name, equip.serial);
+            let _ = db::add_notification(&db_pool, to_id, "equipment_transfer", &message, "/equipment/transfers").await;
+            HttpResponse::Created().json(transfer)
+        }
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Offers everything a departing teacher owns to a successor; lab managers
+// can only hand over the items in rooms they manage
+#[post("/equipment/handover")]
+pub async fn handover_equipment(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    body: web::Json<HandoverRequest>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    if permission & (PERMISSION_ADMIN | PERMISSION_LAB_MANAGER) == 0 {
+        return HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }));
+    }
+    let (from_id, to_id) = (body.from_id.trim(), body.to_id.trim());
+    if from_id == to_id {
+        return HttpResponse::BadRequest().json(json!({ "error": "The recipient already owns these items" }));
+    }
+    if let Err(err) = check_user_exists(&db_pool, to_id).await {
+        return err;
+    }
+    let items = match db::list_inventory(&db_pool, Some(from_id)).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+
+    let (mut offered, mut pending, mut skipped) = (Vec::new(), Vec::new(), Vec::new());
+    for item in items {
+        if permission & PERMISSION_ADMIN == 0 {
+            match db::manages_equipment_room(&db_pool, &item.position, &user_id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    skipped.push(item.id);
+                    continue;
+                }
+                Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+            }
+        }
+        match db::add_equipment_transfer(&db_pool, item.id, from_id, to_id, body.note.trim(), &user_id).await {
+            Ok(transfer) => offered.push(transfer),
+            Err(sqlx::Error::Protocol(_)) => pending.push(item.id),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        }
+    }
+    if !offered.is_empty() {
+        let message = format!("{} items from {} are waiting for you to accept", offered.len(), from_id);
+        let _ = db::add_notification(&db_pool, to_id, "equipment_transfer", &message, "/equipment/transfers").await;
+    }
+    HttpResponse::Ok().json(json!({
+        "offered": offered,
+        "already_pending": pending,
+        "not_managed": skipped,
+    }))
+}
+
+#[get("/equipment/transfers")]
+pub async fn list_my_transfers(db_pool: web::Data<SqlitePool>, session: Session) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::list_pending_transfers(&db_pool, &user_id).await {
+        Ok(transfers) => {
+            let (incoming, outgoing): (Vec<_>, Vec<_>) = transfers.into_iter().partition(|t| t.to_id == user_id);
+            HttpResponse::Ok().json(json!({ "incoming": incoming, "outgoing": outgoing }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+async fn decide_transfer(db_pool: &SqlitePool, session: &Session, id: i64, state: i64) -> HttpResponse {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let transfer = match db::get_equipment_transfer(db_pool, id).await {
+        Ok(t) => t,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Transfer not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    // The recipient accepts or declines; whoever offered it, or an admin, may withdraw it
+    let allowed = match state {
+        TRANSFER_CANCELLED => {
+            transfer.from_id == user_id || transfer.created_by == user_id || permission & PERMISSION_ADMIN != 0
+        }
+        _ => transfer.to_id == user_id,
+    };
+    if !allowed {
+        return HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }));
+    }
+    match db::decide_equipment_transfer(db_pool, id, state, &user_id).await {
+        Ok(transfer) => {
+            let verb = match state {
+                TRANSFER_ACCEPTED => "accepted",
+                TRANSFER_DECLINED => "declined",
+                _ => "cancelled",
+            };
+            let notify = if state == TRANSFER_CANCELLED { &transfer.to_id } else { &transfer.from_id };
+            let message = format!("The transfer of equipment {} to {} was {}", transfer.item_id, transfer.to_id, verb);
+            let link = format!("/equipment/{}/custody", transfer.item_id);
+            let _ = db::add_notification(db_pool, notify, "equipment_transfer", &message, &link).await;
+            HttpResponse::Ok().json(transfer)
+        }
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/equipment/transfer/{id}/accept")]
+pub async fn accept_transfer(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    decide_transfer(&db_pool, &session, path.into_inner(), TRANSFER_ACCEPTED).await
+}
+
+#[put("/equipment/transfer/{id}/decline")]
+pub async fn decline_transfer(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    decide_transfer(&db_pool, &session, path.into_inner(), TRANSFER_DECLINED).await
+}
+
+#[put("/equipment/transfer/{id}/cancel")]
+pub async fn cancel_transfer(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    decide_transfer(&db_pool, &session, path.into_inner(), TRANSFER_CANCELLED).await
+}
+
+#[get("/equipment/{id}/custodians")]
+pub async fn list_custodians(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = check_equip_perm(&db_pool, &session, id).await {
+        return e;
+    }
+    match db::list_equipment_custodians(&db_pool, id).await {
+        Ok(custodians) => HttpResponse::Ok().json(custodians),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[post("/equipment/{id}/custodians")]
+pub async fn add_custodian(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<CustodianRequest>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let (equip, _) = match check_custody_perm(&db_pool, &session, path.into_inner()).await {
+        Ok(found) => found,
+        Err(err) => return err,
+    };
+    let custodian = body.user_id.trim();
+    if custodian == equip.owner_id {
+        return HttpResponse::BadRequest().json(json!({ "error": "The owner is already responsible for this item" }));
+    }
+    if let Err(err) = check_user_exists(&db_pool, custodian).await {
+        return err;
+    }
+    match db::add_equipment_custodian(&db_pool, equip.id, custodian, &user_id).await {
+        Ok(rec) => {
+            let message = format!("You are now a custodian of {} ({})", equip.name, equip.serial);
+            let link = format!("/equipment/{}", equip.id);
+            let _ = db::add_notification(&db_pool, custodian, "equipment_custody", &message, &link).await;
+            HttpResponse::Created().json(rec)
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().json(json!({ "error": "Already a custodian of this item" }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/equipment/{id}/custodians/{user_id}")]
+pub async fn remove_custodian(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<(i64, String)>,
+) -> impl Responder {
+    let (id, custodian) = path.into_inner();
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    // Custodians may step down themselves
+    if custodian != user_id {
+        if let Err(err) = check_custody_perm(&db_pool, &session, id).await {
+            return err;
+        }
+    }
+    match db::remove_equipment_custodian(&db_pool, id, &custodian, &user_id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Custodian removed" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Not a custodian of this item" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/equipment/{id}/custody")]
+pub async fn get_custody_history(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = check_equip_perm(&db_pool, &session, id).await {
+        return e;
+    }
+    match db::list_custody_logs(&db_pool, id).await {
+        Ok(logs) => HttpResponse::Ok().json(logs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Registered ahead of the equipment routes so /equipment/transfers is not taken for an id
+pub fn init_custody_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_my_transfers)
+        .service(handover_equipment)
+        .service(transfer_equipment)
+        .service(accept_transfer)
+        .service(decline_transfer)
+        .service(cancel_transfer)
+        .service(list_custodians)
+        .service(add_custodian)
+        .service(remove_custodian)
+        .service(get_custody_history);
+}
@@ -3,7 +3,20 @@ use actix_web::{get, post, put, delete, web, HttpResponse, Responder};
 use serde_json::json;
 use sqlx::SqlitePool;
 use crate::db;
-use crate::models::{Equipment, EquipmentHistory};
+use crate::config::{Config, PERMISSION_ADMIN, PERMISSION_LAB_MANAGER};
+use crate::models::{Equipment, EquipmentFilter, EquipmentHistory};
+use crate::models::{EQUIP_IN_SERVICE, EQUIP_RETIRED, EQUIP_UNDER_REPAIR};
+use crate::models::{LOAN_CANCELLED, LOAN_CHECKED_OUT, LOAN_LOST, LOAN_RESERVED, LOAN_RETURNED};
+use crate::labels::{self, Label, Symbology};
+use crate::spreadsheet::{self, Sheet};
+use crate::utils::attachment;
+use actix_multipart::Multipart;
+use chrono::NaiveDateTime;
+use futures_util::TryStreamExt;
+
+pub fn valid_status(status: i64) -> bool {
+    (EQUIP_IN_SERVICE..=EQUIP_RETIRED).contains(&status)
+}
 
 #[post("/equipment")]
 pub async fn create_equipment(
@@ -11,6 +24,9 @@ pub async fn create_equipment(
     item: web::Json<Equipment>,
 ) -> impl Responder {
     let equipment = item.into_inner();
+    if !valid_status(equipment.status) {
+        return HttpResponse::BadRequest().json(json!({ "error": format!("Invalid status {}", equipment.status) }));
+    }
 
     match db::add_equipment(&db_pool, equipment).await {
         Ok(equipment) => HttpResponse::Ok().json(equipment),
@@ -29,7 +45,7 @@ pub async fn list_equipments(
         _ => return HttpResponse::Unauthorized().json(json!({ "error": "Not logged in" })),
     };
 
-    let offset = (paging.page.unwrap_or(1) - 1).max(0) * paging.page_size.unwrap_or(10);
+    let offset = paging.page.unwrap_or(1).saturating_sub(1) * paging.page_size.unwrap_or(10);
     let limit = paging.page_size.unwrap_or(10);
 
     match db::list_equipments(&db_pool, &user_id, offset as i64, limit as i64).await {
@@ -38,6 +54,57 @@ pub async fn list_equipments(
     }
 }
 
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Each word becomes a quoted prefix term, so user input can never be FTS5 syntax
+fn fts_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .map(|t| t.replace('"', ""))
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("\"{}\"*", t))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+const SORT_COLUMNS: [&str; 6] = ["id", "name", "serial", "value", "position", "status"];
+
+// Teachers search their own items; lab managers and admins search the whole inventory
+#[get("/equipment/search")]
+pub async fn search_equipments(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    web::Query(mut filter): web::Query<EquipmentFilter>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    if permission & (PERMISSION_LAB_MANAGER | PERMISSION_ADMIN) == 0 {
+        filter.owner_id = Some(user_id);
+    }
+    let sort = filter.sort.clone().unwrap_or_else(|| "id".to_string());
+    if !SORT_COLUMNS.contains(&sort.as_str()) {
+        return HttpResponse::BadRequest().json(json!({ "error": format!("Cannot sort by {}", sort) }));
+    }
+    filter.name = filter.name.as_deref().map(escape_like);
+    filter.serial = filter.serial.as_deref().map(escape_like);
+    filter.position = filter.position.as_deref().map(escape_like);
+    let fts = filter.q.as_deref().and_then(fts_query);
+
+    let page = filter.page.unwrap_or(1).max(1);
+    let page_size = filter.page_size.unwrap_or(20).clamp(1, 100);
+    match db::search_equipments(&db_pool, &filter, fts.as_deref(), &sort, (page - 1).saturating_mul(page_size), page_size).await {
+        Ok((items, total)) => HttpResponse::Ok().json(json!({
+            "total": total,
+            "page": page,
+            "page_size": page_size,
+            "items": items,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
 #[get("/equipment/{id}")]
 pub async fn get_equipment(
     db_pool: web::Data<SqlitePool>,
@@ -50,20 +117,33 @@ pub async fn get_equipment(
     }
 }
 
+// The owner, a co-custodian, the manager of the lab room the item sits in, or an admin
 pub async fn check_equip_perm(
     db_pool: &web::Data<SqlitePool>,
     session: &Session,
     equip_id: i64,
 ) -> Result<(), HttpResponse> {
     let user: String = session.get::<String>("user_id").ok().flatten().unwrap_or("".to_string());
-    match db::get_equipment_by_id(db_pool, equip_id).await {
-        Ok(equip) => {
-            if equip.owner_id != user {
-                return Err(HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" })))
-            }
-            return Ok(())
-        },
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let equip = match db::get_equipment_by_id(db_pool, equip_id).await {
+        Ok(equip) => equip,
+        Err(sqlx::Error::RowNotFound) => return Err(HttpResponse::NotFound().json(json!({ "error": "Equipment not found" }))),
         Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    };
+    if equip.owner_id == user || permission & PERMISSION_ADMIN != 0 {
+        return Ok(());
+    }
+    let allowed = match db::is_equipment_custodian(db_pool, equip_id, &user).await {
+        Ok(true) => Ok(true),
+        Ok(false) if permission & PERMISSION_LAB_MANAGER != 0 => {
+            db::manages_equipment_room(db_pool, &equip.position, &user).await
+        }
+        other => other,
+    };
+    match allowed {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
     }
 }
 
@@ -80,6 +160,14 @@ pub async fn update_equipment(
     }
 
     let equipment = item.into_inner();
+    // Items still carrying a legacy status can be edited as long as it is left alone
+    let unchanged = match db::get_equipment_by_id(&db_pool, id).await {
+        Ok(current) => current.status == equipment.status,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if !unchanged && !valid_status(equipment.status) {
+        return HttpResponse::BadRequest().json(json!({ "error": format!("Invalid status {}", equipment.status) }));
+    }
     match db::update_equipment(&db_pool, id, equipment).await {
         Ok(equipment) => HttpResponse::Ok().json(equipment),
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
@@ -112,10 +200,14 @@ pub struct PaginationParams {
 
 #[derive(Debug, serde::Deserialize)]
 pub struct NewEquipmentHistory {
-    pub user: String,
+    pub borrower_id: String,
     pub telephone: String,
     pub note: String,
     pub item_id: i64,
+    pub due_date: NaiveDateTime,
+    #[serde(default)]
+    pub reserve: bool, // Hold the item for a later pickup instead of handing it out now
+    pub pickup_date: Option<NaiveDateTime>,
 }
 
 #[post("/equipment/history")]
@@ -128,19 +220,137 @@ pub async fn create_equipment_history(
     if let Err(e) = check_equip_perm(&db_pool, &session, new_item.item_id).await {
         return e;
     }
+    match db::get_equipment_by_id(&db_pool, new_item.item_id).await {
+        Ok(equip) if equip.status == EQUIP_UNDER_REPAIR || equip.status == EQUIP_RETIRED => {
+            return HttpResponse::Conflict().json(json!({ "error": "Item is under repair or retired" }))
+        }
+        Ok(_) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    let borrower = match db::get_user_by_id(&db_pool, &new_item.borrower_id).await {
+        Ok(user) => user,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Unknown borrower" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
     let now = chrono::Local::now().naive_local();
+    let (state, borrowed_date) = if new_item.reserve {
+        (LOAN_RESERVED, new_item.pickup_date.unwrap_or(now))
+    } else {
+        (LOAN_CHECKED_OUT, now)
+    };
+    if new_item.due_date <= borrowed_date {
+        return HttpResponse::BadRequest().json(json!({ "error": "Due date must be after pickup" }));
+    }
 
     let history = EquipmentHistory {
         id: 0, // will be ignored in insert
-        user: new_item.user,
-        borrowed_date: now,
+        user: borrower.username,
+        borrowed_date,
         telephone: new_item.telephone,
         note: new_item.note,
         returned_date: None,
         item_id: new_item.item_id,
+        borrower_id: Some(borrower.user_id),
+        state,
+        due_date: Some(new_item.due_date),
     };
     match db::add_equipment_history(&db_pool, history).await {
         Ok(history) => HttpResponse::Ok().json(history),
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().json(json!({ "error": "Item is already reserved or checked out" }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+async fn transition_loan(
+    db_pool: &web::Data<SqlitePool>,
+    session: &Session,
+    id: i64,
+    from: i64,
+    to: i64,
+) -> HttpResponse {
+    match db::get_equipment_history_by_id(db_pool, id).await {
+        Ok(history) => {
+            if let Err(e) = check_equip_perm(db_pool, session, history.item_id).await {
+                return e;
+            }
+        }
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "History not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    let now = chrono::Local::now().naive_local();
+    match db::transition_loan(db_pool, id, from, to, now).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(sqlx::Error::RowNotFound) => {
+            HttpResponse::Conflict().json(json!({ "error": "Loan is not in a state that allows this" }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/equipment/history/{id}/checkout")]
+pub async fn checkout_loan(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    transition_loan(&db_pool, &session, path.into_inner(), LOAN_RESERVED, LOAN_CHECKED_OUT).await
+}
+
+#[put("/equipment/history/{id}/return")]
+pub async fn return_loan(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    transition_loan(&db_pool, &session, path.into_inner(), LOAN_CHECKED_OUT, LOAN_RETURNED).await
+}
+
+#[put("/equipment/history/{id}/lost")]
+pub async fn mark_loan_lost(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    transition_loan(&db_pool, &session, path.into_inner(), LOAN_CHECKED_OUT, LOAN_LOST).await
+}
+
+#[put("/equipment/history/{id}/cancel")]
+pub async fn cancel_reservation(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    transition_loan(&db_pool, &session, path.into_inner(), LOAN_RESERVED, LOAN_CANCELLED).await
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DueDateRequest {
+    pub due_date: NaiveDateTime,
+}
+
+#[put("/equipment/history/{id}/due")]
+pub async fn extend_loan(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    item: web::Json<DueDateRequest>,
+) -> impl Responder {
+    let id = path.into_inner();
+    match db::get_equipment_history_by_id(&db_pool, id).await {
+        Ok(history) => {
+            if let Err(e) = check_equip_perm(&db_pool, &session, history.item_id).await {
+                return e;
+            }
+            if item.due_date <= history.borrowed_date {
+                return HttpResponse::BadRequest().json(json!({ "error": "Due date must be after pickup" }));
+            }
+        }
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "History not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::set_loan_due_date(&db_pool, id, item.due_date).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::Conflict().json(json!({ "error": "Loan is already closed" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/equipment/loans/overdue")]
+pub async fn list_overdue_loans(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let now = chrono::Local::now().naive_local();
+    match db::list_overdue_loans(&db_pool, &user_id, now).await {
+        Ok(list) => HttpResponse::Ok().json(list),
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -157,7 +367,7 @@ pub async fn get_equipment_history(
             if let Err(e) = check_equip_perm(&db_pool, &session, history.item_id).await {
                 return e
             }
-            return HttpResponse::Ok().json(history)
+            HttpResponse::Ok().json(history)
         },
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
@@ -192,6 +402,7 @@ pub async fn update_equipment_history(
     let now = chrono::Local::now().naive_local();
     match db::update_equipment_history(&db_pool, item_id, now).await {
         Ok(history) => HttpResponse::Ok().json(history),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(json!({ "error": "Item is not checked out" })),
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -215,8 +426,357 @@ pub async fn delete_equipment_history(
     }
 }
 
+const MAX_IMPORT_BYTES: usize = 10 << 20;
+const IMPORT_COLUMNS: [&str; 7] = ["name", "serial", "value", "position", "status", "note", "owner_id"];
+
+// Parsed rows and per-line errors, both keyed by spreadsheet line number
+type ParsedImport = (Vec<(usize, Equipment)>, Vec<(usize, String)>);
+
+// Spreadsheet rows to equipment; bad rows are reported by their 1-based row number
+fn parse_import(rows: &[Vec<String>], default_owner: &str) -> Result<ParsedImport, String> {
+    let (header, body) = rows.split_first().ok_or("The file is empty")?;
+    let idx = spreadsheet::header_index(header, &IMPORT_COLUMNS);
+    if idx[0].is_none() || idx[1].is_none() {
+        return Err("The header must contain name and serial columns".to_string());
+    }
+    let (mut parsed, mut errors) = (Vec::new(), Vec::new());
+    for (n, row) in body.iter().enumerate() {
+        let line = n + 2;
+        let cell = |i: usize| idx[i].and_then(|c| row.get(c)).map(|v| v.trim()).unwrap_or("");
+        let (name, serial) = (cell(0), cell(1));
+        if name.is_empty() || serial.is_empty() {
+            errors.push((line, "Missing name or serial".to_string()));
+            continue;
+        }
+        // Finance exports write values as decimals
+        let value = match cell(2) {
+            "" => 0,
+            v => match v.replace(',', "").parse::<f64>() {
+                Ok(v) => v.round() as i64,
+                Err(_) => {
+                    errors.push((line, format!("Invalid value {}", v)));
+                    continue;
+                }
+            },
+        };
+        let status = match cell(4).parse::<f64>() {
+            Ok(s) if valid_status(s as i64) => s as i64,
+            Err(_) if cell(4).is_empty() => 0,
+            _ => {
+                errors.push((line, format!("Invalid status {}", cell(4))));
+                continue;
+            }
+        };
+        let note = Some(cell(5).to_string()).filter(|n| !n.is_empty());
+        let owner_id = Some(cell(6)).filter(|o| !o.is_empty()).unwrap_or(default_owner).to_string();
+        parsed.push((line, Equipment {
+            id: 0,
+            name: name.to_string(),
+            serial: serial.to_string(),
+            value,
+            position: cell(3).to_string(),
+            status,
+            note,
+            owner_id,
+            available: true,
+        }));
+    }
+    Ok((parsed, errors))
+}
+
+// Upserts by serial. Teachers may only touch their own items; lab managers may
+// import the whole inventory and assign owners through an owner_id column.
+#[post("/equipment/import")]
+pub async fn import_equipments(
+    db_pool: web::Data<SqlitePool>,
+    mut payload: Multipart,
+    session: Session,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let manager = permission & (PERMISSION_LAB_MANAGER | PERMISSION_ADMIN) != 0;
+
+    let mut upload = None;
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        if field.content_disposition().get_name() != Some("file") {
+            continue;
+        }
+        let fname = field.content_disposition().get_filename().unwrap_or_default().to_string();
+        let mut data = Vec::new();
+        while let Ok(Some(chunk)) = field.try_next().await {
+            if data.len() + chunk.len() > MAX_IMPORT_BYTES {
+                return HttpResponse::PayloadTooLarge().json(json!({ "error": "File too large" }));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        upload = Some((fname, data));
+    }
+    let Some((fname, data)) = upload else {
+        return HttpResponse::BadRequest().json(json!({ "error": "No file uploaded" }));
+    };
+    let Some(kind) = Sheet::from_name(&fname) else {
+        return HttpResponse::UnsupportedMediaType().json(json!({ "error": "Upload a .csv or .xlsx file" }));
+    };
+
+    let owner = user_id.clone();
+    let parsed = web::block(move || {
+        spreadsheet::read_rows(&data, kind).and_then(|rows| parse_import(&rows, &owner))
+    })
+    .await;
+    let (mut rows, mut errors) = match parsed {
+        Ok(Ok(parsed)) => parsed,
+        Ok(Err(e)) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if !manager {
+        for (_, equipment) in rows.iter_mut() {
+            equipment.owner_id = user_id.clone();
+        }
+    }
+    let restrict = if manager { None } else { Some(user_id.as_str()) };
+    match db::import_equipments(&db_pool, rows, restrict).await {
+        Ok((created, updated, refused)) => {
+            errors.extend(refused);
+            errors.sort();
+            HttpResponse::Ok().json(json!({
+                "created": created,
+                "updated": updated,
+                "errors": errors.into_iter().map(|(row, error)| json!({ "row": row, "error": error })).collect::<Vec<_>>(),
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ExportParams {
+    pub format: Option<String>, // csv (default) or xlsx
+    pub owner_id: Option<String>,
+}
+
+fn loan_state_name(state: Option<i64>) -> &'static str {
+    match state {
+        Some(LOAN_RESERVED) => "reserved",
+        Some(LOAN_CHECKED_OUT) => "checked out",
+        _ => "available",
+    }
+}
+
+#[get("/equipment/export")]
+pub async fn export_equipments(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    web::Query(params): web::Query<ExportParams>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let owner = if permission & (PERMISSION_LAB_MANAGER | PERMISSION_ADMIN) != 0 {
+        params.owner_id.clone()
+    } else {
+        Some(user_id)
+    };
+    let items = match db::list_inventory(&db_pool, owner.as_deref()).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+
+    let header = [
+        "name", "serial", "value", "position", "status", "note", "owner_id",
+        "loan", "borrower_id", "borrower", "due_date",
+    ];
+    let rows: Vec<Vec<String>> = items
+        .into_iter()
+        .map(|i| vec![
+            i.name,
+            i.serial,
+            i.value.to_string(),
+            i.position,
+            i.status.to_string(),
+            i.note.unwrap_or_default(),
+            i.owner_id,
+            loan_state_name(i.loan_state).to_string(),
+            i.borrower_id.unwrap_or_default(),
+            i.borrower.unwrap_or_default(),
+            i.due_date.map(|d| d.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default(),
+        ])
+        .collect();
+    let (written, mime, fname) = match params.format.as_deref() {
+        Some("xlsx") => (
+            spreadsheet::write_xlsx(&header, &rows, &[2, 4]),
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "equipment.xlsx",
+        ),
+        None | Some("csv") => (spreadsheet::write_csv(&header, &rows), "text/csv; charset=utf-8", "equipment.csv"),
+        Some(other) => return HttpResponse::BadRequest().json(json!({ "error": format!("Unknown format {}", other) })),
+    };
+    match written {
+        Ok(body) => HttpResponse::Ok().content_type(mime).insert_header(attachment(fname)).body(body),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e })),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LabelParams {
+    pub kind: Option<String>,    // qr (default) or code128
+    pub format: Option<String>,  // svg (default) or png
+    pub content: Option<String>, // link (default for QR) or serial; barcodes always carry the serial
+    pub scale: Option<u32>,      // Pixels per module for PNG
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LabelSheetRequest {
+    pub ids: Vec<i64>,
+    pub kind: Option<String>,
+    pub content: Option<String>,
+    pub skip: Option<usize>, // Cells already used on the first sheet
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ScanParams {
+    pub code: String,
+}
+
+const MAX_LABELS: usize = 1000;
+
+pub fn equipment_link(config: &Config, id: i64) -> String {
+    format!("{}/equipment/{}", config.public_url, id)
+}
+
+// QR codes default to a deep link, or to the serial while PUBLIC_URL is unset,
+// since a relative link is useless to a phone scanning the label
+fn label_code(config: &Config, kind: Symbology, content: Option<&str>, equipment: &Equipment) -> Result<String, String> {
+    match (kind, content) {
+        (Symbology::Code128, _) | (_, Some("serial")) => Ok(equipment.serial.clone()),
+        (Symbology::Qr, None) if config.public_url.is_empty() => Ok(equipment.serial.clone()),
+        (Symbology::Qr, Some("link")) if config.public_url.is_empty() => {
+            Err("Link labels need PUBLIC_URL to be configured".to_string())
+        }
+        (Symbology::Qr, None | Some("link")) => Ok(equipment_link(config, equipment.id)),
+        (_, Some(other)) => Err(format!("Unknown label content {}", other)),
+    }
+}
+
+#[get("/equipment/{id}/label")]
+pub async fn get_equipment_label(
+    db_pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+    session: Session,
+    path: web::Path<i64>,
+    web::Query(params): web::Query<LabelParams>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let Some(kind) = Symbology::from_name(params.kind.as_deref().unwrap_or("qr")) else {
+        return HttpResponse::BadRequest().json(json!({ "error": "Unknown label kind" }));
+    };
+    if let Err(e) = check_equip_perm(&db_pool, &session, id).await {
+        return e;
+    }
+    let equipment = match db::get_equipment_by_id(&db_pool, id).await {
+        Ok(e) => e,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Equipment not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let code = match label_code(&config, kind, params.content.as_deref(), &equipment) {
+        Ok(code) => code,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let rendered = match params.format.as_deref() {
+        None | Some("svg") => labels::svg(kind, &code).map(|s| (s.into_bytes(), "image/svg+xml")),
+        Some("png") => labels::png(kind, &code, params.scale.unwrap_or(8).clamp(1, 32)).map(|b| (b, "image/png")),
+        Some(other) => return HttpResponse::BadRequest().json(json!({ "error": format!("Unknown format {}", other) })),
+    };
+    match rendered {
+        Ok((body, mime)) => HttpResponse::Ok().content_type(mime).body(body),
+        Err(e) => HttpResponse::BadRequest().json(json!({ "error": e })),
+    }
+}
+
+// A4 label sheet for the chosen items; teachers may only print their own
+#[post("/equipment/labels")]
+pub async fn print_equipment_labels(
+    db_pool: web::Data<SqlitePool>,
+    config: web::Data<Config>,
+    session: Session,
+    body: web::Json<LabelSheetRequest>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let Some(kind) = Symbology::from_name(body.kind.as_deref().unwrap_or("qr")) else {
+        return HttpResponse::BadRequest().json(json!({ "error": "Unknown label kind" }));
+    };
+    if body.ids.is_empty() || body.ids.len() > MAX_LABELS {
+        return HttpResponse::BadRequest().json(json!({ "error": format!("Select between 1 and {} items", MAX_LABELS) }));
+    }
+
+    let mut items = Vec::with_capacity(body.ids.len());
+    for &id in &body.ids {
+        let equipment = match db::get_equipment_by_id(&db_pool, id).await {
+            Ok(e) => e,
+            Err(sqlx::Error::RowNotFound) => {
+                return HttpResponse::NotFound().json(json!({ "error": format!("Equipment {} not found", id) }))
+            }
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        };
+        if permission & (PERMISSION_LAB_MANAGER | PERMISSION_ADMIN) == 0 && equipment.owner_id != user_id {
+            return HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }));
+        }
+        let code = match label_code(&config, kind, body.content.as_deref(), &equipment) {
+            Ok(code) => code,
+            Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+        };
+        items.push(Label { code, name: equipment.name, serial: equipment.serial, position: equipment.position });
+    }
+
+    let skip = body.skip.unwrap_or(0);
+    match web::block(move || labels::label_sheet(kind, &items, skip)).await {
+        Ok(Ok(pdf)) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header(attachment("equipment-labels.pdf"))
+            .body(pdf),
+        Ok(Err(e)) => HttpResponse::BadRequest().json(json!({ "error": e })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Resolves a scanned label, either a deep link or a bare serial, to the item and its open loan
+#[get("/equipment/scan")]
+pub async fn scan_equipment(db_pool: web::Data<SqlitePool>, web::Query(params): web::Query<ScanParams>) -> impl Responder {
+    let code = params.code.trim();
+    let link_id = code
+        .rsplit_once("/equipment/")
+        .and_then(|(_, rest)| rest.trim_end_matches('/').parse::<i64>().ok());
+    let found = match link_id {
+        Some(id) => match db::get_equipment_by_id(&db_pool, id).await {
+            Ok(e) => Ok(Some(e)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        },
+        None => db::get_equipment_by_serial(&db_pool, code).await,
+    };
+    let equipment = match found {
+        Ok(Some(e)) => e,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "error": "No equipment matches this code" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::get_open_loan(&db_pool, equipment.id).await {
+        Ok(loan) => HttpResponse::Ok().json(json!({
+            "matched": if link_id.is_some() { "link" } else { "serial" },
+            "equipment": equipment,
+            "loan": loan,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
 pub fn init_equipment_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(create_equipment)
+    cfg.service(list_overdue_loans)
+        .service(search_equipments)
+        .service(export_equipments)
+        .service(import_equipments)
+        .service(scan_equipment)
+        .service(print_equipment_labels)
+        .service(get_equipment_label)
+        .service(create_equipment)
         .service(list_equipments)
         .service(get_equipment)
         .service(update_equipment)
@@ -225,5 +785,10 @@ pub fn init_equipment_routes(cfg: &mut web::ServiceConfig) {
         .service(get_equipment_history)
         .service(list_histories_by_item)
         .service(update_equipment_history)
+        .service(checkout_loan)
+        .service(return_loan)
+        .service(mark_loan_lost)
+        .service(cancel_reservation)
+        .service(extend_loan)
         .service(delete_equipment_history);
 }
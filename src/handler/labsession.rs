@@ -0,0 +1,158 @@
+use actix_session::Session;
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::checkin;
+use crate::db;
+use crate::utils::{check_subcourse_perm, schedule_week};
+
+#[derive(Deserialize)]
+pub struct OpenSessionRequest {
+    pub subcourse_id: i64,
+    pub schedule_id: Option<i64>, // Defaults to the schedule of the current week
+}
+
+#[post("/lab_session")]
+pub async fn open_lab_session(
+    db_pool: web::Data<SqlitePool>,
+    item: web::Json<OpenSessionRequest>,
+    session: Session,
+) -> impl Responder {
+    let req = item.into_inner();
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, req.subcourse_id).await {
+        return err;
+    }
+    let schedule_id = match req.schedule_id {
+        Some(id) => id,
+        None => match current_schedule_id(&db_pool, req.subcourse_id).await {
+            Ok(Some(id)) => id,
+            Ok(None) => return HttpResponse::BadRequest().json(json!({ "error": "No schedule for this week" })),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        },
+    };
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let secret = checkin::new_secret();
+    match db::add_lab_session(&db_pool, req.subcourse_id, schedule_id, &user_id, &secret).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+async fn current_schedule_id(
+    db_pool: &SqlitePool,
+    subcourse_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    let today = Local::now().naive_local().date();
+    let subcourse = db::get_subcourse_by_id(db_pool, subcourse_id).await?;
+    let semester = db::get_semester_by_id(db_pool, subcourse.year_id).await?;
+    let week = schedule_week(semester.start, subcourse.lag_week, today);
+    let schedule = db::get_schedule_by_week(db_pool, subcourse.course_id, week).await?;
+    Ok(schedule.map(|s| s.id))
+}
+
+#[put("/lab_session/{id}/close")]
+pub async fn close_lab_session(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let lab_session = match db::get_lab_session_by_id(&db_pool, id).await {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, lab_session.subcourse_id).await {
+        return err;
+    }
+    match db::close_lab_session(&db_pool, id).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::BadRequest().json(json!({ "error": "Session already closed" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Shown on the teacher's screen, either as digits or rendered into a QR code
+#[get("/lab_session/{id}/code")]
+pub async fn get_checkin_code(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let lab_session = match db::get_lab_session_by_id(&db_pool, path.into_inner()).await {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, lab_session.subcourse_id).await {
+        return err;
+    }
+    if lab_session.closed_at.is_some() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Session already closed" }));
+    }
+    let (code, expires_in) = checkin::current_code(&lab_session.secret);
+    HttpResponse::Ok().json(json!({
+        "code": code,
+        "expires_in": expires_in,
+        "qr": format!("laboxide:checkin:{}:{}", lab_session.id, code),
+    }))
+}
+
+#[get("/lab_session/subcourse/{subcourse_id}")]
+pub async fn list_lab_sessions(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let subcourse_id = path.into_inner();
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+        return err;
+    }
+    match db::list_lab_sessions(&db_pool, subcourse_id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/lab_session/{id}/logs")]
+pub async fn list_session_logs(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let lab_session = match db::get_lab_session_by_id(&db_pool, id).await {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, lab_session.subcourse_id).await {
+        return err;
+    }
+    match db::list_logs_by_session(&db_pool, id).await {
+        Ok(logs) => HttpResponse::Ok().json(logs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Students and teachers both need to know whether a session is running
+#[get("/lab_session/current/{subcourse_id}")]
+pub async fn get_current_lab_session(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    match db::get_open_lab_session(&db_pool, path.into_inner()).await {
+        Ok(Some(rec)) => HttpResponse::Ok().json(rec),
+        Ok(None) => HttpResponse::NotFound().json(json!({ "error": "No open lab session" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub fn init_lab_session_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(open_lab_session)
+        .service(close_lab_session)
+        .service(get_checkin_code)
+        .service(list_lab_sessions)
+        .service(list_session_logs);
+}
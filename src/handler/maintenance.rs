@@ -0,0 +1,304 @@
+use actix_session::Session;
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::config::{PERMISSION_ADMIN, PERMISSION_LAB_MANAGER};
+use crate::db;
+use crate::handler::equipment::check_equip_perm;
+use crate::models::{DepreciationRow, EquipmentAsset, EquipmentMaintenance};
+use crate::models::{MAINT_CALIBRATION, MAINT_REPAIR, MAINT_SERVICE};
+use crate::spreadsheet;
+use crate::tasks::CALIBRATION_REMIND_DAYS;
+use crate::utils::attachment;
+
+#[derive(Deserialize)]
+pub struct NewMaintenance {
+    pub kind: i64,
+    pub summary: String,
+    #[serde(default)]
+    pub cost: i64,
+    #[serde(default)]
+    pub vendor: String,
+    #[serde(default)]
+    pub certificate: String,
+    pub started_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+pub struct CompleteMaintenance {
+    pub completed_at: Option<NaiveDateTime>,
+    pub cost: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct DueParams {
+    pub days: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct DepreciationParams {
+    pub as_of: Option<NaiveDate>,
+    pub owner_id: Option<String>,
+    pub format: Option<String>, // json (default), csv or xlsx
+}
+
+#[derive(Serialize)]
+pub struct Depreciation {
+    #[serde(flatten)]
+    pub item: DepreciationRow,
+    pub months_used: Option<i64>,
+    pub accumulated: i64,
+    pub book_value: i64,
+}
+
+fn can_see_all(session: &Session) -> bool {
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    permission & (PERMISSION_LAB_MANAGER | PERMISSION_ADMIN) != 0
+}
+
+#[get("/equipment/{id}/maintenance")]
+pub async fn list_maintenance(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = check_equip_perm(&db_pool, &session, id).await {
+        return e;
+    }
+    match db::list_equipment_maintenances(&db_pool, id).await {
+        Ok(records) => HttpResponse::Ok().json(records),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[post("/equipment/{id}/maintenance")]
+pub async fn create_maintenance(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<NewMaintenance>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = check_equip_perm(&db_pool, &session, id).await {
+        return e;
+    }
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let body = body.into_inner();
+    if ![MAINT_REPAIR, MAINT_CALIBRATION, MAINT_SERVICE].contains(&body.kind) {
+        return HttpResponse::BadRequest().json(json!({ "error": format!("Invalid maintenance kind {}", body.kind) }));
+    }
+    if body.summary.trim().is_empty() || body.cost < 0 {
+        return HttpResponse::BadRequest().json(json!({ "error": "A summary and a non-negative cost are required" }));
+    }
+    if body.kind == MAINT_CALIBRATION && body.expires_at.is_none() {
+        return HttpResponse::BadRequest().json(json!({ "error": "A calibration needs a certificate expiry date" }));
+    }
+    let now = Local::now().naive_local();
+    let started_at = body.started_at.unwrap_or(now);
+    // Only repairs stay open; calibrations and services are recorded once done
+    let completed_at = match body.kind {
+        MAINT_REPAIR => body.completed_at,
+        _ => Some(body.completed_at.unwrap_or(started_at)),
+    };
+    if completed_at.is_some_and(|c| c < started_at) {
+        return HttpResponse::BadRequest().json(json!({ "error": "Completion cannot be before the start" }));
+    }
+
+    let record = EquipmentMaintenance {
+        id: 0,
+        item_id: id,
+        kind: body.kind,
+        summary: body.summary.trim().to_string(),
+        cost: body.cost,
+        vendor: body.vendor.trim().to_string(),
+        certificate: body.certificate.trim().to_string(),
+        started_at,
+        completed_at,
+        expires_at: body.expires_at.filter(|_| body.kind == MAINT_CALIBRATION),
+        reminded_at: None,
+        created_by: user_id,
+        created_at: now,
+    };
+    match db::add_equipment_maintenance(&db_pool, record).await {
+        Ok(record) => HttpResponse::Created().json(record),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+async fn load_maintenance(db_pool: &web::Data<SqlitePool>, session: &Session, id: i64) -> Result<EquipmentMaintenance, HttpResponse> {
+    let record = match db::get_equipment_maintenance(db_pool, id).await {
+        Ok(r) => r,
+        Err(sqlx::Error::RowNotFound) => return Err(HttpResponse::NotFound().json(json!({ "error": "Maintenance record not found" }))),
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    };
+    check_equip_perm(db_pool, session, record.item_id).await?;
+    Ok(record)
+}
+
+#[put("/equipment/maintenance/{id}/complete")]
+pub async fn complete_maintenance(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<CompleteMaintenance>,
+) -> impl Responder {
+    let record = match load_maintenance(&db_pool, &session, path.into_inner()).await {
+        Ok(r) => r,
+        Err(err) => return err,
+    };
+    let completed_at = body.completed_at.unwrap_or_else(|| Local::now().naive_local());
+    if completed_at < record.started_at || body.cost.is_some_and(|c| c < 0) {
+        return HttpResponse::BadRequest().json(json!({ "error": "Invalid completion time or cost" }));
+    }
+    match db::complete_equipment_maintenance(&db_pool, record.id, completed_at, body.cost).await {
+        Ok(record) => HttpResponse::Ok().json(record),
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/equipment/maintenance/{id}")]
+pub async fn delete_maintenance(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    let record = match load_maintenance(&db_pool, &session, path.into_inner()).await {
+        Ok(r) => r,
+        Err(err) => return err,
+    };
+    match db::delete_equipment_maintenance(&db_pool, record.id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Maintenance record deleted" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Maintenance record not found" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/equipment/{id}/asset")]
+pub async fn set_asset(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<EquipmentAsset>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = check_equip_perm(&db_pool, &session, id).await {
+        return e;
+    }
+    let mut asset = body.into_inner();
+    if asset.life_months <= 0 || asset.salvage_value < 0 {
+        return HttpResponse::BadRequest().json(json!({ "error": "Useful life must be positive and salvage value non-negative" }));
+    }
+    asset.item_id = id;
+    match db::set_equipment_asset(&db_pool, asset).await {
+        Ok(asset) => HttpResponse::Ok().json(asset),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Calibrations expiring within `days` (30 by default), including ones already expired
+#[get("/equipment/calibration/due")]
+pub async fn list_calibration_due(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    web::Query(params): web::Query<DueParams>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let owner = (!can_see_all(&session)).then_some(user_id);
+    let until = Local::now().date_naive() + Duration::days(params.days.unwrap_or(CALIBRATION_REMIND_DAYS).clamp(0, 3650));
+    match db::list_calibrations_due(&db_pool, until, owner.as_deref()).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Whole months from acquisition to the report date
+fn months_between(from: NaiveDate, to: NaiveDate) -> i64 {
+    let months = (to.year() - from.year()) as i64 * 12 + to.month() as i64 - from.month() as i64;
+    if to.day() < from.day() { months - 1 } else { months }.max(0)
+}
+
+// Straight line from `value` at acquisition down to the salvage value over the useful life.
+// Items without purchase data keep their full value.
+fn depreciate(item: DepreciationRow, as_of: NaiveDate) -> Depreciation {
+    let (months_used, accumulated) = match (item.acquired_on, item.life_months) {
+        (Some(acquired), Some(life)) if life > 0 => {
+            let used = months_between(acquired, as_of).min(life);
+            let depreciable = (item.value - item.salvage_value.unwrap_or(0)).max(0);
+            (Some(used), depreciable * used / life)
+        }
+        _ => (None, 0),
+    };
+    Depreciation { book_value: item.value - accumulated, accumulated, months_used, item }
+}
+
+#[get("/equipment/depreciation")]
+pub async fn depreciation_report(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    web::Query(params): web::Query<DepreciationParams>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let owner = if can_see_all(&session) { params.owner_id.clone() } else { Some(user_id) };
+    let as_of = params.as_of.unwrap_or_else(|| Local::now().date_naive());
+    let rows = match db::list_depreciation_rows(&db_pool, owner.as_deref()).await {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let items: Vec<Depreciation> = rows.into_iter().map(|r| depreciate(r, as_of)).collect();
+
+    let header = [
+        "name", "serial", "position", "status", "owner_id", "value", "acquired_on", "life_months",
+        "salvage_value", "months_used", "accumulated", "book_value", "maintenance_cost",
+    ];
+    let opt = |v: Option<i64>| v.map(|v| v.to_string()).unwrap_or_default();
+    let table = |items: &[Depreciation]| -> Vec<Vec<String>> {
+        items
+            .iter()
+            .map(|d| vec![
+                d.item.name.clone(),
+                d.item.serial.clone(),
+                d.item.position.clone(),
+                d.item.status.to_string(),
+                d.item.owner_id.clone(),
+                d.item.value.to_string(),
+                d.item.acquired_on.map(|a| a.to_string()).unwrap_or_default(),
+                opt(d.item.life_months),
+                opt(d.item.salvage_value),
+                opt(d.months_used),
+                d.accumulated.to_string(),
+                d.book_value.to_string(),
+                d.item.maintenance_cost.to_string(),
+            ])
+            .collect()
+    };
+    let written = match params.format.as_deref() {
+        None | Some("json") => {
+            return HttpResponse::Ok().json(json!({
+                "as_of": as_of,
+                "total_value": items.iter().map(|d| d.item.value).sum::<i64>(),
+                "total_accumulated": items.iter().map(|d| d.accumulated).sum::<i64>(),
+                "total_book_value": items.iter().map(|d| d.book_value).sum::<i64>(),
+                "total_maintenance_cost": items.iter().map(|d| d.item.maintenance_cost).sum::<i64>(),
+                "items": items,
+            }));
+        }
+        Some("csv") => spreadsheet::write_csv(&header, &table(&items)).map(|b| (b, "text/csv; charset=utf-8", "depreciation.csv")),
+        Some("xlsx") => spreadsheet::write_xlsx(&header, &table(&items), &[5, 7, 8, 9, 10, 11, 12])
+            .map(|b| (b, "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", "depreciation.xlsx")),
+        Some(other) => return HttpResponse::BadRequest().json(json!({ "error": format!("Unknown format {}", other) })),
+    };
+    match written {
+        Ok((body, mime, fname)) => HttpResponse::Ok().content_type(mime).insert_header(attachment(fname)).body(body),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e })),
+    }
+}
+
+// Registered ahead of the equipment routes so the fixed paths are not taken for an id
+pub fn init_maintenance_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_calibration_due)
+        .service(depreciation_report)
+        .service(list_maintenance)
+        .service(create_maintenance)
+        .service(complete_maintenance)
+        .service(delete_maintenance)
+        .service(set_asset);
+}
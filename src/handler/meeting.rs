@@ -1,11 +1,141 @@
 use actix_session::Session;
 use actix_web::{get, post, put, delete, web, HttpResponse, Responder};
+use chrono::{Duration, Local, NaiveDate, NaiveTime};
+use std::collections::HashSet;
+use serde::Deserialize;
 use serde_json::json;
 use sqlx::SqlitePool;
 
 use crate::db;
-use crate::models::{MeetingRoom, MeetingAgenda};
+use crate::models::{MeetingRoom, MeetingAgenda, MeetingException, MeetingOccurrence, FreeSlot, RoomAvailability};
+use crate::models::{MEETING_APPROVED, MEETING_CANCELLED, MEETING_PENDING, MEETING_REJECTED};
 use crate::config::{PERMISSION_MEETING_MANAGER, PERMISSION_ADMIN};
+use crate::recurrence;
+
+#[derive(Deserialize)]
+pub struct DecisionRequest {
+    #[serde(default)]
+    pub reason: String,
+}
+
+// A meeting of `duration` minutes somewhere between `start` and `end` on
+// `date`, or on every date of the given recurrence
+#[derive(Deserialize)]
+pub struct AvailabilityParams {
+    pub date: NaiveDate,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub duration: i64,
+    #[serde(default)]
+    pub capacity: i64,
+    #[serde(default)]
+    pub projector: bool,
+    #[serde(default)]
+    pub video_conferencing: bool,
+    #[serde(default)]
+    pub repeat: i64,
+    #[serde(default)]
+    pub repeat_interval: i64,
+    pub repeat_until: Option<NaiveDate>,
+    pub repeat_count: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct OccurrenceParams {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+// Validates the recurrence fields and bounds a repeating agenda by the end
+// of the semester its first occurrence falls in
+async fn prepare_recurrence(db_pool: &SqlitePool, agenda: &mut MeetingAgenda) -> Result<(), HttpResponse> {
+    if agenda.end_time <= agenda.start_time {
+        return Err(HttpResponse::BadRequest().json(json!({ "error": "End time must be after start time" })));
+    }
+    if agenda.repeat != 1 {
+        agenda.repeat = 0;
+        agenda.repeat_interval = 1;
+        agenda.repeat_until = None;
+        agenda.repeat_count = None;
+        return Ok(());
+    }
+    agenda.repeat_interval = agenda.repeat_interval.max(1);
+    if agenda.repeat_count.is_some_and(|n| n < 1) {
+        return Err(HttpResponse::BadRequest().json(json!({ "error": "Repeat count must be at least 1" })));
+    }
+    // A series is only ever expanded MAX_HORIZON_DAYS ahead, so nothing may reach further
+    let span = |occurrences: i64| recurrence::step_days(agenda).checked_mul(occurrences).unwrap_or(i64::MAX);
+    if span(1) > recurrence::MAX_HORIZON_DAYS || agenda.repeat_count.is_some_and(|n| span(n - 1) > recurrence::MAX_HORIZON_DAYS) {
+        return Err(HttpResponse::BadRequest().json(json!({
+            "error": format!("A series may span at most {} days", recurrence::MAX_HORIZON_DAYS),
+        })));
+    }
+    if agenda.repeat_until.is_some_and(|until| until < agenda.date) {
+        return Err(HttpResponse::BadRequest().json(json!({ "error": "Repeat end is before the first meeting" })));
+    }
+    match db::get_semester_by_date(db_pool, agenda.date).await {
+        Ok(Some(semester)) => {
+            agenda.repeat_until = Some(agenda.repeat_until.map_or(semester.end, |until| until.min(semester.end)));
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+// Booking rules: approved bookings block everyone. A pending request holds its
+// slot against other requests, but a meeting manager may book or approve over
+// it, which rejects the pending request.
+fn is_meeting_manager(session: &Session) -> bool {
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    permission & PERMISSION_MEETING_MANAGER != 0
+}
+
+pub fn is_active(agenda: &MeetingAgenda) -> bool {
+    agenda.confirm == MEETING_PENDING || agenda.confirm == MEETING_APPROVED
+}
+
+async fn notify_requester(db_pool: &SqlitePool, agenda: &MeetingAgenda) {
+    let verb = match agenda.confirm {
+        MEETING_APPROVED => "approved",
+        MEETING_REJECTED => "rejected",
+        MEETING_CANCELLED => "cancelled",
+        _ => return,
+    };
+    let mut message = format!("Your meeting \"{}\" on {} was {}", agenda.title, agenda.date, verb);
+    if !agenda.reason.is_empty() {
+        message.push_str(&format!(": {}", agenda.reason));
+    }
+    let link = format!("/meeting_agenda/{}", agenda.id.unwrap_or_default());
+    let _ = db::add_notification(db_pool, &agenda.userid, "meeting_decision", &message, &link).await;
+}
+
+async fn notify_managers(db_pool: &SqlitePool, agenda: &MeetingAgenda) {
+    let Ok(managers) = db::list_user_ids_with_permission(db_pool, PERMISSION_MEETING_MANAGER).await else {
+        return;
+    };
+    let message = format!("{} requested \"{}\" on {}", agenda.username, agenda.title, agenda.date);
+    let link = format!("/meeting_agenda/{}", agenda.id.unwrap_or_default());
+    for manager in managers {
+        let _ = db::add_notification(db_pool, &manager, "meeting_request", &message, &link).await;
+    }
+}
+
+// Rejects the pending requests an approved booking now overlaps
+async fn reject_overlapping(db_pool: &SqlitePool, agenda: &MeetingAgenda, actor_id: &str) -> Result<(), sqlx::Error> {
+    for rejected in db::reject_overlapping_requests(db_pool, agenda, actor_id).await? {
+        notify_requester(db_pool, &rejected).await;
+    }
+    Ok(())
+}
+
+fn conflict_response(conflict: MeetingAgenda, occurrence: MeetingOccurrence) -> HttpResponse {
+    HttpResponse::Conflict().json(json!({
+        "error": "Time conflict with existing agenda",
+        "conflict": conflict,
+        "date": occurrence.date,
+    }))
+}
 
 #[post("/meeting_room")]
 pub async fn create_meeting_room(
@@ -59,17 +189,26 @@ pub async fn create_meeting_agenda(
     item: web::Json<MeetingAgenda>,
 ) -> impl Responder {
     let mut agenda = item.into_inner();
-    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
-    agenda.confirm = if permission & PERMISSION_MEETING_MANAGER != 0 { 1 } else { 0 };
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let manager = is_meeting_manager(&session);
+    agenda.id = None;
+    agenda.reason = String::new();
+    if manager {
+        agenda.confirm = MEETING_APPROVED;
+        agenda.decided_by = Some(user_id.clone());
+        agenda.decided_at = Some(Local::now().naive_local());
+    } else {
+        agenda.confirm = MEETING_PENDING;
+        agenda.decided_by = None;
+        agenda.decided_at = None;
+    }
+    if let Err(e) = prepare_recurrence(&db_pool, &mut agenda).await {
+        return e;
+    }
 
     // Check conflict before insertion
-    match db::check_meeting_conflict(&db_pool, &agenda).await {
-        Ok(Some(conflict)) => {
-            return HttpResponse::Conflict().json(json!({
-                "error": "Time conflict with existing agenda",
-                "conflict": conflict
-            }))
-        }
+    match db::check_meeting_conflict(&db_pool, &agenda, &[], !manager).await {
+        Ok(Some((conflict, occurrence))) => return conflict_response(conflict, occurrence),
         Ok(None) => {} // Proceed
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
@@ -77,7 +216,16 @@ pub async fn create_meeting_agenda(
     }
 
     match db::add_meeting_agenda(&db_pool, agenda).await {
-        Ok(record) => HttpResponse::Ok().json(record),
+        Ok(record) => {
+            if manager {
+                if let Err(e) = reject_overlapping(&db_pool, &record, &user_id).await {
+                    return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+                }
+            } else {
+                notify_managers(&db_pool, &record).await;
+            }
+            HttpResponse::Ok().json(record)
+        }
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -116,12 +264,13 @@ pub async fn check_meeting_perm(
     let user: String = session.get::<String>("user_id").ok().flatten().unwrap_or("".to_string());
     match db::get_meeting_agenda_by_id(db_pool, agenda_id).await {
         Ok(agenda) => {
-            if (agenda.confirm == 1) || (agenda.userid != user) {
+            if (agenda.confirm != MEETING_PENDING) || (agenda.userid != user) {
                 return Err(HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" })))
             }
-            return Ok(())
+            Ok(())
         },
-        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::NotFound().json(json!({ "error": "Meeting agenda not found" }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
     }
 }
 
@@ -132,13 +281,40 @@ pub async fn update_meeting_agenda(
     path: web::Path<i64>,
     item: web::Json<MeetingAgenda>,
 ) -> impl Responder {
-    let agenda = item.into_inner();
+    let mut agenda = item.into_inner();
     let agenda_id = path.into_inner();
     if let Err(e) = check_meeting_perm(&db_pool, &session, agenda_id).await {
         return e;
     }
+    // The state only changes through confirm, reject and cancel
+    match db::get_meeting_agenda_by_id(&db_pool, agenda_id).await {
+        Ok(existing) => agenda.confirm = existing.confirm,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    agenda.id = Some(agenda_id);
+    if let Err(e) = prepare_recurrence(&db_pool, &mut agenda).await {
+        return e;
+    }
+    let exceptions = match db::list_meeting_exceptions(&db_pool, agenda_id).await {
+        Ok(exceptions) => exceptions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let manager = is_meeting_manager(&session);
+    match db::check_meeting_conflict(&db_pool, &agenda, &exceptions, !manager).await {
+        Ok(Some((conflict, occurrence))) => return conflict_response(conflict, occurrence),
+        Ok(None) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
     match db::update_meeting_agenda(&db_pool, agenda_id, agenda).await {
-        Ok(updated) => HttpResponse::Ok().json(updated),
+        Ok(updated) => {
+            if updated.confirm == MEETING_APPROVED {
+                let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+                if let Err(e) = reject_overlapping(&db_pool, &updated, &user_id).await {
+                    return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+                }
+            }
+            HttpResponse::Ok().json(updated)
+        }
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -146,9 +322,14 @@ pub async fn update_meeting_agenda(
 #[delete("/meeting_agenda/{id}")]
 pub async fn delete_meeting_agenda(
     db_pool: web::Data<SqlitePool>,
+    session: Session,
     path: web::Path<i64>,
 ) -> impl Responder {
-    match db::delete_meeting_agenda(&db_pool, path.into_inner()).await {
+    let agenda_id = path.into_inner();
+    if let Err(e) = check_meeting_perm(&db_pool, &session, agenda_id).await {
+        return e;
+    }
+    match db::delete_meeting_agenda(&db_pool, agenda_id).await {
         Ok(true) => HttpResponse::Ok().json(json!({ "message": "Meeting agenda deleted" })),
         Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Meeting agenda not found" })),
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
@@ -162,14 +343,320 @@ pub async fn confirm_meeting_agenda(
     path: web::Path<i64>,
 ) -> impl Responder {
     let id = path.into_inner();
+    if !is_meeting_manager(&session) {
+        return HttpResponse::Forbidden().json(json!({ "error": "Permission denied" }));
+    }
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let agenda = match db::get_meeting_agenda_by_id(&db_pool, id).await {
+        Ok(agenda) => agenda,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Meeting agenda not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let exceptions = match db::list_meeting_exceptions(&db_pool, id).await {
+        Ok(exceptions) => exceptions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    // Another booking may have been approved for the slot since the request was made
+    match db::check_meeting_conflict(&db_pool, &agenda, &exceptions, false).await {
+        Ok(Some((conflict, occurrence))) => return conflict_response(conflict, occurrence),
+        Ok(None) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+
+    match db::decide_meeting_agenda(&db_pool, id, MEETING_APPROVED, "", &user_id).await {
+        Ok(agenda) => {
+            notify_requester(&db_pool, &agenda).await;
+            if let Err(e) = reject_overlapping(&db_pool, &agenda, &user_id).await {
+                return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+            }
+            HttpResponse::Ok().json(agenda)
+        }
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/meeting_agenda/{id}/reject")]
+pub async fn reject_meeting_agenda(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    item: web::Json<DecisionRequest>,
+) -> impl Responder {
+    if !is_meeting_manager(&session) {
+        return HttpResponse::Forbidden().json(json!({ "error": "Permission denied" }));
+    }
+    let reason = item.reason.trim();
+    if reason.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "A reason is required" }));
+    }
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::decide_meeting_agenda(&db_pool, path.into_inner(), MEETING_REJECTED, reason, &user_id).await {
+        Ok(agenda) => {
+            notify_requester(&db_pool, &agenda).await;
+            HttpResponse::Ok().json(agenda)
+        }
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// The requester withdraws, or a manager revokes, a pending or approved booking
+#[put("/meeting_agenda/{id}/cancel")]
+pub async fn cancel_meeting_agenda(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    item: web::Json<DecisionRequest>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
     let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let agenda = match db::get_meeting_agenda_by_id(&db_pool, id).await {
+        Ok(agenda) => agenda,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Meeting agenda not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if agenda.userid != user_id && permission & (PERMISSION_MEETING_MANAGER | PERMISSION_ADMIN) == 0 {
+        return HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }));
+    }
+    match db::decide_meeting_agenda(&db_pool, id, MEETING_CANCELLED, item.reason.trim(), &user_id).await {
+        Ok(agenda) => {
+            if agenda.userid != user_id {
+                notify_requester(&db_pool, &agenda).await;
+            }
+            HttpResponse::Ok().json(agenda)
+        }
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
 
-    if permission & PERMISSION_MEETING_MANAGER == 0 {
+// Pending requests across all rooms for meeting managers to decide on
+#[get("/meeting_agenda/pending")]
+pub async fn list_pending_meeting_agendas(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+) -> impl Responder {
+    if !is_meeting_manager(&session) {
         return HttpResponse::Forbidden().json(json!({ "error": "Permission denied" }));
     }
+    match db::list_pending_meeting_agendas(&db_pool).await {
+        Ok(agendas) => HttpResponse::Ok().json(agendas),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
 
-    match db::confirm_meeting_agenda(&db_pool, id).await {
-        Ok(agenda) => HttpResponse::Ok().json(agenda),
+// Gaps of at least `min` between `start` and `end` not covered by `busy`
+fn free_slots(mut busy: Vec<(NaiveTime, NaiveTime)>, start: NaiveTime, end: NaiveTime, min: Duration) -> Vec<FreeSlot> {
+    busy.sort();
+    let mut slots = Vec::new();
+    let mut cursor = start;
+    for (busy_start, busy_end) in busy {
+        if cursor >= end {
+            break;
+        }
+        if busy_start > cursor {
+            let gap_end = busy_start.min(end);
+            if gap_end - cursor >= min {
+                slots.push(FreeSlot { start_time: cursor, end_time: gap_end });
+            }
+        }
+        cursor = cursor.max(busy_end);
+    }
+    if cursor < end && end - cursor >= min {
+        slots.push(FreeSlot { start_time: cursor, end_time: end });
+    }
+    slots
+}
+
+// Rooms meeting the requirements with their free slots in the time window.
+// For a recurrence a slot is only listed if it is free on every date.
+#[get("/meeting_room/search")]
+pub async fn search_meeting_rooms(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    query: web::Query<AvailabilityParams>,
+) -> impl Responder {
+    let params = query.into_inner();
+    let mut probe = MeetingAgenda {
+        id: None,
+        title: String::new(),
+        userid: String::new(),
+        username: String::new(),
+        repeat: params.repeat,
+        date: params.date,
+        start_time: params.start,
+        end_time: params.end,
+        room_id: 0,
+        confirm: MEETING_PENDING,
+        repeat_interval: params.repeat_interval,
+        repeat_until: params.repeat_until,
+        repeat_count: params.repeat_count,
+        reason: String::new(),
+        decided_by: None,
+        decided_at: None,
+        created_at: None,
+    };
+    if let Err(e) = prepare_recurrence(&db_pool, &mut probe).await {
+        return e;
+    }
+    if params.duration <= 0 || params.duration > (params.end - params.start).num_minutes() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Duration must fit in the time window" }));
+    }
+    let duration = Duration::minutes(params.duration);
+    let dates: Vec<NaiveDate> = recurrence::series(&probe, &[]).iter().map(|o| o.date).collect();
+    let (Some(&from), Some(&to)) = (dates.first(), dates.last()) else {
+        return HttpResponse::BadRequest().json(json!({ "error": "The recurrence has no dates" }));
+    };
+    let wanted: HashSet<NaiveDate> = dates.iter().copied().collect();
+
+    let rooms = match db::list_meeting_rooms(&db_pool).await {
+        Ok(rooms) => rooms,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let include_pending = !is_meeting_manager(&session);
+    let mut available = Vec::new();
+    for room in rooms {
+        let Some(room_id) = room.id else { continue };
+        if room.capacity < params.capacity
+            || (params.projector && room.projector == 0)
+            || (params.video_conferencing && room.video_conferencing == 0)
+        {
+            continue;
+        }
+        let agendas = match db::list_meeting_agendas(&db_pool, room_id).await {
+            Ok(agendas) => agendas,
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        };
+        let exceptions = match db::list_room_meeting_exceptions(&db_pool, room_id).await {
+            Ok(exceptions) => exceptions,
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        };
+        let busy = agendas
+            .iter()
+            .filter(|a| recurrence::blocks(a, include_pending))
+            .flat_map(|a| recurrence::expand(a, &exceptions, from, to))
+            .filter(|o| wanted.contains(&o.date))
+            .map(|o| (o.start_time, o.end_time))
+            .collect();
+        let slots = free_slots(busy, params.start, params.end, duration);
+        if !slots.is_empty() {
+            available.push(RoomAvailability { room, slots });
+        }
+    }
+    HttpResponse::Ok().json(json!({ "dates": dates, "rooms": available }))
+}
+
+// Every occurrence in the room between `from` and `to` (default: the next 30 days)
+#[get("/meeting_agenda/room/{id}/occurrences")]
+pub async fn list_room_occurrences(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    query: web::Query<OccurrenceParams>,
+) -> impl Responder {
+    let room_id = path.into_inner();
+    let from = query.from.unwrap_or_else(|| Local::now().date_naive());
+    let to = query.to.unwrap_or(from + Duration::days(30));
+    if to < from || (to - from).num_days() > recurrence::MAX_HORIZON_DAYS {
+        return HttpResponse::BadRequest().json(json!({ "error": "Invalid date range" }));
+    }
+    let agendas = match db::list_meeting_agendas(&db_pool, room_id).await {
+        Ok(agendas) => agendas,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let exceptions = match db::list_room_meeting_exceptions(&db_pool, room_id).await {
+        Ok(exceptions) => exceptions,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let mut occurrences: Vec<MeetingOccurrence> = agendas
+        .iter()
+        .filter(|a| is_active(a))
+        .flat_map(|a| recurrence::expand(a, &exceptions, from, to))
+        .collect();
+    occurrences.sort_by_key(|o| (o.date, o.start_time));
+    HttpResponse::Ok().json(occurrences)
+}
+
+#[get("/meeting_agenda/{id}/exceptions")]
+pub async fn list_meeting_exceptions(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    match db::list_meeting_exceptions(&db_pool, path.into_inner()).await {
+        Ok(exceptions) => HttpResponse::Ok().json(exceptions),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Cancels one occurrence, or moves it to another date and/or time
+#[post("/meeting_agenda/{id}/exceptions")]
+pub async fn set_meeting_exception(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    item: web::Json<MeetingException>,
+) -> impl Responder {
+    let agenda_id = path.into_inner();
+    if let Err(e) = check_meeting_perm(&db_pool, &session, agenda_id).await {
+        return e;
+    }
+    let agenda = match db::get_meeting_agenda_by_id(&db_pool, agenda_id).await {
+        Ok(agenda) => agenda,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Meeting agenda not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let mut exception = item.into_inner();
+    exception.agenda_id = agenda_id;
+    if !recurrence::is_occurrence(&agenda, exception.date) {
+        return HttpResponse::BadRequest().json(json!({ "error": "The agenda has no meeting on this date" }));
+    }
+    if exception.cancelled != 0 {
+        exception.cancelled = 1;
+        exception.new_date = None;
+        exception.start_time = None;
+        exception.end_time = None;
+    } else if let Some(moved) = recurrence::apply_exception(&agenda, &exception) {
+        if moved.end_time <= moved.start_time {
+            return HttpResponse::BadRequest().json(json!({ "error": "End time must be after start time" }));
+        }
+        match db::find_meeting_conflict(&db_pool, agenda.room_id, agenda_id, &[moved], !is_meeting_manager(&session)).await {
+            Ok(Some((conflict, occurrence))) => return conflict_response(conflict, occurrence),
+            Ok(None) => {}
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        }
+    }
+    match db::set_meeting_exception(&db_pool, exception).await {
+        Ok(exception) => HttpResponse::Ok().json(exception),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Restores the occurrence as the series defines it
+#[delete("/meeting_agenda/{id}/exceptions/{date}")]
+pub async fn delete_meeting_exception(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<(i64, NaiveDate)>,
+) -> impl Responder {
+    let (agenda_id, date) = path.into_inner();
+    if let Err(e) = check_meeting_perm(&db_pool, &session, agenda_id).await {
+        return e;
+    }
+    let agenda = match db::get_meeting_agenda_by_id(&db_pool, agenda_id).await {
+        Ok(agenda) => agenda,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Meeting agenda not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let restored = recurrence::expand(&agenda, &[], date, date);
+    match db::find_meeting_conflict(&db_pool, agenda.room_id, agenda_id, &restored, !is_meeting_manager(&session)).await {
+        Ok(Some((conflict, occurrence))) => return conflict_response(conflict, occurrence),
+        Ok(None) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::delete_meeting_exception(&db_pool, agenda_id, date).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Meeting exception deleted" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Meeting exception not found" })),
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -182,11 +669,19 @@ pub fn init_meeting_routes(cfg: &mut web::ServiceConfig) {
 
 pub fn init_agenda_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(create_meeting_agenda)
+       .service(list_pending_meeting_agendas)
        .service(list_meeting_agendas)
+       .service(list_room_occurrences)
+       .service(list_meeting_exceptions)
+       .service(set_meeting_exception)
+       .service(delete_meeting_exception)
        .service(list_meeting_rooms)
+       .service(search_meeting_rooms)
        .service(get_meeting_agenda)
        .service(update_meeting_agenda)
        .service(confirm_meeting_agenda)
+       .service(reject_meeting_agenda)
+       .service(cancel_meeting_agenda)
        .service(delete_meeting_agenda);
 }
 
@@ -11,5 +11,16 @@ pub mod studentlog;
 pub mod subschedule;
 pub mod timeline;
 pub mod equipment;
+pub mod custody;
+pub mod maintenance;
 pub mod meeting;
+pub mod calendar;
 pub mod linux;
+pub mod labsession;
+pub mod analytics;
+pub mod progress;
+pub mod assignment;
+pub mod storedfile;
+pub mod notification;
+pub mod similarity;
+pub mod stocktake;
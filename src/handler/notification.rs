@@ -0,0 +1,62 @@
+use actix_session::Session;
+use actix_web::{get, put, web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::db;
+
+#[derive(Deserialize)]
+pub struct NotificationQuery {
+    #[serde(default)]
+    pub unread: bool,
+}
+
+#[get("/notifications")]
+pub async fn list_notifications(
+    db_pool: web::Data<SqlitePool>,
+    query: web::Query<NotificationQuery>,
+    session: Session,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let unread = match db::count_unread_notifications(&db_pool, &user_id).await {
+        Ok(count) => count,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::list_notifications(&db_pool, &user_id, query.unread).await {
+        Ok(items) => HttpResponse::Ok().json(json!({ "unread": unread, "items": items })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/notifications/{id}/read")]
+pub async fn mark_notification_read(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::mark_notification_read(&db_pool, path.into_inner(), &user_id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Notification marked as read" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Notification not found" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/notifications/read")]
+pub async fn mark_all_notifications_read(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::mark_all_notifications_read(&db_pool, &user_id).await {
+        Ok(count) => HttpResponse::Ok().json(json!({ "marked": count })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub fn init_notification_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_notifications)
+        .service(mark_notification_read)
+        .service(mark_all_notifications_read);
+}
@@ -0,0 +1,193 @@
+use actix_session::Session;
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+use crate::db;
+use crate::models::{STEP_REJECTED, STEP_VERIFIED};
+use crate::utils::check_subcourse_perm;
+
+#[derive(Deserialize)]
+pub struct MarkStepRequest {
+    pub subcourse_id: i64,
+    pub subschedule_id: i64,
+    pub timeline_id: Option<i64>,
+}
+
+// The step must belong to a schedule of the subcourse's course
+async fn check_step_in_subcourse(
+    db_pool: &SqlitePool,
+    subcourse_id: i64,
+    subschedule_id: i64,
+) -> Result<(), HttpResponse> {
+    let step = match db::get_subschedule_by_id(db_pool, subschedule_id).await {
+        Ok(step) => step,
+        Err(sqlx::Error::RowNotFound) => return Err(HttpResponse::NotFound().json(json!({ "error": "Step not found" }))),
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    };
+    check_schedule_in_subcourse(db_pool, subcourse_id, step.schedule_id).await
+}
+
+async fn check_schedule_in_subcourse(
+    db_pool: &SqlitePool,
+    subcourse_id: i64,
+    schedule_id: i64,
+) -> Result<(), HttpResponse> {
+    let schedule = match db::get_schedule_by_id(db_pool, schedule_id).await {
+        Ok(schedule) => schedule,
+        Err(sqlx::Error::RowNotFound) => return Err(HttpResponse::NotFound().json(json!({ "error": "Schedule not found" }))),
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    };
+    match db::get_subcourse_by_id(db_pool, subcourse_id).await {
+        Ok(subcourse) if subcourse.course_id == schedule.course_id => Ok(()),
+        Ok(_) => Err(HttpResponse::BadRequest().json(json!({ "error": "Schedule does not belong to this subcourse" }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[post("/progress")]
+pub async fn mark_step(
+    db_pool: web::Data<SqlitePool>,
+    item: web::Json<MarkStepRequest>,
+    session: Session,
+) -> impl Responder {
+    let req = item.into_inner();
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    if db::get_student_seat(&db_pool, &user_id, req.subcourse_id).await.is_err() {
+        return HttpResponse::Forbidden().json(json!({ "error": "Not enrolled in this subcourse" }));
+    }
+    if let Err(err) = check_step_in_subcourse(&db_pool, req.subcourse_id, req.subschedule_id).await {
+        return err;
+    }
+    if let Some(timeline_id) = req.timeline_id {
+        match db::get_timeline_by_id(&db_pool, timeline_id).await {
+            Ok(tl) if tl.stu_id == user_id && tl.subcourse_id == req.subcourse_id => {}
+            Ok(_) => return HttpResponse::Forbidden().json(json!({ "error": "Not your timeline entry" })),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        }
+    }
+    match db::mark_step_progress(&db_pool, &user_id, req.subcourse_id, req.subschedule_id, req.timeline_id).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::BadRequest().json(json!({ "error": "Step already verified" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/progress/{subcourse_id}/{schedule_id}/{stu_id}")]
+pub async fn list_student_progress(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<(i64, i64, String)>,
+    session: Session,
+) -> impl Responder {
+    let (subcourse_id, schedule_id, stu_id) = path.into_inner();
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    // Other students' progress is only visible to the subcourse's teacher
+    if user_id != stu_id {
+        if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+            return err;
+        }
+    }
+    match db::list_student_step_progress(&db_pool, &stu_id, subcourse_id, schedule_id).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReviewStepRequest {
+    pub tea_note: String,
+}
+
+async fn review_step(
+    db_pool: &web::Data<SqlitePool>,
+    session: &Session,
+    id: i64,
+    status: i64,
+    tea_note: &str,
+) -> HttpResponse {
+    let progress = match db::get_step_progress_by_id(db_pool, id).await {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_subcourse_perm(db_pool, session, progress.subcourse_id).await {
+        return err;
+    }
+    match db::review_step_progress(db_pool, id, status, tea_note).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/progress/{id}/verify")]
+pub async fn verify_step(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<ReviewStepRequest>,
+    session: Session,
+) -> impl Responder {
+    review_step(&db_pool, &session, path.into_inner(), STEP_VERIFIED, &item.tea_note).await
+}
+
+#[put("/progress/{id}/reject")]
+pub async fn reject_step(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    item: web::Json<ReviewStepRequest>,
+    session: Session,
+) -> impl Responder {
+    review_step(&db_pool, &session, path.into_inner(), STEP_REJECTED, &item.tea_note).await
+}
+
+// Step x student overview for one schedule of a subcourse: one row per step,
+// one cell per student in roster order, null where nothing was marked
+#[get("/progress/matrix/{subcourse_id}/{schedule_id}")]
+pub async fn progress_matrix(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<(i64, i64)>,
+    session: Session,
+) -> impl Responder {
+    let (subcourse_id, schedule_id) = path.into_inner();
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+        return err;
+    }
+    if let Err(err) = check_schedule_in_subcourse(&db_pool, subcourse_id, schedule_id).await {
+        return err;
+    }
+    let steps = match db::list_subschedules(&db_pool, schedule_id).await {
+        Ok(steps) => steps,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let students = match db::get_group_by_subcourse_id(&db_pool, subcourse_id).await {
+        Ok(students) => students,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let progress = match db::list_step_progress_by_schedule(&db_pool, subcourse_id, schedule_id).await {
+        Ok(progress) => progress,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let cells: HashMap<(i64, &str), _> = progress
+        .iter()
+        .map(|p| ((p.subschedule_id, p.stu_id.as_str()), p))
+        .collect();
+    let rows: Vec<_> = steps
+        .iter()
+        .map(|step| {
+            let row: Vec<_> = students
+                .iter()
+                .map(|stu| cells.get(&(step.id, stu.stu_id.as_str())))
+                .collect();
+            json!({ "step": step, "cells": row })
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "students": students,
+        "rows": rows,
+    }))
+}
+
+pub fn init_progress_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(mark_step)
+        .service(list_student_progress);
+}
@@ -0,0 +1,154 @@
+use actix_session::Session;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+
+use crate::db;
+use crate::models::{SimilarityCandidate, FINGERPRINT_IMAGE, FINGERPRINT_TEXT};
+use crate::similarity;
+use crate::storage::Storage;
+use crate::utils::check_course_perm;
+
+#[derive(Deserialize)]
+pub struct SimilarityQuery {
+    pub threshold: Option<f64>,       // Minimum text similarity (Jaccard), default 0.4
+    pub image_threshold: Option<f64>, // Minimum image hash similarity, default 0.9
+}
+
+#[derive(Serialize)]
+pub struct SimilarPair {
+    pub kind: &'static str, // "identical", "text" or "image"
+    pub similarity: f64,
+    pub shared: usize, // Shared text fingerprints, 0 for images
+    pub a: SimilarityCandidate,
+    pub b: SimilarityCandidate,
+}
+
+const MAX_PAIRS: usize = 500;
+
+enum Print {
+    Text(Vec<u64>),
+    Image(u64),
+    None,
+}
+
+fn compare(
+    candidates: Vec<SimilarityCandidate>,
+    mut prints: HashMap<i64, Print>,
+    threshold: f64,
+    image_threshold: f64,
+) -> Vec<SimilarPair> {
+    // Template code handed out by the teacher shows up in most students' work
+    let students = candidates.iter().map(|c| c.stu_id.as_str()).collect::<HashSet<_>>().len();
+    let common = similarity::common_hashes(
+        candidates.iter().filter_map(|c| match prints.get(&c.file_id) {
+            Some(Print::Text(hashes)) => Some((c.stu_id.as_str(), hashes.as_slice())),
+            _ => None,
+        }),
+        (students / 2).max(2),
+    );
+    for print in prints.values_mut() {
+        if let Print::Text(hashes) = print {
+            hashes.retain(|h| !common.contains(h));
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (i, a) in candidates.iter().enumerate() {
+        for b in &candidates[i + 1..] {
+            if a.stu_id == b.stu_id {
+                continue;
+            }
+            let found = if a.sha256 == b.sha256 {
+                Some(("identical", 1.0, 0))
+            } else {
+                match (prints.get(&a.file_id), prints.get(&b.file_id)) {
+                    (Some(Print::Text(x)), Some(Print::Text(y))) => {
+                        let (sim, shared) = similarity::text_similarity(x, y);
+                        (sim >= threshold).then_some(("text", sim, shared))
+                    }
+                    (Some(Print::Image(x)), Some(Print::Image(y))) => {
+                        let sim = similarity::image_similarity(*x, *y);
+                        (sim >= image_threshold).then_some(("image", sim, 0))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some((kind, similarity, shared)) = found {
+                pairs.push((kind, similarity, shared, a, b));
+            }
+        }
+    }
+    pairs.sort_by(|x, y| y.1.total_cmp(&x.1));
+    pairs.truncate(MAX_PAIRS);
+    pairs
+        .into_iter()
+        .map(|(kind, similarity, shared, a, b)| SimilarPair { kind, similarity, shared, a: a.clone(), b: b.clone() })
+        .collect()
+}
+
+// Suspiciously similar pairs among one schedule's uploads, across every subcourse of the course
+#[get("/similarity/schedule/{schedule_id}")]
+pub async fn schedule_similarity(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<i64>,
+    query: web::Query<SimilarityQuery>,
+    session: Session,
+) -> impl Responder {
+    let schedule_id = path.into_inner();
+    let schedule = match db::get_schedule_by_id(&db_pool, schedule_id).await {
+        Ok(schedule) => schedule,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_course_perm(&db_pool, &session, schedule.course_id).await {
+        return err;
+    }
+    let threshold = query.threshold.unwrap_or(0.4).clamp(0.0, 1.0);
+    let image_threshold = query.image_threshold.unwrap_or(0.9).clamp(0.0, 1.0);
+
+    let candidates = match db::list_similarity_candidates(&db_pool, schedule_id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    // Fingerprints are normally computed on upload; older files are filled in here
+    let mut prints = HashMap::new();
+    let mut unreadable = 0;
+    for c in &candidates {
+        if prints.contains_key(&c.file_id) {
+            continue;
+        }
+        let print = match similarity::ensure_fingerprint(storage.as_ref(), &db_pool, c.file_id).await {
+            Ok(fp) if fp.kind == FINGERPRINT_TEXT => Print::Text(similarity::text_hashes(&fp)),
+            Ok(fp) if fp.kind == FINGERPRINT_IMAGE => similarity::image_hash(&fp).map_or(Print::None, Print::Image),
+            Ok(_) => Print::None,
+            Err(_) => {
+                unreadable += 1;
+                Print::None
+            }
+        };
+        prints.insert(c.file_id, print);
+    }
+
+    let files = candidates.len();
+    let compared = prints.values().filter(|p| !matches!(p, Print::None)).count();
+    match web::block(move || compare(candidates, prints, threshold, image_threshold)).await {
+        Ok(pairs) => HttpResponse::Ok().json(json!({
+            "schedule_id": schedule_id,
+            "course_id": schedule.course_id,
+            "threshold": threshold,
+            "image_threshold": image_threshold,
+            "files": files,
+            "compared": compared,
+            "unreadable": unreadable,
+            "pairs": pairs,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub fn init_similarity_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(schedule_similarity);
+}
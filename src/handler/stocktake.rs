@@ -0,0 +1,306 @@
+use actix_session::Session;
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+use crate::db;
+use crate::models::{Stocktake, StocktakeItem, STOCK_FOUND, STOCK_MISSING, STOCK_MOVED, STOCK_UNKNOWN};
+use crate::spreadsheet;
+use crate::utils::attachment;
+
+#[derive(Deserialize)]
+pub struct NewStocktake {
+    pub name: String,
+    pub positions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScanRequest {
+    pub serial: String,
+    pub position: String, // Where the item was actually found
+}
+
+#[derive(Deserialize)]
+pub struct MissingRequest {
+    pub serial: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReportParams {
+    pub format: Option<String>, // json (default), csv or xlsx
+}
+
+// Loads the stocktake and refuses changes once it has been closed
+async fn open_stocktake(pool: &SqlitePool, id: i64) -> Result<Stocktake, HttpResponse> {
+    match db::get_stocktake(pool, id).await {
+        Ok(st) if st.closed_at.is_some() => {
+            Err(HttpResponse::Conflict().json(json!({ "error": "Stocktake is already closed" })))
+        }
+        Ok(st) => Ok(st),
+        Err(sqlx::Error::RowNotFound) => Err(HttpResponse::NotFound().json(json!({ "error": "Stocktake not found" }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+}
+
+#[post("/stocktake")]
+pub async fn create_stocktake(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    body: web::Json<NewStocktake>,
+) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let name = body.name.trim();
+    let positions: Vec<String> = body
+        .positions
+        .iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if name.is_empty() || positions.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "A name and at least one position are required" }));
+    }
+    match db::add_stocktake(&db_pool, name, &user_id, &positions).await {
+        Ok(st) => HttpResponse::Created().json(st),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/stocktake")]
+pub async fn list_stocktakes(db_pool: web::Data<SqlitePool>) -> impl Responder {
+    match db::list_stocktakes(&db_pool).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/stocktake/{id}")]
+pub async fn get_stocktake(db_pool: web::Data<SqlitePool>, path: web::Path<i64>) -> impl Responder {
+    let id = path.into_inner();
+    let stocktake = match db::get_stocktake(&db_pool, id).await {
+        Ok(st) => st,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Stocktake not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let positions = match db::list_stocktake_positions(&db_pool, id).await {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let items = match db::list_stocktake_items(&db_pool, id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    HttpResponse::Ok().json(json!({ "stocktake": stocktake, "positions": positions, "items": items }))
+}
+
+// Records a scanned serial, comparing where it was found with where it is registered
+#[post("/stocktake/{id}/scan")]
+pub async fn scan_stocktake_item(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<ScanRequest>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(err) = open_stocktake(&db_pool, id).await {
+        return err;
+    }
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let serial = body.serial.trim();
+    let position = body.position.trim();
+    if serial.is_empty() || position.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Serial and position are required" }));
+    }
+    let equipment = match db::get_equipment_by_serial(&db_pool, serial).await {
+        Ok(e) => e,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let (equipment_id, status, found_position) = match &equipment {
+        None => (None, STOCK_UNKNOWN, Some(position.to_string())),
+        Some(e) if e.position == position => (Some(e.id), STOCK_FOUND, None),
+        Some(e) => (Some(e.id), STOCK_MOVED, Some(position.to_string())),
+    };
+    let item = StocktakeItem {
+        id: 0,
+        stocktake_id: id,
+        serial: serial.to_string(),
+        equipment_id,
+        status,
+        found_position,
+        checked_by: user_id,
+        checked_at: Local::now().naive_local(),
+    };
+    match db::record_stocktake_item(&db_pool, item).await {
+        Ok(item) => HttpResponse::Ok().json(json!({ "item": item, "equipment": equipment })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/stocktake/{id}/missing")]
+pub async fn mark_stocktake_missing(
+    db_pool: web::Data<SqlitePool>,
+    session: Session,
+    path: web::Path<i64>,
+    body: web::Json<MissingRequest>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(err) = open_stocktake(&db_pool, id).await {
+        return err;
+    }
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let equipment = match db::get_equipment_by_serial(&db_pool, body.serial.trim()).await {
+        Ok(Some(e)) => e,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "error": "Equipment not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let item = StocktakeItem {
+        id: 0,
+        stocktake_id: id,
+        serial: equipment.serial,
+        equipment_id: Some(equipment.id),
+        status: STOCK_MISSING,
+        found_position: None,
+        checked_by: user_id,
+        checked_at: Local::now().naive_local(),
+    };
+    match db::record_stocktake_item(&db_pool, item).await {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/stocktake/{id}/close")]
+pub async fn close_stocktake(db_pool: web::Data<SqlitePool>, session: Session, path: web::Path<i64>) -> impl Responder {
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::close_stocktake(&db_pool, path.into_inner(), &user_id).await {
+        Ok(st) => HttpResponse::Ok().json(st),
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Discrepancies between what was checked and what the equipments table expects.
+// Items expected but not yet checked are listed as unchecked until the stocktake closes.
+#[get("/stocktake/{id}/report")]
+pub async fn stocktake_report(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    web::Query(params): web::Query<ReportParams>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let stocktake = match db::get_stocktake(&db_pool, id).await {
+        Ok(st) => st,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Stocktake not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let expected = match db::list_stocktake_expected(&db_pool, id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let checked = match db::list_stocktake_items(&db_pool, id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+
+    let by_serial: HashMap<&str, &StocktakeItem> = checked.iter().map(|i| (i.serial.as_str(), i)).collect();
+    // Moved items may come from positions outside the stocktake, so look them up too
+    let mut registered = HashMap::new();
+    for item in &checked {
+        if let Some(eid) = item.equipment_id {
+            if let Some(e) = expected.iter().find(|e| e.id == eid) {
+                registered.insert(eid, (e.name.clone(), e.position.clone(), e.value));
+            } else if let Ok(e) = db::get_equipment_by_id(&db_pool, eid).await {
+                registered.insert(eid, (e.name, e.position, e.value));
+            }
+        }
+    }
+
+    // Rows: result, serial, name, registered position, found position, value, checked by
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut missing_value = 0;
+    for item in &checked {
+        let result = match item.status {
+            STOCK_FOUND => "found",
+            STOCK_MOVED => "moved",
+            STOCK_MISSING => "missing",
+            STOCK_UNKNOWN => "unknown",
+            _ => "other",
+        };
+        let (name, position, value) = item
+            .equipment_id
+            .and_then(|eid| registered.get(&eid).cloned())
+            .map(|(n, p, v)| (n, p, Some(v)))
+            .unwrap_or_default();
+        if item.status == STOCK_MISSING {
+            missing_value += value.unwrap_or(0);
+        }
+        rows.push(vec![
+            result.to_string(),
+            item.serial.clone(),
+            name,
+            position,
+            item.found_position.clone().unwrap_or_default(),
+            value.map(|v| v.to_string()).unwrap_or_default(),
+            item.checked_by.clone(),
+        ]);
+    }
+    let unchecked: Vec<_> = expected.iter().filter(|e| !by_serial.contains_key(e.serial.as_str())).collect();
+    for e in &unchecked {
+        rows.push(vec![
+            "unchecked".to_string(),
+            e.serial.clone(),
+            e.name.clone(),
+            e.position.clone(),
+            String::new(),
+            e.value.to_string(),
+            String::new(),
+        ]);
+    }
+
+    let header = ["result", "serial", "name", "position", "found_position", "value", "checked_by"];
+    let fname = format!("stocktake-{}", id);
+    let written = match params.format.as_deref() {
+        None | Some("json") => {
+            let count = |s: i64| checked.iter().filter(|i| i.status == s).count();
+            let pick = |s: i64| checked.iter().filter(|i| i.status == s).collect::<Vec<_>>();
+            return HttpResponse::Ok().json(json!({
+                "stocktake": stocktake,
+                "expected": expected.len(),
+                "found": count(STOCK_FOUND),
+                "moved": count(STOCK_MOVED),
+                "missing": count(STOCK_MISSING),
+                "unknown": count(STOCK_UNKNOWN),
+                "unchecked": unchecked.len(),
+                "missing_value": missing_value,
+                "discrepancies": {
+                    "moved": pick(STOCK_MOVED),
+                    "missing": pick(STOCK_MISSING),
+                    "unknown": pick(STOCK_UNKNOWN),
+                    "unchecked": unchecked,
+                },
+            }));
+        }
+        Some("csv") => spreadsheet::write_csv(&header, &rows)
+            .map(|b| (b, "text/csv; charset=utf-8", format!("{}.csv", fname))),
+        Some("xlsx") => spreadsheet::write_xlsx(&header, &rows, &[5]).map(|b| {
+            (b, "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", format!("{}.xlsx", fname))
+        }),
+        Some(other) => return HttpResponse::BadRequest().json(json!({ "error": format!("Unknown format {}", other) })),
+    };
+    match written {
+        Ok((body, mime, fname)) => HttpResponse::Ok().content_type(mime).insert_header(attachment(&fname)).body(body),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e })),
+    }
+}
+
+pub fn init_stocktake_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_stocktake)
+        .service(list_stocktakes)
+        .service(get_stocktake)
+        .service(scan_stocktake_item)
+        .service(mark_stocktake_missing)
+        .service(close_stocktake)
+        .service(stocktake_report);
+}
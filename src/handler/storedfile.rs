@@ -0,0 +1,36 @@
+use actix_web::{get, put, web, HttpResponse, Responder};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::config::Config;
+use crate::db;
+use crate::scanner::Scanner;
+use crate::storage::{self, Storage};
+
+// Quarantined uploads and those the scanner could not check
+#[get("/storedfile/flagged")]
+pub async fn list_flagged_files(db_pool: web::Data<SqlitePool>) -> impl Responder {
+    match db::list_flagged_stored_files(&db_pool).await {
+        Ok(recs) => HttpResponse::Ok().json(recs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[put("/storedfile/{id}/rescan")]
+pub async fn rescan_stored_file(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    scanner: web::Data<dyn Scanner>,
+    config: web::Data<Config>,
+    path: web::Path<i64>,
+) -> impl Responder {
+    match storage::rescan_file(storage.as_ref(), scanner.as_ref(), &db_pool, &config.upload_tmp, path.into_inner()).await {
+        Ok(rec) => HttpResponse::Ok().json(rec),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub fn init_stored_file_adminroutes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_flagged_files)
+        .service(rescan_stored_file);
+}
@@ -1,31 +1,46 @@
-use actix_web::{post, put, web, get, HttpResponse, Responder};
+use actix_web::{post, put, web, get, HttpRequest, HttpResponse, Responder};
 use actix_session::Session;
 use serde_json::json;
 use sqlx::SqlitePool;
 use serde::Deserialize;
+use crate::checkin;
 use crate::db;
+use crate::config::Config;
+use crate::utils::client_ip;
 use crate::models::StudentLog;
 use chrono::NaiveDateTime;
 
 pub fn check_stu_id(
     session: &Session,
     stu_id: &String,
-) -> Result<(), HttpResponse> {
+) -> Result<(), Box<HttpResponse>> {
     let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
     if user_id != *stu_id {
-        return Err(HttpResponse::Forbidden().json(json!({"error": "Can't use a different ID"})));
+        return Err(Box::new(HttpResponse::Forbidden().json(json!({"error": "Can't use a different ID"}))));
     }
     Ok(())
 }
 
+#[derive(Deserialize)]
+pub struct CheckinLogRequest {
+    #[serde(flatten)]
+    pub log: StudentLog,
+    pub checkin_code: String,
+}
+
 #[post("/student_log")]
 pub async fn create_student_log(
     db_pool: web::Data<SqlitePool>,
-    item: web::Json<StudentLog>,
+    item: web::Json<CheckinLogRequest>,
+    config: web::Data<Config>,
     session: Session,
+    req: HttpRequest,
 ) -> impl Responder {
-    let mut log = item.into_inner();
+    let CheckinLogRequest { mut log, checkin_code } = item.into_inner();
     if let Err(err) = check_stu_id(&session, &log.stu_id) {
+        return *err;
+    }
+    if let Err(err) = check_checkin(&db_pool, log.subcourse_id, &checkin_code, &client_ip(&req, &config)).await {
         return err;
     }
     log.confirm = 0; // make sure it's not confirmed.
@@ -35,6 +50,35 @@ pub async fn create_student_log(
     }
 }
 
+// The student must be in the lab room and copy the code shown on the teacher's screen
+async fn check_checkin(
+    db_pool: &SqlitePool,
+    subcourse_id: i64,
+    code: &str,
+    ip: &str,
+) -> Result<(), HttpResponse> {
+    let lab_session = match db::get_open_lab_session(db_pool, subcourse_id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return Err(HttpResponse::BadRequest().json(json!({ "error": "No open lab session" }))),
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    };
+    let labroom = match db::get_subcourse_by_id(db_pool, subcourse_id).await {
+        Ok(sub) => db::get_labroom_by_id(db_pool, sub.room_id).await,
+        Err(e) => Err(e),
+    };
+    match labroom {
+        Ok(room) if !checkin::ip_allowed(&room.allowed_ips, ip) => {
+            return Err(HttpResponse::Forbidden().json(json!({ "error": "Check in from the lab room network" })));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
+    }
+    if !checkin::verify_code(&lab_session.secret, code) {
+        return Err(HttpResponse::Forbidden().json(json!({ "error": "Invalid or expired check-in code" })));
+    }
+    Ok(())
+}
+
 #[put("/student_log/{id}")]
 pub async fn update_student_log(
     db_pool: web::Data<SqlitePool>,
@@ -45,7 +89,7 @@ pub async fn update_student_log(
     let id = path.into_inner();
     let newlog = item.into_inner();
     if let Err(err) = check_stu_id(&session, &newlog.stu_id) {
-        return err;
+        return *err;
     }
     if let Ok(log) = db::get_student_log_by_id(&db_pool, id).await {
         if newlog.stu_id != log.stu_id {
@@ -56,7 +100,7 @@ pub async fn update_student_log(
             Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
         }
     } else {
-        return HttpResponse::Forbidden().json(json!({"error": "No log found."}));
+        HttpResponse::Forbidden().json(json!({"error": "No log found."}))
     }
 }
 
@@ -105,14 +149,31 @@ pub async fn force_student_log(
 ) -> impl Responder {
     let (subcourse_id, stu_id) = path.into_inner();
     let realname: String = session.get::<String>("realname").ok().flatten().unwrap_or_default();
-    if let Ok(mut log) = db::get_default_log(&db_pool, &stu_id, subcourse_id).await {
+    // Teachers may still fix attendance once the session is closed, so fall
+    // back to the most recent session rather than requiring an open one
+    let lab_session = match db::get_latest_lab_session(&db_pool, subcourse_id).await {
+        Ok(Some(lab_session)) => lab_session,
+        Ok(None) => return HttpResponse::BadRequest().json(json!({ "error": "No lab session for this subcourse" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Ok(mut log) = db::get_session_default_log(&db_pool, &stu_id, &lab_session).await {
+        if log.id != 0 {
+            // Already logged in this session: confirming it is all that is left to do
+            return match db::confirm_student_log(&db_pool, log.id, "Log by T", &realname).await {
+                Ok(_) => match db::get_student_log_by_id(&db_pool, log.id).await {
+                    Ok(log) => HttpResponse::Ok().json(log),
+                    Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+                },
+                Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+            };
+        }
         if let Ok(stu_name) = db::get_student_name(&db_pool, &stu_id, subcourse_id).await {
             log.stu_name = stu_name;
         }
         log.confirm = 1;
         log.tea_name = realname;
         log.tea_note = "Log by T".to_string();
-        match db::add_student_log(&db_pool, log).await {
+        match db::add_session_student_log(&db_pool, log, &lab_session).await {
             Ok(log) => HttpResponse::Ok().json(log),
             Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
         }
@@ -3,31 +3,41 @@ use actix_web::{post, delete, get, web, HttpResponse, Responder, HttpRequest};
 use actix_multipart::Multipart;
 use actix_files::NamedFile;
 use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::SqlitePool;
-use std::path::{Path, PathBuf};
-use std::fs;
-use std::io::Write;
+use std::io::ErrorKind;
 
-use crate::config::{PERMISSION_TEACHER, PERMISSION_ADMIN, PERMISSION_STUDENT};
-use crate::models::StudentTimeline;
+use crate::config::{Config, PERMISSION_TEACHER, PERMISSION_ADMIN, PERMISSION_STUDENT};
+use crate::markdown;
+use crate::models::{StudentTimeline, TimelineComment, TimelineFeedback, NOTETYPE_FILE, NOTETYPE_MARKDOWN, SCAN_CLEAN};
+use crate::models::{FEEDBACK_APPROVED, FEEDBACK_CHANGES_REQUESTED, FEEDBACK_COMMENT};
+use crate::preview::{self, Derivative};
 use crate::db;
+use crate::scanner::Scanner;
+use crate::similarity;
+use crate::storage::{self, Storage};
+use crate::utils::{attachment, check_course_mime, check_subcourse_perm, check_quota, check_scan, serve_stored_file, upload_error};
 
 #[post("/timeline")]
 pub async fn create_timeline(
     db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    scanner: web::Data<dyn Scanner>,
+    config: web::Data<Config>,
     mut payload: Multipart,
     session: Session,
 ) -> impl Responder {
     let mut stu_id = None;
-    let mut tea_id = None;
     let mut schedule_id = None;
     let mut subschedule = None;
     let mut subcourse_id = None;
+    let mut subschedule_id = None;
     let mut note_type = None;
 
     let mut note_filename = None;
-    let mut file_bytes = vec![];
+    let mut uploads = Vec::new();
+    let mut body = None;
     let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
     let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
 
@@ -38,39 +48,48 @@ pub async fn create_timeline(
         match name {
             "file" => {
                 let original_filename = content_disposition.get_filename().unwrap_or("unnamed").to_string();
-                note_filename = Some(original_filename.clone());
-
-                while let Some(chunk) = field.try_next().await.unwrap() {
-                    file_bytes.extend_from_slice(&chunk);
+                match storage::receive_upload(&mut field, &config.upload_tmp, &original_filename, config.upload_limit_timeline).await {
+                    Ok(received) if uploads.len() < MAX_ATTACHMENTS => uploads.push(received),
+                    Ok(_) => return HttpResponse::BadRequest().json(json!({ "error": "Too many attachments" })),
+                    Err(e) => return upload_error(e),
                 }
             }
+            "body" => match read_text(&mut field, markdown::MAX_BODY_BYTES).await {
+                Ok(text) => body = Some(text),
+                Err(e) => return upload_error(e),
+            },
             "stu_id" => {
-                let data = field.try_next().await.unwrap().unwrap();
-                stu_id = Some(String::from_utf8_lossy(&data).to_string());
-            }
-            "tea_id" => {
-                let data = field.try_next().await.unwrap().unwrap();
-                tea_id = Some(String::from_utf8_lossy(&data).to_string());
+                if let Ok(Some(data)) = field.try_next().await {
+                    stu_id = Some(String::from_utf8_lossy(&data).to_string());
+                }
             }
             "schedule_id" => {
-                let data = field.try_next().await.unwrap().unwrap();
-                schedule_id = Some(String::from_utf8_lossy(&data).parse::<i64>().unwrap_or(0));
+                if let Ok(Some(data)) = field.try_next().await {
+                    schedule_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+                }
             }
             "subschedule" => {
-                let data = field.try_next().await.unwrap().unwrap();
-                subschedule = Some(String::from_utf8_lossy(&data).to_string());
+                if let Ok(Some(data)) = field.try_next().await {
+                    subschedule = Some(String::from_utf8_lossy(&data).to_string());
+                }
+            }
+            "subschedule_id" => {
+                if let Ok(Some(data)) = field.try_next().await {
+                    subschedule_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+                }
             }
             "subcourse_id" => {
-                let data = field.try_next().await.unwrap().unwrap();
-                subcourse_id = Some(String::from_utf8_lossy(&data).parse::<i64>().unwrap_or(0));
+                if let Ok(Some(data)) = field.try_next().await {
+                    subcourse_id = String::from_utf8_lossy(&data).parse::<i64>().ok();
+                }
             }
             "notetype" => {
-                let data = field.try_next().await.unwrap().unwrap();
-                note_type = Some(String::from_utf8_lossy(&data).parse::<i64>().unwrap_or(0));
+                if let Ok(Some(data)) = field.try_next().await {
+                    note_type = String::from_utf8_lossy(&data).parse::<i64>().ok();
+                }
             }
-            "note" => {
-                if note_type.unwrap_or(0) != 1 {
-                    let data = field.try_next().await.unwrap().unwrap();
+            "note" if note_type.unwrap_or(0) != NOTETYPE_FILE => {
+                if let Ok(Some(data)) = field.try_next().await {
                     note_filename = Some(String::from_utf8_lossy(&data).to_string());
                 }
             }
@@ -81,7 +100,7 @@ pub async fn create_timeline(
         if (permission & PERMISSION_TEACHER == 0) && stu_id != &user_id {
             return HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }));
         }
-        if let Ok(count) = db::count_student_timeline_entries(&db_pool, &stu_id, schedule_id).await {
+        if let Ok(count) = db::count_student_timeline_entries(&db_pool, stu_id, schedule_id).await {
             if count > 100 {
                 return HttpResponse::Unauthorized().json(json!({ "error": "Too many entries." }));
             }
@@ -89,37 +108,82 @@ pub async fn create_timeline(
     } else {
         return HttpResponse::BadRequest().json(json!({ "error": "Missing required parameters" }));
     }
-    // Save file if it's a file note
-    if note_type == Some(1) && note_filename.is_some() && stu_id.is_some() && subcourse_id.is_some() {
-        let upload_dir = format!("uploads/coursetl/{}/{}", subcourse_id.as_ref().unwrap(), stu_id.as_ref().unwrap());
-        fs::create_dir_all(&upload_dir).unwrap();
-
-        let original_name = note_filename.as_ref().unwrap();
-        let mut file_path = PathBuf::from(&upload_dir);
-        file_path.push(original_name);
-
-        let mut final_filename = original_name.clone();
-        let mut counter = 1;
-
-        while file_path.exists() {
-            let stem = Path::new(original_name).file_stem().unwrap().to_string_lossy();
-            let ext = Path::new(original_name).extension().and_then(|e| Some(format!(".{}", e.to_string_lossy()))).unwrap_or_default();
-            final_filename = format!("{}({}){}", stem, counter, ext);
-            file_path = PathBuf::from(&upload_dir);
-            file_path.push(&final_filename);
-            counter += 1;
+    // The step title is taken from the subschedule itself rather than trusted from the form
+    if let Some(sub_id) = subschedule_id {
+        match db::get_subschedule_by_id(&db_pool, sub_id).await {
+            Ok(sub) if Some(sub.schedule_id) == schedule_id => subschedule = Some(sub.title),
+            Ok(_) => return HttpResponse::BadRequest().json(json!({ "error": "Step does not belong to this schedule" })),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
         }
-
-        let mut f = std::fs::File::create(&file_path).unwrap();
-        f.write_all(&file_bytes).unwrap();
-
-        // Update the filename to the final one used
-        note_filename = Some(final_filename);
     }
+    // The reviewing teacher is whoever teaches the subcourse, not whatever the form claims
+    let subcourse = match subcourse_id {
+        Some(id) => match db::get_subcourse_by_id(&db_pool, id).await {
+            Ok(subcourse) => subcourse,
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        },
+        None => return HttpResponse::BadRequest().json(json!({ "error": "Missing required parameters" })),
+    };
+    let tea_id = if permission & PERMISSION_TEACHER != 0 { user_id.clone() } else { subcourse.tea_id.clone() };
+    // A file note carries exactly one file and keeps its original name for display;
+    // a markdown note carries its body plus any number of attachments up to the cap
+    let body = match note_type {
+        Some(NOTETYPE_FILE) => {
+            if uploads.len() != 1 {
+                return HttpResponse::BadRequest().json(json!({ "error": "A file note takes exactly one file" }));
+            }
+            note_filename = Some(uploads[0].fname.clone());
+            String::new()
+        }
+        Some(NOTETYPE_MARKDOWN) => match body {
+            Some(body) if !body.trim().is_empty() => {
+                note_filename = Some(markdown::summary(&body));
+                body
+            }
+            _ => return HttpResponse::BadRequest().json(json!({ "error": "Markdown note needs a body" })),
+        },
+        _ => {
+            uploads.clear();
+            String::new()
+        }
+    };
+    let mut file_ids = Vec::new();
+    if !uploads.is_empty() {
+        let course_id = subcourse.course_id;
+        if permission & PERMISSION_TEACHER == 0 {
+            for upload in &uploads {
+                if let Err(err) = check_course_mime(&db_pool, course_id, upload).await {
+                    return err;
+                }
+            }
+        }
+        for upload in uploads {
+            if let Err(err) = check_quota(&db_pool, &config, &user_id, &upload).await {
+                release_all(storage.as_ref(), &db_pool, &file_ids).await;
+                return err;
+            }
+            match storage::store_upload(storage.as_ref(), scanner.as_ref(), &db_pool, upload, &user_id).await {
+                Ok(stored) => {
+                    file_ids.push(stored.id);
+                    if let Err(err) = check_scan(&stored) {
+                        release_all(storage.as_ref(), &db_pool, &file_ids).await;
+                        return *err;
+                    }
+                }
+                Err(e) => {
+                    release_all(storage.as_ref(), &db_pool, &file_ids).await;
+                    return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+                }
+            }
+        }
+    }
+    let file_id = match note_type {
+        Some(NOTETYPE_FILE) => file_ids.first().copied(),
+        _ => None,
+    };
 
     match (
         stu_id,
-        tea_id,
         schedule_id,
         subschedule,
         subcourse_id,
@@ -128,13 +192,17 @@ pub async fn create_timeline(
     ) {
         (
             Some(stu_id),
-            Some(tea_id),
             Some(schedule_id),
             Some(subschedule),
             Some(subcourse_id),
             Some(note),
             Some(note_type),
         ) => {
+            // Entries posted outside a running session are kept but left unattached
+            let session_id = match db::get_open_lab_session(&db_pool, subcourse_id).await {
+                Ok(session) => session.map(|s| s.id),
+                Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+            };
             let new_timeline = StudentTimeline {
                 id: 0,
                 stu_id,
@@ -145,15 +213,63 @@ pub async fn create_timeline(
                 note,
                 notetype: note_type,
                 timestamp: chrono::Local::now().naive_local(),
+                session_id,
+                subschedule_id,
+                file_id,
+                body,
+                body_html: String::new(),
             };
 
-            match db::add_student_timeline(&db_pool, new_timeline).await {
-                Ok(record) => HttpResponse::Ok().json(record),
-                Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+            let mut record = match db::add_student_timeline(&db_pool, new_timeline).await {
+                Ok(record) => record,
+                Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+            };
+            // Attachment links need the entry id, so the body is rendered once it exists
+            if record.notetype == NOTETYPE_MARKDOWN {
+                if let Err(e) = db::add_timeline_attachments(&db_pool, record.id, &file_ids).await {
+                    return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+                }
+                let count = file_ids.len() as i64;
+                let id = record.id;
+                record.body_html = markdown::render(&record.body, |n| {
+                    (1..=count).contains(&n).then(|| attachment_url(id, n))
+                });
+                if let Err(e) = db::set_timeline_body_html(&db_pool, id, &record.body_html).await {
+                    return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }));
+                }
             }
+            similarity::fingerprint_later(storage.into_inner(), db_pool.get_ref().clone(), file_ids);
+            HttpResponse::Ok().json(record)
+        }
+        _ => {
+            release_all(storage.as_ref(), &db_pool, &file_ids).await;
+            HttpResponse::BadRequest().json(json!({ "error": "Missing or invalid fields" }))
+        }
+    }
+}
+
+const MAX_ATTACHMENTS: usize = 10;
+
+fn attachment_url(timeline_id: i64, position: i64) -> String {
+    format!("/member/timeline/{}/attachment/{}", timeline_id, position)
+}
+
+// Undo stored uploads when the entry itself is refused
+async fn release_all(storage: &dyn Storage, db_pool: &SqlitePool, file_ids: &[i64]) {
+    for &file_id in file_ids {
+        let _ = storage::release_file(storage, db_pool, file_id).await;
+    }
+}
+
+async fn read_text(field: &mut actix_multipart::Field, limit: usize) -> std::io::Result<String> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(|e| std::io::Error::other(e.to_string()))? {
+        if buf.len() + chunk.len() > limit {
+            return Err(std::io::Error::new(ErrorKind::FileTooLarge, "text field too large"));
         }
-        _ => HttpResponse::BadRequest().json(json!({ "error": "Missing or invalid fields" })),
+        buf.extend_from_slice(&chunk);
     }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
 }
 
 async fn check_timeline_permission(
@@ -173,7 +289,7 @@ async fn check_timeline_permission(
     let is_admin = permission & PERMISSION_ADMIN != 0;
 
     if is_student {
-        if let Ok(Some(log)) = db::get_student_log_by_schedule(&db_pool, &timeline.stu_id, timeline.schedule_id).await {
+        if let Ok(Some(log)) = db::get_student_log_by_schedule(db_pool, &timeline.stu_id, timeline.schedule_id).await {
             if log.confirm == 1 {
                 return Err(HttpResponse::Unauthorized().json(json!({ "error": "Can't delete after confirmation." })));
             }
@@ -190,6 +306,7 @@ async fn check_timeline_permission(
 #[delete("/timeline/{id}")]
 pub async fn delete_timeline(
     db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<i64>,
     session: Session,
 ) -> impl Responder {
@@ -197,9 +314,20 @@ pub async fn delete_timeline(
 
     match check_timeline_permission(&db_pool, id, &session).await {
         Ok(timeline) => {
-            if timeline.notetype == 1 {
-                let file_path = format!("uploads/coursetl/{}/{}/{}", timeline.subcourse_id, timeline.stu_id, timeline.note);
-                let _ = std::fs::remove_file(&file_path);
+            if let Some(file_id) = timeline.file_id {
+                let _ = storage::release_file(storage.as_ref(), &db_pool, file_id).await;
+            } else if timeline.notetype == NOTETYPE_MARKDOWN {
+                match db::list_timeline_attachments(&db_pool, id).await {
+                    Ok(attachments) => {
+                        let file_ids: Vec<i64> = attachments.iter().map(|a| a.file_id).collect();
+                        release_all(storage.as_ref(), &db_pool, &file_ids).await;
+                    }
+                    Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+                }
+            } else if timeline.notetype == NOTETYPE_FILE {
+                if let Some(file_path) = storage::legacy_timeline_path(timeline.subcourse_id, &timeline.stu_id, &timeline.note) {
+                    let _ = std::fs::remove_file(&file_path);
+                }
             }
 
             match db::delete_student_timeline(&db_pool, id).await {
@@ -212,6 +340,15 @@ pub async fn delete_timeline(
     }
 }
 
+#[derive(Serialize)]
+pub struct TimelineWithPreview {
+    #[serde(flatten)]
+    pub timeline: StudentTimeline,
+    pub thumb_url: Option<String>,
+    pub preview_url: Option<String>,
+}
+
+// Teachers skim screenshots from the list instead of downloading each file
 #[get("/timeline/schedule/{subcourse_id}/{schedule_id}")]
 pub async fn list_timelines_by_schedule(
     db_pool: web::Data<SqlitePool>,
@@ -219,7 +356,27 @@ pub async fn list_timelines_by_schedule(
 ) -> impl Responder {
     let (subcourse_id, schedule_id) = path.into_inner();
     match db::list_timelines_by_schedule(&db_pool, subcourse_id, schedule_id).await {
-        Ok(items) => HttpResponse::Ok().json(items),
+        Ok(items) => {
+            let mut result = Vec::new();
+            for timeline in items {
+                let previewable = match timeline.file_id {
+                    Some(file_id) => db::get_stored_file_by_id(&db_pool, file_id)
+                        .await
+                        .is_ok_and(|f| f.scan_status == SCAN_CLEAN && preview::can_preview(&f.mime)),
+                    None => false,
+                };
+                let (thumb_url, preview_url) = if previewable {
+                    (
+                        Some(format!("/member/timeline/thumb/{}", timeline.id)),
+                        Some(format!("/member/timeline/preview/{}", timeline.id)),
+                    )
+                } else {
+                    (None, None)
+                };
+                result.push(TimelineWithPreview { timeline, thumb_url, preview_url });
+            }
+            HttpResponse::Ok().json(result)
+        }
         Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
     }
 }
@@ -249,27 +406,430 @@ pub async fn list_timelines_by_student(
 #[get("/timeline/file/{id}")]
 pub async fn download_timeline_file(
     db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<i64>,
     req: HttpRequest,
+    session: Session,
 ) -> impl Responder {
     let id = path.into_inner();
 
-    match db::get_timeline_by_id(&db_pool, id).await {
+    match check_timeline_view(&db_pool, id, &session).await {
+        Ok(StudentTimeline { notetype: 1, file_id: Some(file_id), .. }) => {
+            serve_stored_file(&req, storage.as_ref(), &db_pool, file_id).await
+        }
         Ok(entry) if entry.notetype == 1 => {
-            let file_path = format!("uploads/coursetl/{}/{}/{}", entry.subcourse_id, entry.stu_id, entry.note);
+            let Some(file_path) = storage::legacy_timeline_path(entry.subcourse_id, &entry.stu_id, &entry.note) else {
+                return HttpResponse::BadRequest().json(json!({ "error": "Invalid file name" }));
+            };
             match NamedFile::open_async(&file_path).await {
-                Ok(file) => file.into_response(&req),
+                Ok(file) => file.set_content_disposition(attachment(&entry.note)).into_response(&req),
                 Err(_) => HttpResponse::NotFound().body("File not found"),
             }
         }
         Ok(_) => HttpResponse::BadRequest().json(json!({"error": "This entry does not contain a file."})),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        Err(resp) => resp,
+    }
+}
+
+async fn serve_derivative(
+    db_pool: &web::Data<SqlitePool>,
+    storage: &dyn Storage,
+    session: &Session,
+    id: i64,
+    kind: Derivative,
+) -> HttpResponse {
+    let file_id = match check_timeline_view(db_pool, id, session).await {
+        Ok(StudentTimeline { notetype: 1, file_id: Some(file_id), .. }) => file_id,
+        Ok(_) => return HttpResponse::BadRequest().json(json!({ "error": "This entry has no previewable file." })),
+        Err(resp) => return resp,
+    };
+    match preview::derivative(storage, db_pool, file_id, kind).await {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type("image/jpeg")
+            .insert_header(("Cache-Control", "private, max-age=86400"))
+            .body(bytes),
+        Err(e) => match e.kind() {
+            ErrorKind::Unsupported => HttpResponse::UnsupportedMediaType().json(json!({ "error": e.to_string() })),
+            ErrorKind::PermissionDenied => HttpResponse::Forbidden().json(json!({ "error": e.to_string() })),
+            ErrorKind::InvalidData => HttpResponse::UnprocessableEntity().json(json!({ "error": e.to_string() })),
+            ErrorKind::NotFound => HttpResponse::NotFound().json(json!({ "error": "File not found in storage" })),
+            _ => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        },
+    }
+}
+
+#[get("/timeline/thumb/{id}")]
+pub async fn timeline_thumbnail(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    serve_derivative(&db_pool, storage.as_ref(), &session, path.into_inner(), Derivative::Thumb).await
+}
+
+#[get("/timeline/preview/{id}")]
+pub async fn timeline_preview(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    serve_derivative(&db_pool, storage.as_ref(), &session, path.into_inner(), Derivative::Preview).await
+}
+
+// The owning student and the subcourse's teacher may read an entry's attachments and thread
+async fn check_timeline_view(
+    db_pool: &web::Data<SqlitePool>,
+    id: i64,
+    session: &Session,
+) -> Result<StudentTimeline, HttpResponse> {
+    let timeline = db::get_timeline_by_id(db_pool, id).await.map_err(|e| match e {
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json(json!({ "error": "Timeline not found" })),
+        e => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    })?;
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    if user_id == timeline.stu_id {
+        return Ok(timeline);
+    }
+    if permission & (PERMISSION_TEACHER | PERMISSION_ADMIN) == 0 {
+        return Err(HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" })));
+    }
+    check_subcourse_perm(db_pool, session, timeline.subcourse_id).await?;
+    Ok(timeline)
+}
+
+#[derive(Serialize)]
+pub struct AttachmentResponse {
+    pub position: i64,
+    pub fname: String,
+    pub mime: String,
+    pub fsize: i64,
+    pub url: String,
+}
+
+#[derive(Serialize)]
+pub struct TimelineDetail {
+    #[serde(flatten)]
+    pub timeline: StudentTimeline,
+    pub attachments: Vec<AttachmentResponse>,
+    pub comments: Vec<TimelineComment>,
+}
+
+#[get("/timeline/{id}/detail")]
+pub async fn get_timeline_detail(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let timeline = match check_timeline_view(&db_pool, id, &session).await {
+        Ok(timeline) => timeline,
+        Err(resp) => return resp,
+    };
+    let attachments = match db::list_timeline_attachments(&db_pool, id).await {
+        Ok(items) => items
+            .into_iter()
+            .map(|a| AttachmentResponse {
+                url: attachment_url(id, a.position),
+                position: a.position,
+                fname: a.fname,
+                mime: a.mime,
+                fsize: a.fsize,
+            })
+            .collect(),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    match db::list_timeline_comments(&db_pool, id).await {
+        Ok(comments) => HttpResponse::Ok().json(TimelineDetail { timeline, attachments, comments }),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/timeline/{id}/attachment/{position}")]
+pub async fn download_timeline_attachment(
+    db_pool: web::Data<SqlitePool>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(i64, i64)>,
+    req: HttpRequest,
+    session: Session,
+) -> impl Responder {
+    let (id, position) = path.into_inner();
+    if let Err(resp) = check_timeline_view(&db_pool, id, &session).await {
+        return resp;
+    }
+    match db::get_timeline_attachment(&db_pool, id, position).await {
+        Ok(attachment) => serve_stored_file(&req, storage.as_ref(), &db_pool, attachment.file_id).await,
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(json!({ "error": "Attachment not found" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[get("/timeline/{id}/comments")]
+pub async fn list_timeline_comments(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(resp) = check_timeline_view(&db_pool, id, &session).await {
+        return resp;
+    }
+    match db::list_timeline_comments(&db_pool, id).await {
+        Ok(comments) => HttpResponse::Ok().json(comments),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CommentRequest {
+    pub body: String,
+    pub parent_id: Option<i64>,
+}
+
+// Teachers open threads on an entry; the student who wrote it can only reply
+#[post("/timeline/{id}/comments")]
+pub async fn create_timeline_comment(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    data: web::Json<CommentRequest>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let timeline = match check_timeline_view(&db_pool, id, &session).await {
+        Ok(timeline) => timeline,
+        Err(resp) => return resp,
+    };
+    let data = data.into_inner();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let realname: String = session.get::<String>("realname").ok().flatten().unwrap_or_default();
+
+    if data.body.trim().is_empty() || data.body.len() > markdown::MAX_BODY_BYTES {
+        return HttpResponse::BadRequest().json(json!({ "error": "Comment must be between 1 byte and 64 KB" }));
+    }
+    match data.parent_id {
+        Some(parent_id) => match db::get_timeline_comment(&db_pool, parent_id).await {
+            Ok(parent) if parent.timeline_id == id => {}
+            Ok(_) => return HttpResponse::BadRequest().json(json!({ "error": "Parent comment belongs to another entry" })),
+            Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Parent comment not found" })),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        },
+        None if permission & (PERMISSION_TEACHER | PERMISSION_ADMIN) == 0 => {
+            return HttpResponse::Unauthorized().json(json!({ "error": "Students can only reply to a teacher's comment" }));
+        }
+        None => {}
+    }
+
+    let comment = TimelineComment {
+        id: 0,
+        timeline_id: timeline.id,
+        parent_id: data.parent_id,
+        author_id: user_id,
+        author_name: realname,
+        body_html: markdown::render(&data.body, |_| None),
+        body: data.body,
+        created_at: chrono::Local::now().naive_local(),
+    };
+    let parent_author = match data.parent_id {
+        Some(parent_id) => db::get_timeline_comment(&db_pool, parent_id).await.ok().map(|p| p.author_id),
+        None => None,
+    };
+    match db::add_timeline_comment(&db_pool, comment).await {
+        Ok(comment) => {
+            // The student hears about teacher comments; whoever started a thread hears about replies
+            let link = format!("/member/timeline/{}/detail", id);
+            let message = format!("{} commented on \"{}\"", comment.author_name, timeline.note);
+            for recipient in [Some(timeline.stu_id.clone()), parent_author].into_iter().flatten() {
+                if recipient != comment.author_id {
+                    let _ = db::add_notification(&db_pool, &recipient, "timeline_comment", &message, &link).await;
+                }
+            }
+            HttpResponse::Ok().json(comment)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/timeline/comment/{id}")]
+pub async fn delete_timeline_comment(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::get_timeline_comment(&db_pool, id).await {
+        Ok(comment) if comment.author_id == user_id || permission & PERMISSION_ADMIN != 0 => {}
+        Ok(_) => return HttpResponse::Unauthorized().json(json!({ "error": "Only the author can delete a comment" })),
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Comment not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::delete_timeline_comment(&db_pool, id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Comment deleted" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "error": "Comment not found" })),
+        Err(sqlx::Error::Protocol(msg)) => HttpResponse::Conflict().json(json!({ "error": msg })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FeedbackRequest {
+    pub verdict: i64,
+    pub score: Option<i64>,
+    #[serde(default)]
+    pub comment: String,
+}
+
+#[post("/timeline/{id}/feedback")]
+pub async fn create_timeline_feedback(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    data: web::Json<FeedbackRequest>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let timeline = match db::get_timeline_by_id(&db_pool, id).await {
+        Ok(timeline) => timeline,
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Timeline not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    if let Err(err) = check_subcourse_perm(&db_pool, &session, timeline.subcourse_id).await {
+        return err;
+    }
+    if !matches!(data.verdict, FEEDBACK_COMMENT | FEEDBACK_APPROVED | FEEDBACK_CHANGES_REQUESTED) {
+        return HttpResponse::BadRequest().json(json!({ "error": "Invalid verdict" }));
+    }
+    if data.score.is_some_and(|score| !(0..=100).contains(&score)) {
+        return HttpResponse::BadRequest().json(json!({ "error": "Score must be between 0 and 100" }));
+    }
+    if data.verdict == FEEDBACK_COMMENT && data.comment.trim().is_empty() && data.score.is_none() {
+        return HttpResponse::BadRequest().json(json!({ "error": "Feedback is empty" }));
+    }
+
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let realname: String = session.get::<String>("realname").ok().flatten().unwrap_or_default();
+    let feedback = TimelineFeedback {
+        id: 0,
+        timeline_id: id,
+        tea_id: user_id,
+        tea_name: realname,
+        verdict: data.verdict,
+        score: data.score,
+        comment: data.comment,
+        created_at: chrono::Local::now().naive_local(),
+    };
+    match db::add_timeline_feedback(&db_pool, feedback).await {
+        Ok(feedback) => {
+            let action = match feedback.verdict {
+                FEEDBACK_APPROVED => "approved",
+                FEEDBACK_CHANGES_REQUESTED => "requested changes on",
+                _ => "left feedback on",
+            };
+            let message = format!("{} {} \"{}\" ({})", feedback.tea_name, action, timeline.note, timeline.subschedule);
+            let link = format!("/member/timeline/review/{}/{}", timeline.subcourse_id, timeline.stu_id);
+            let _ = db::add_notification(&db_pool, &timeline.stu_id, "timeline_feedback", &message, &link).await;
+            HttpResponse::Ok().json(feedback)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[delete("/timeline/feedback/{id}")]
+pub async fn delete_timeline_feedback(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<i64>,
+    session: Session,
+) -> impl Responder {
+    let id = path.into_inner();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    match db::get_timeline_feedback(&db_pool, id).await {
+        Ok(feedback) if feedback.tea_id == user_id || permission & PERMISSION_ADMIN != 0 => {}
+        Ok(_) => return HttpResponse::Unauthorized().json(json!({ "error": "Only the author can delete feedback" })),
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json(json!({ "error": "Feedback not found" })),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+    match db::delete_timeline_feedback(&db_pool, id).await {
+        Ok(_) => HttpResponse::Ok().json(json!({ "message": "Feedback deleted" })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReviewedTimeline {
+    #[serde(flatten)]
+    pub timeline: StudentTimeline,
+    pub status: Option<i64>, // latest approve / request-changes verdict, None while unreviewed
+    pub score: Option<i64>,  // latest score given
+    pub feedback: Vec<TimelineFeedback>,
+}
+
+// One student's entries in a subcourse with the teachers' feedback folded in
+#[get("/timeline/review/{subcourse_id}/{stu_id}")]
+pub async fn review_student_timeline(
+    db_pool: web::Data<SqlitePool>,
+    path: web::Path<(i64, String)>,
+    session: Session,
+) -> impl Responder {
+    let (subcourse_id, stu_id) = path.into_inner();
+    let user_id: String = session.get::<String>("user_id").ok().flatten().unwrap_or_default();
+    let permission: i64 = session.get::<i64>("permissions").ok().flatten().unwrap_or(0);
+    if user_id != stu_id {
+        if permission & (PERMISSION_TEACHER | PERMISSION_ADMIN) == 0 {
+            return HttpResponse::Unauthorized().json(json!({ "error": "Unauthorized" }));
+        }
+        if let Err(err) = check_subcourse_perm(&db_pool, &session, subcourse_id).await {
+            return err;
+        }
+    }
+    let subcourse = match db::get_subcourse_by_id(&db_pool, subcourse_id).await {
+        Ok(subcourse) => subcourse,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let timelines = match db::list_timelines_by_student(&db_pool, subcourse_id, &stu_id, &subcourse.tea_id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+    let mut feedback = match db::list_feedback_by_student(&db_pool, subcourse_id, &stu_id).await {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    };
+
+    let mut entries = Vec::new();
+    for timeline in timelines {
+        let (mine, rest): (Vec<_>, Vec<_>) = feedback.into_iter().partition(|f| f.timeline_id == timeline.id);
+        feedback = rest;
+        let status = mine.iter().rev().find(|f| f.verdict != FEEDBACK_COMMENT).map(|f| f.verdict);
+        let score = mine.iter().rev().find_map(|f| f.score);
+        entries.push(ReviewedTimeline { timeline, status, score, feedback: mine });
     }
+    let count = |verdict| entries.iter().filter(|e| e.status == Some(verdict)).count();
+    HttpResponse::Ok().json(json!({
+        "stu_id": stu_id,
+        "subcourse_id": subcourse_id,
+        "approved": count(FEEDBACK_APPROVED),
+        "changes_requested": count(FEEDBACK_CHANGES_REQUESTED),
+        "unreviewed": entries.iter().filter(|e| e.status.is_none()).count(),
+        "total_score": entries.iter().filter_map(|e| e.score).sum::<i64>(),
+        "entries": entries,
+    }))
 }
 
 pub fn init_timeline_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(create_timeline)
        .service(list_timelines_by_student)
        .service(download_timeline_file)
+       .service(timeline_thumbnail)
+       .service(timeline_preview)
+       .service(get_timeline_detail)
+       .service(download_timeline_attachment)
+       .service(list_timeline_comments)
+       .service(create_timeline_comment)
+       .service(delete_timeline_comment)
+       .service(review_student_timeline)
        .service(delete_timeline);
 }
@@ -0,0 +1,95 @@
+// Minimal iCalendar (RFC 5545) writer for subscription feeds. Times are
+// written floating, i.e. in the subscriber's local zone, which matches how
+// the rest of the app stores naive local times.
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+
+pub enum EventTime {
+    Date(NaiveDate),
+    Local(NaiveDateTime),
+}
+
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub location: String,
+    pub description: String,
+    pub start: EventTime,
+    pub end: EventTime,
+    pub rrule: Option<String>,
+    // Occurrences of the series that were cancelled
+    pub exdates: Vec<EventTime>,
+    // Set on an override of a single occurrence, naming its original start
+    pub recurrence_id: Option<EventTime>,
+    pub tentative: bool,
+}
+
+// Longest content line in octets before folding
+const LINE_LIMIT: usize = 75;
+
+pub fn calendar(name: &str, events: &[Event]) -> String {
+    // DTSTAMP must be in UTC, unlike the floating event times
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut out = String::new();
+    line(&mut out, "BEGIN:VCALENDAR");
+    line(&mut out, "VERSION:2.0");
+    line(&mut out, "PRODID:-//Laboxide//Calendar//EN");
+    line(&mut out, "CALSCALE:GREGORIAN");
+    line(&mut out, "METHOD:PUBLISH");
+    line(&mut out, &format!("X-WR-CALNAME:{}", escape(name)));
+    for event in events {
+        line(&mut out, "BEGIN:VEVENT");
+        line(&mut out, &format!("UID:{}", event.uid));
+        line(&mut out, &format!("DTSTAMP:{}", stamp));
+        line(&mut out, &time("DTSTART", &event.start));
+        line(&mut out, &time("DTEND", &event.end));
+        if let Some(recurrence_id) = &event.recurrence_id {
+            line(&mut out, &time("RECURRENCE-ID", recurrence_id));
+        }
+        if let Some(rrule) = &event.rrule {
+            line(&mut out, &format!("RRULE:{}", rrule));
+        }
+        for exdate in &event.exdates {
+            line(&mut out, &time("EXDATE", exdate));
+        }
+        line(&mut out, &format!("SUMMARY:{}", escape(&event.summary)));
+        if !event.location.is_empty() {
+            line(&mut out, &format!("LOCATION:{}", escape(&event.location)));
+        }
+        if !event.description.is_empty() {
+            line(&mut out, &format!("DESCRIPTION:{}", escape(&event.description)));
+        }
+        line(&mut out, if event.tentative { "STATUS:TENTATIVE" } else { "STATUS:CONFIRMED" });
+        line(&mut out, "END:VEVENT");
+    }
+    line(&mut out, "END:VCALENDAR");
+    out
+}
+
+fn time(name: &str, t: &EventTime) -> String {
+    match t {
+        EventTime::Date(d) => format!("{};VALUE=DATE:{}", name, d.format("%Y%m%d")),
+        EventTime::Local(dt) => format!("{}:{}", name, dt.format("%Y%m%dT%H%M%S")),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Folds long lines with CRLF + space, never splitting a UTF-8 character
+fn line(out: &mut String, content: &str) {
+    let mut width = 0;
+    for c in content.chars() {
+        if width + c.len_utf8() > LINE_LIMIT {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
@@ -0,0 +1,300 @@
+// Asset labels for equipment: QR codes and Code 128 barcodes rendered as SVG
+// or PNG, and A4 label sheets written as PDF.
+use image::{GrayImage, ImageFormat, Luma};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, Stream};
+use qrcode::{Color, EcLevel, QrCode};
+use std::io::Cursor;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Symbology {
+    Qr,
+    Code128,
+}
+
+impl Symbology {
+    pub fn from_name(name: &str) -> Option<Symbology> {
+        match name {
+            "qr" => Some(Symbology::Qr),
+            "code128" => Some(Symbology::Code128),
+            _ => None,
+        }
+    }
+}
+
+// Dark/light modules, row by row; a barcode is a single row
+pub struct Symbol {
+    pub width: usize,
+    pub modules: Vec<bool>,
+}
+
+impl Symbol {
+    fn height(&self) -> usize {
+        self.modules.len() / self.width
+    }
+
+    fn dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.width + x]
+    }
+
+    // Horizontal runs of dark modules as (x, y, length), so each becomes one rectangle
+    fn runs(&self) -> Vec<(usize, usize, usize)> {
+        let mut runs = Vec::new();
+        for y in 0..self.height() {
+            let mut x = 0;
+            while x < self.width {
+                if !self.dark(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.width && self.dark(x, y) {
+                    x += 1;
+                }
+                runs.push((start, y, x - start));
+            }
+        }
+        runs
+    }
+}
+
+pub struct Label {
+    pub code: String, // What the symbol encodes
+    pub name: String,
+    pub serial: String,
+    pub position: String,
+}
+
+// Quiet zone around a QR code and on either side of a barcode, in modules
+const QR_QUIET: usize = 4;
+const BAR_QUIET: usize = 10;
+
+pub fn encode(kind: Symbology, data: &str) -> Result<Symbol, String> {
+    match kind {
+        Symbology::Qr => qr(data),
+        Symbology::Code128 => code128(data),
+    }
+}
+
+fn qr(data: &str) -> Result<Symbol, String> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::M).map_err(|e| e.to_string())?;
+    let width = code.width();
+    let modules = code.to_colors().into_iter().map(|c| c == Color::Dark).collect();
+    Ok(Symbol { width, modules })
+}
+
+// Bar/space widths for Code 128 symbol values 0..=105
+const CODE128_PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: &str = "2331112";
+
+// Code set B covers printable ASCII, which is all a serial needs
+fn code128(data: &str) -> Result<Symbol, String> {
+    if data.is_empty() || !data.bytes().all(|b| (32..127).contains(&b)) {
+        return Err("Code 128 labels need a printable ASCII serial".into());
+    }
+    let mut values = vec![CODE128_START_B];
+    values.extend(data.bytes().map(|b| (b - 32) as usize));
+    let checksum = values.iter().enumerate().fold(0, |sum, (i, v)| sum + i.max(1) * v) % 103;
+    values.push(checksum);
+
+    let mut modules = vec![false; BAR_QUIET];
+    let patterns = values.iter().map(|&v| CODE128_PATTERNS[v]).chain([CODE128_STOP]);
+    for pattern in patterns {
+        for (i, w) in pattern.bytes().enumerate() {
+            modules.extend(std::iter::repeat_n(i % 2 == 0, (w - b'0') as usize));
+        }
+    }
+    modules.extend(std::iter::repeat_n(false, BAR_QUIET));
+    Ok(Symbol { width: modules.len(), modules })
+}
+
+pub fn svg(kind: Symbology, data: &str) -> Result<String, String> {
+    let symbol = encode(kind, data)?;
+    // Barcodes are drawn 40 modules tall; QR codes get their quiet zone on every side
+    let (quiet, width, height) = match kind {
+        Symbology::Qr => (QR_QUIET, symbol.width + 2 * QR_QUIET, symbol.height() + 2 * QR_QUIET),
+        Symbology::Code128 => (0, symbol.width, 40),
+    };
+    let mut path = String::new();
+    for (x, y, len) in symbol.runs() {
+        let h = if kind == Symbology::Qr { 1 } else { height };
+        path.push_str(&format!("M{} {}h{}v{}h-{}z", x + quiet, y + quiet, len, h, len));
+    }
+    Ok(format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {w} {h}" width="{w}" height="{h}" shape-rendering="crispEdges"><rect width="{w}" height="{h}" fill="white"/><path d="{path}" fill="black"/></svg>"#,
+        w = width,
+        h = height,
+        path = path,
+    ))
+}
+
+pub fn png(kind: Symbology, data: &str, scale: u32) -> Result<Vec<u8>, String> {
+    let symbol = encode(kind, data)?;
+    let (quiet, rows) = match kind {
+        Symbology::Qr => (QR_QUIET, symbol.height()),
+        Symbology::Code128 => (0, 40),
+    };
+    let width = (symbol.width + 2 * quiet) as u32 * scale;
+    let height = (rows + 2 * quiet) as u32 * scale;
+    let img = GrayImage::from_fn(width, height, |px, py| {
+        let x = (px / scale) as usize;
+        let y = (py / scale) as usize;
+        let dark = x >= quiet && x < quiet + symbol.width && y >= quiet && y < quiet + rows && match kind {
+            Symbology::Qr => symbol.dark(x - quiet, y - quiet),
+            Symbology::Code128 => symbol.dark(x, 0),
+        };
+        Luma([if dark { 0 } else { 255 }])
+    });
+    let mut buf = Cursor::new(Vec::new());
+    img.write_to(&mut buf, ImageFormat::Png).map_err(|e| e.to_string())?;
+    Ok(buf.into_inner())
+}
+
+// A4 sheet of 3 x 8 labels, 70 x 37 mm each
+const PAGE_WIDTH: f32 = 595.28;
+const PAGE_HEIGHT: f32 = 841.89;
+const COLUMNS: usize = 3;
+const ROWS: usize = 8;
+const LABEL_WIDTH: f32 = 70.0 * MM;
+const LABEL_HEIGHT: f32 = 37.0 * MM;
+const PADDING: f32 = 3.0 * MM;
+const MM: f32 = 72.0 / 25.4;
+
+pub const LABELS_PER_SHEET: usize = COLUMNS * ROWS;
+
+// `skip` leaves the first cells of the first sheet empty so partly used sheets can be fed again
+pub fn label_sheet(kind: Symbology, labels: &[Label], skip: usize) -> Result<Vec<u8>, String> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let margin_x = (PAGE_WIDTH - COLUMNS as f32 * LABEL_WIDTH) / 2.0;
+    let margin_y = (PAGE_HEIGHT - ROWS as f32 * LABEL_HEIGHT) / 2.0;
+    let cells = skip % LABELS_PER_SHEET + labels.len();
+    let mut kids = Vec::new();
+    for page in 0..cells.div_ceil(LABELS_PER_SHEET) {
+        let mut ops = Vec::new();
+        for slot in 0..LABELS_PER_SHEET {
+            let cell = page * LABELS_PER_SHEET + slot;
+            let Some(label) = cell.checked_sub(skip % LABELS_PER_SHEET).and_then(|i| labels.get(i)) else {
+                continue;
+            };
+            let x = margin_x + (slot % COLUMNS) as f32 * LABEL_WIDTH;
+            let y = PAGE_HEIGHT - margin_y - (slot / COLUMNS + 1) as f32 * LABEL_HEIGHT;
+            draw_label(&mut ops, kind, label, x, y)?;
+        }
+        let content = Content { operations: ops }.encode().map_err(|e| e.to_string())?;
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        kids.push(Object::from(doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        })));
+    }
+
+    let count = kids.len() as i64;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH.into(), PAGE_HEIGHT.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.compress();
+    let mut buf = Vec::new();
+    doc.save_to(&mut buf).map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+// QR labels put the code on the left and text on the right; barcode labels
+// put the bars on top and text underneath
+fn draw_label(ops: &mut Vec<Operation>, kind: Symbology, label: &Label, x: f32, y: f32) -> Result<(), String> {
+    let symbol = encode(kind, &label.code).map_err(|e| format!("{}: {}", label.serial, e))?;
+    let inner = LABEL_HEIGHT - 2.0 * PADDING;
+    let (text_x, text_top, text_width) = match kind {
+        Symbology::Qr => {
+            let module = inner / symbol.width as f32;
+            for (mx, my, len) in symbol.runs() {
+                let top = y + LABEL_HEIGHT - PADDING - my as f32 * module;
+                rect(ops, x + PADDING + mx as f32 * module, top - module, len as f32 * module, module);
+            }
+            let left = x + 2.0 * PADDING + inner;
+            (left, y + LABEL_HEIGHT - PADDING - 8.0, x + LABEL_WIDTH - PADDING - left)
+        }
+        Symbology::Code128 => {
+            let module = (LABEL_WIDTH - 2.0 * PADDING) / symbol.width as f32;
+            let bar_height = 16.0 * MM;
+            for (mx, _, len) in symbol.runs() {
+                rect(ops, x + PADDING + mx as f32 * module, y + LABEL_HEIGHT - PADDING - bar_height, len as f32 * module, bar_height);
+            }
+            (x + PADDING, y + LABEL_HEIGHT - PADDING - bar_height - 10.0, LABEL_WIDTH - 2.0 * PADDING)
+        }
+    };
+    let lines = [(9.0, &label.serial), (8.0, &label.name), (8.0, &label.position)];
+    let mut baseline = text_top;
+    for (size, line) in lines {
+        let Some(fitted) = fit(line, text_width, size) else { continue };
+        text(ops, text_x, baseline, size, &fitted);
+        baseline -= size + 2.0;
+    }
+    Ok(())
+}
+
+fn rect(ops: &mut Vec<Operation>, x: f32, y: f32, w: f32, h: f32) {
+    ops.push(Operation::new("re", vec![x.into(), y.into(), w.into(), h.into()]));
+    ops.push(Operation::new("f", vec![]));
+}
+
+fn text(ops: &mut Vec<Operation>, x: f32, y: f32, size: f32, s: &[u8]) {
+    ops.push(Operation::new("BT", vec![]));
+    ops.push(Operation::new("Tf", vec!["F1".into(), size.into()]));
+    ops.push(Operation::new("Td", vec![x.into(), y.into()]));
+    ops.push(Operation::new("Tj", vec![Object::string_literal(s.to_vec())]));
+    ops.push(Operation::new("ET", vec![]));
+}
+
+// The standard PDF fonts only cover Latin-1, so text with other characters
+// (e.g. Chinese names) is left off the label rather than printed as '?'.
+// Text is cut to what fits, assuming an average Helvetica glyph of 0.55 em.
+fn fit(s: &str, width: f32, size: f32) -> Option<Vec<u8>> {
+    let printable = |c: char| matches!(c as u32, 0x20..=0x7e | 0xa0..=0xff);
+    if !s.chars().all(printable) {
+        return None;
+    }
+    let max = (width / (size * 0.55)) as usize;
+    let mut out: Vec<u8> = s.chars().map(|c| c as u8).collect();
+    if out.len() > max {
+        out.truncate(max.saturating_sub(3));
+        out.extend_from_slice(b"...");
+    }
+    Some(out)
+}
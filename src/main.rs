@@ -8,15 +8,28 @@ use crate::handler::user::init_user_routes;
 use crate::handler::semester::{init_semester_routes, get_current_semester};
 use crate::handler::course::init_course_adminroutes;
 use crate::handler::course::{list_courses, get_course, update_course};
+use crate::handler::storedfile::init_stored_file_adminroutes;
 use crate::handler::labroom::{init_labroom_adminroutes, get_labroom, list_labrooms};
 use crate::handler::subcourse::{init_subcourse_routes, list_subcourses, list_my_subcourses, get_subcourse};
 use crate::handler::group::{init_group_routes, remove_student, list_group, update_student_seat};
 use crate::handler::schedule::{init_schedule_routes, list_schedules, get_schedule};
 use crate::handler::coursefile::{init_course_file_routes, list_course_files, download_course_file};
+use crate::handler::coursefile::{list_course_folders, list_schedule_files, download_shared_file};
 use crate::handler::subschedule::{init_subschedule_routes, list_subschedules};
 use crate::handler::timeline::{init_timeline_routes, list_timelines_by_schedule};
+use crate::handler::timeline::{create_timeline_feedback, delete_timeline_feedback};
+use crate::handler::notification::init_notification_routes;
+use crate::handler::similarity::init_similarity_routes;
+use crate::handler::stocktake::init_stocktake_routes;
 use crate::handler::equipment::init_equipment_routes;
+use crate::handler::custody::init_custody_routes;
+use crate::handler::maintenance::init_maintenance_routes;
 use crate::handler::meeting::{init_meeting_routes, init_agenda_routes};
+use crate::handler::calendar::init_calendar_routes;
+use crate::handler::labsession::{init_lab_session_routes, get_current_lab_session};
+use crate::handler::analytics::init_analytics_routes;
+use crate::handler::progress::{init_progress_routes, verify_step, reject_step, progress_matrix};
+use crate::handler::assignment::{init_assignment_routes, init_submission_routes, create_submission};
 use crate::handler::linux::{add_linux_user, add_forgejo_user, reset_forgejo_password, show_diff, copy_vi_hw};
 use crate::config::{Config, PERMISSION_ADMIN, PERMISSION_TEACHER, PERMISSION_STUDENT, PERMISSION_LAB_MANAGER};
 use crate::middleware::CheckPermission;
@@ -27,6 +40,17 @@ mod config;
 mod handler;
 mod middleware;
 mod utils;
+mod checkin;
+mod storage;
+mod scanner;
+mod preview;
+mod markdown;
+mod similarity;
+mod spreadsheet;
+mod labels;
+mod tasks;
+mod ical;
+mod recurrence;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -37,6 +61,9 @@ async fn main() -> std::io::Result<()> {
     let config = Config::from_env();
     // Initialize the database pool
     let db_pool = db::init_db(&config).await.unwrap();
+    let storage = storage::init_storage(&config);
+    let scanner = scanner::init_scanner(&config);
+    tasks::start(db_pool.clone());
 
     // Initialize session secret key
     let raw_key = general_purpose::STANDARD.decode(&config.secret)
@@ -49,6 +76,8 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
+            .app_data(web::Data::from(scanner.clone()))
             .wrap(Logger::default())
             .wrap(
                 SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
@@ -56,6 +85,7 @@ async fn main() -> std::io::Result<()> {
                 .build(),
             )
             .configure(init_auth_routes) // Register authentication routes
+            .configure(init_calendar_routes)
             .service(list_courses)
             .service(list_subcourses)
             .service(get_course)
@@ -67,6 +97,10 @@ async fn main() -> std::io::Result<()> {
             .service(list_schedules)
             .service(get_schedule)
             .service(list_course_files)
+            .service(list_course_folders)
+            .service(list_schedule_files)
+            .service(download_shared_file)
+            .service(download_course_file) // public files need no login; also kept under /member
             .service(
                 web::scope("/admin")
                 .wrap(CheckPermission::new(PERMISSION_ADMIN))
@@ -74,6 +108,7 @@ async fn main() -> std::io::Result<()> {
                 .configure(init_semester_routes)
                 .configure(init_course_adminroutes)
                 .configure(init_meeting_routes)
+                .configure(init_stored_file_adminroutes)
             )
             .service(
                 web::scope("/teacher")
@@ -82,20 +117,35 @@ async fn main() -> std::io::Result<()> {
                 .configure(init_schedule_routes)
                 .configure(init_course_file_routes)
                 .configure(init_subschedule_routes)
+                .configure(init_custody_routes)
+                .configure(init_maintenance_routes)
                 .configure(init_equipment_routes)
                 .configure(init_agenda_routes)
+                .configure(init_lab_session_routes)
+                .configure(init_analytics_routes)
+                .configure(init_assignment_routes)
+                .configure(init_similarity_routes)
                 .service(update_course)
                 .service(remove_student)
                 .service(update_student_seat)
                 .service(confirm_student_log)
                 .service(get_recent_logs)
                 .service(list_timelines_by_schedule)
+                .service(create_timeline_feedback)
+                .service(delete_timeline_feedback)
                 .service(force_student_log)
+                .service(verify_step)
+                .service(reject_step)
+                .service(progress_matrix)
             )
             .service(
                 web::scope("/lab")
                 .wrap(CheckPermission::new(PERMISSION_LAB_MANAGER | PERMISSION_ADMIN))
                 .service(get_student_logs_by_room)
+                .configure(init_custody_routes)
+                .configure(init_maintenance_routes)
+                .configure(init_equipment_routes)
+                .configure(init_stocktake_routes)
                 .configure(init_labroom_adminroutes)
             )
             .service(
@@ -104,6 +154,7 @@ async fn main() -> std::io::Result<()> {
                 .configure(init_group_routes)
                 .configure(init_student_log_routes)
                 .service(default_student_log)
+                .service(create_submission)
                 .service(add_linux_user)
                 .service(add_forgejo_user)
                 .service(reset_forgejo_password)
@@ -114,9 +165,13 @@ async fn main() -> std::io::Result<()> {
                 web::scope("/member")
                 .wrap(CheckPermission::new(PERMISSION_STUDENT | PERMISSION_TEACHER))
                 .configure(init_timeline_routes)
+                .configure(init_progress_routes)
+                .configure(init_submission_routes)
+                .configure(init_notification_routes)
                 .service(list_group)
                 .service(download_course_file)
                 .service(list_subschedules)
+                .service(get_current_lab_session)
             )
     })
     .bind("127.0.0.1:8080")?
@@ -0,0 +1,40 @@
+// Markdown for timeline notes and comments, rendered once on write and sanitized before storage.
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+// `attachment:N` links and images resolve through `attachment_url`; anything it
+// does not know is left alone and the sanitizer drops the unknown scheme.
+pub fn render(source: &str, attachment_url: impl Fn(i64) -> Option<String>) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(source, options).map(|event| match event {
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+            Event::Start(Tag::Link { link_type, dest_url: resolve(dest_url, &attachment_url), title, id })
+        }
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+            Event::Start(Tag::Image { link_type, dest_url: resolve(dest_url, &attachment_url), title, id })
+        }
+        other => other,
+    });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+
+    // Fenced code keeps its language-* class so the client can highlight it
+    ammonia::Builder::default()
+        .add_tag_attributes("code", &["class"])
+        .clean(&out)
+        .to_string()
+}
+
+fn resolve<'a>(dest: CowStr<'a>, attachment_url: &impl Fn(i64) -> Option<String>) -> CowStr<'a> {
+    match dest.strip_prefix("attachment:").and_then(|n| n.parse::<i64>().ok()).and_then(attachment_url) {
+        Some(url) => CowStr::from(url),
+        None => dest,
+    }
+}
+
+// Plain one-line summary kept in `note` so lists stay readable without the body
+pub fn summary(source: &str) -> String {
+    let line = source.lines().map(|l| l.trim_start_matches('#').trim()).find(|l| !l.is_empty()).unwrap_or("");
+    line.chars().take(100).collect()
+}
@@ -27,6 +27,8 @@ pub struct Course{
     pub intro: String,
     pub mailbox: String,
     pub term: i64,
+    #[serde(default)]
+    pub allowed_mime: String, // e.g. "image/*, application/pdf"; empty accepts any student upload
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -36,6 +38,8 @@ pub struct Labroom{
     pub name: String,
     pub manager: String,
     pub tea_id: String,
+    #[serde(default)]
+    pub allowed_ips: String, // comma separated CIDR ranges, empty = anywhere
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -89,6 +93,34 @@ pub struct CourseFile {
     pub fname: String,
     pub finfo: String,
     pub course_id: i64,
+    pub file_id: Option<i64>, // None for files uploaded before the storage layer
+    pub folder_id: Option<i64>,
+    pub version: i64, // The version currently served
+    pub visible_from: Option<NaiveDateTime>, // Hidden from students until then
+    pub visibility: i64,
+}
+
+// Who may download a course file, besides the course's own teachers
+pub const VISIBILITY_PUBLIC: i64 = 0;
+pub const VISIBILITY_ENROLLED: i64 = 1;
+pub const VISIBILITY_TEACHERS: i64 = 2;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CourseFolder {
+    pub id: i64,
+    pub course_id: i64,
+    pub parent_id: Option<i64>,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CourseFileVersion {
+    pub id: i64,
+    pub course_file_id: i64,
+    pub version: i64,
+    pub file_id: i64,
+    pub uploader: String,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -105,6 +137,52 @@ pub struct StudentLog {
     pub tea_name: String,
     pub fin_time: NaiveDateTime,
     pub confirm: i64,
+    #[serde(default)]
+    pub session_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct StoredFile {
+    pub id: i64,
+    pub sha256: String,
+    pub fsize: i64,
+    pub mime: String,
+    pub fname: String, // original name, kept for display only
+    pub uploader: String,
+    pub created_at: NaiveDateTime,
+    pub scan_status: i64,
+    pub scan_result: String, // Signature or scanner error, empty when clean
+}
+
+pub const SCAN_CLEAN: i64 = 0;
+pub const SCAN_INFECTED: i64 = 1; // Blob moved to quarantine, never served
+pub const SCAN_FAILED: i64 = 2; // Scanner unavailable; held back until rescanned
+pub const SCAN_TOO_LARGE: i64 = 3; // Beyond the scanner's size limit; held back until rescanned
+
+// Similarity fingerprint of a blob, shared by every stored file with the same content
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct FileFingerprint {
+    pub sha256: String,
+    pub kind: i64,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+    pub created_at: NaiveDateTime,
+}
+
+pub const FINGERPRINT_NONE: i64 = 0; // Content we cannot compare, e.g. archives
+pub const FINGERPRINT_TEXT: i64 = 1; // Winnowed k-gram hashes, sorted, little-endian u64s
+pub const FINGERPRINT_IMAGE: i64 = 2; // 64-bit difference hash
+
+// A clean file attached to a schedule, either on a timeline entry or as a submission
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SimilarityCandidate {
+    pub file_id: i64,
+    pub sha256: String,
+    pub fname: String,
+    pub stu_id: String,
+    pub subcourse_id: i64,
+    pub source: String, // "timeline" or "submission"
+    pub source_id: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -124,8 +202,57 @@ pub struct StudentTimeline {
     pub subschedule: String,
     pub subcourse_id: i64,
     pub note: String, // can be a file path if type == 1
-    pub notetype: i64,  // 0 = text, 1 = file
+    pub notetype: i64,  // 0 = text, 1 = file, 2 = markdown
     pub timestamp: NaiveDateTime, // store as ISO string for JSON
+    #[serde(default)]
+    pub session_id: Option<i64>,
+    #[serde(default)]
+    pub subschedule_id: Option<i64>,
+    #[serde(default)]
+    pub file_id: Option<i64>,
+    #[serde(default)]
+    pub body: String, // Markdown source when notetype == 2
+    #[serde(default)]
+    pub body_html: String, // Sanitized rendering of body
+}
+
+pub const NOTETYPE_FILE: i64 = 1;
+pub const NOTETYPE_MARKDOWN: i64 = 2;
+
+// Files attached to a markdown note, referenced from the body as attachment:N
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TimelineAttachment {
+    pub id: i64,
+    pub timeline_id: i64,
+    pub position: i64,
+    pub file_id: i64,
+    pub fname: String,
+    pub mime: String,
+    pub fsize: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TimelineComment {
+    pub id: i64,
+    pub timeline_id: i64,
+    pub parent_id: Option<i64>,
+    pub author_id: String,
+    pub author_name: String,
+    pub body: String,
+    pub body_html: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct LabSession {
+    pub id: i64,
+    pub subcourse_id: i64,
+    pub schedule_id: i64,
+    pub opened_at: NaiveDateTime,
+    pub closed_at: Option<NaiveDateTime>, // None while the session is open
+    pub opened_by: String,
+    #[serde(skip_serializing, default)]
+    pub secret: String, // seed for the rotating check-in code
 }
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -138,17 +265,218 @@ pub struct Equipment {
     pub status: i64,
     pub note: Option<String>,
     pub owner_id: String,
+    #[serde(default)]
+    pub available: bool, // No reservation or checkout open on the item
 }
 
+pub const EQUIP_IN_SERVICE: i64 = 0;
+pub const EQUIP_UNDER_REPAIR: i64 = 1;
+pub const EQUIP_CALIBRATION_DUE: i64 = 2; // Latest calibration certificate has expired
+pub const EQUIP_RETIRED: i64 = 3;
+
+// One loan of an item. `user` keeps the borrower's display name; loans
+// recorded before borrower_id existed only have that free-text name.
 #[derive(Debug, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
 pub struct EquipmentHistory {
     pub id: i64,
     pub user: String,
-    pub borrowed_date: NaiveDateTime,
+    pub borrowed_date: NaiveDateTime, // Pickup time; planned pickup while reserved
     pub telephone: String,
     pub note: String,
     pub returned_date: Option<NaiveDateTime>,
     pub item_id: i64,
+    #[serde(default)]
+    pub borrower_id: Option<String>,
+    pub state: i64,
+    #[serde(default)]
+    pub due_date: Option<NaiveDateTime>,
+}
+
+// Inventory search; every filter is optional and they combine with AND
+#[derive(Debug, Default, Deserialize)]
+pub struct EquipmentFilter {
+    pub q: Option<String>, // Full-text query over note
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    pub position: Option<String>,
+    pub status: Option<i64>,
+    pub min_value: Option<i64>,
+    pub max_value: Option<i64>,
+    pub owner_id: Option<String>,
+    pub available: Option<bool>,
+    pub sort: Option<String>, // id, name, serial, value, position or status
+    #[serde(default)]
+    pub desc: bool,
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+}
+
+// An item with its open loan, if any, as written to inventory exports
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct InventoryRow {
+    pub id: i64,
+    pub name: String,
+    pub serial: String,
+    pub value: i64,
+    pub position: String,
+    pub status: i64,
+    pub note: Option<String>,
+    pub owner_id: String,
+    pub loan_state: Option<i64>,
+    pub borrower_id: Option<String>,
+    pub borrower: Option<String>,
+    pub due_date: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Stocktake {
+    pub id: i64,
+    pub name: String,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub closed_at: Option<NaiveDateTime>,
+}
+
+// One serial checked during a stocktake
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct StocktakeItem {
+    pub id: i64,
+    pub stocktake_id: i64,
+    pub serial: String,
+    pub equipment_id: Option<i64>,
+    pub status: i64,
+    pub found_position: Option<String>,
+    pub checked_by: String,
+    pub checked_at: NaiveDateTime,
+}
+
+pub const STOCK_FOUND: i64 = 0;
+pub const STOCK_MISSING: i64 = 1;
+pub const STOCK_MOVED: i64 = 2; // Found somewhere other than its registered position
+pub const STOCK_UNKNOWN: i64 = 3; // Serial not in the equipments table
+
+// A repair ticket, calibration certificate or routine service of an item.
+// A repair stays open until completed_at is set.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct EquipmentMaintenance {
+    pub id: i64,
+    pub item_id: i64,
+    pub kind: i64,
+    pub summary: String,
+    pub cost: i64,
+    pub vendor: String,
+    pub certificate: String,
+    pub started_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDate>, // Calibration certificates only
+    pub reminded_at: Option<NaiveDateTime>,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+}
+
+pub const MAINT_REPAIR: i64 = 0;
+pub const MAINT_CALIBRATION: i64 = 1;
+pub const MAINT_SERVICE: i64 = 2;
+
+// Purchase data for straight-line depreciation of `value` down to `salvage_value`
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct EquipmentAsset {
+    #[serde(default)]
+    pub item_id: i64,
+    pub acquired_on: NaiveDate,
+    pub life_months: i64,
+    #[serde(default)]
+    pub salvage_value: i64,
+}
+
+// An item's latest calibration certificate
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CalibrationDue {
+    pub item_id: i64,
+    pub name: String,
+    pub serial: String,
+    pub position: String,
+    pub owner_id: String,
+    pub maintenance_id: i64,
+    pub certificate: String,
+    pub expires_at: NaiveDate,
+    pub reminded_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DepreciationRow {
+    pub id: i64,
+    pub name: String,
+    pub serial: String,
+    pub position: String,
+    pub status: i64,
+    pub owner_id: String,
+    pub value: i64,
+    pub acquired_on: Option<NaiveDate>,
+    pub life_months: Option<i64>,
+    pub salvage_value: Option<i64>,
+    pub maintenance_cost: i64,
+}
+
+// Someone other than the owner who may manage an item
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct EquipmentCustodian {
+    pub item_id: i64,
+    pub user_id: String,
+    pub added_by: String,
+    pub added_at: NaiveDateTime,
+}
+
+// A handover of ownership, which takes effect once the recipient accepts
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct EquipmentTransfer {
+    pub id: i64,
+    pub item_id: i64,
+    pub from_id: String,
+    pub to_id: String,
+    pub note: String,
+    pub state: i64,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+}
+
+pub const TRANSFER_PENDING: i64 = 0;
+pub const TRANSFER_ACCEPTED: i64 = 1;
+pub const TRANSFER_DECLINED: i64 = 2;
+pub const TRANSFER_CANCELLED: i64 = 3;
+
+// One custody change; `user_id` is who the change is about
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CustodyLog {
+    pub id: i64,
+    pub item_id: i64,
+    pub action: String,
+    pub actor_id: String,
+    pub user_id: String,
+    pub note: String,
+    pub created_at: NaiveDateTime,
+}
+
+// reserved -> checked out -> returned | lost; a reservation may also be cancelled
+pub const LOAN_RESERVED: i64 = 0;
+pub const LOAN_CHECKED_OUT: i64 = 1;
+pub const LOAN_RETURNED: i64 = 2;
+pub const LOAN_LOST: i64 = 3;
+pub const LOAN_CANCELLED: i64 = 4;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct OverdueLoan {
+    pub id: i64,
+    pub item_id: i64,
+    pub name: String,
+    pub serial: String,
+    pub borrower_id: Option<String>,
+    pub user: String,
+    pub telephone: String,
+    pub borrowed_date: NaiveDateTime,
+    pub due_date: NaiveDateTime,
+    pub days_overdue: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -156,6 +484,43 @@ pub struct MeetingRoom {
     pub id: Option<i64>,
     pub room: String,
     pub info: String,
+    // Seats; 0 when unknown
+    #[serde(default)]
+    pub capacity: i64,
+    #[serde(default)]
+    pub projector: i64,
+    #[serde(default)]
+    pub video_conferencing: i64,
+}
+
+// A gap in a room's bookings long enough for the requested meeting
+#[derive(Debug, Serialize)]
+pub struct FreeSlot {
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomAvailability {
+    pub room: MeetingRoom,
+    pub slots: Vec<FreeSlot>,
+}
+
+// One scheduled lab of a subcourse, with the recorded session if it took place
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CalendarSession {
+    pub subcourse_id: i64,
+    pub course_name: String,
+    pub room: String,
+    pub tea_name: String,
+    pub weekday: i64,
+    pub lag_week: i64,
+    pub semester_start: NaiveDate,
+    pub schedule_id: i64,
+    pub week: i64,
+    pub schedule_name: String,
+    pub opened_at: Option<NaiveDateTime>,
+    pub closed_at: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -170,4 +535,151 @@ pub struct MeetingAgenda {
     pub end_time: NaiveTime,
     pub room_id: i64,
     pub confirm: i64,
+    // Weeks between occurrences when repeat = 1; 0 is read as weekly
+    #[serde(default)]
+    pub repeat_interval: i64,
+    pub repeat_until: Option<NaiveDate>,
+    pub repeat_count: Option<i64>,
+    // Why a request was rejected or cancelled; set by the server
+    #[serde(default)]
+    pub reason: String,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// States of a meeting agenda, kept in its `confirm` column
+pub const MEETING_PENDING: i64 = 0;
+pub const MEETING_APPROVED: i64 = 1;
+pub const MEETING_REJECTED: i64 = 2;
+pub const MEETING_CANCELLED: i64 = 3;
+
+// Cancels or moves the occurrence of a repeating agenda that falls on `date`
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MeetingException {
+    pub id: Option<i64>,
+    #[serde(default)]
+    pub agenda_id: i64,
+    pub date: NaiveDate,
+    pub cancelled: i64,
+    pub new_date: Option<NaiveDate>,
+    pub start_time: Option<NaiveTime>,
+    pub end_time: Option<NaiveTime>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+// A single expanded occurrence of an agenda
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeetingOccurrence {
+    pub agenda_id: i64,
+    pub title: String,
+    pub username: String,
+    pub room_id: i64,
+    pub date: NaiveDate,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub original_date: NaiveDate,
+    pub moved: bool,
+    pub confirm: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Assignment {
+    pub id: i64,
+    pub schedule_id: i64,
+    pub title: String,
+    pub due_days: i64, // days after the lab session, due at the end of that day
+    pub allowed_types: String, // comma separated extensions, empty = any
+    pub max_size: i64, // bytes
+    pub allow_late: i64,
+}
+
+pub const SUBMISSION_SUBMITTED: i64 = 0;
+pub const SUBMISSION_RETURNED: i64 = 1; // sent back for resubmission
+pub const SUBMISSION_ACCEPTED: i64 = 2;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Submission {
+    pub id: i64,
+    pub assignment_id: i64,
+    pub stu_id: String,
+    pub subcourse_id: i64,
+    pub version: i64,
+    pub fname: String,
+    pub fsize: i64,
+    pub submitted_at: NaiveDateTime,
+    pub late: i64,
+    pub status: i64,
+    pub tea_comment: String,
+    pub file_id: Option<i64>,
+}
+
+pub const FEEDBACK_COMMENT: i64 = 0;
+pub const FEEDBACK_APPROVED: i64 = 1;
+pub const FEEDBACK_CHANGES_REQUESTED: i64 = 2;
+
+// A teacher's review of one timeline entry; the newest non-comment verdict is the entry's status
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TimelineFeedback {
+    pub id: i64,
+    pub timeline_id: i64,
+    pub tea_id: String,
+    pub tea_name: String,
+    pub verdict: i64,
+    pub score: Option<i64>,
+    pub comment: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Notification {
+    pub id: i64,
+    pub user_id: String,
+    pub kind: String,
+    pub message: String,
+    pub link: String,
+    pub created_at: NaiveDateTime,
+    pub read_at: Option<NaiveDateTime>,
+}
+
+pub const STEP_DONE: i64 = 0; // marked complete by the student
+pub const STEP_VERIFIED: i64 = 1;
+pub const STEP_REJECTED: i64 = 2;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct StepProgress {
+    pub id: i64,
+    pub stu_id: String,
+    pub subcourse_id: i64,
+    pub subschedule_id: i64,
+    pub timeline_id: Option<i64>, // evidence for the step, if any
+    pub status: i64,
+    pub tea_note: String,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AttendanceStat {
+    pub stu_id: String,
+    pub stu_name: String,
+    pub subcourse_id: i64,
+    pub attended: i64,
+    pub held: i64, // sessions opened for the subcourse
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ScheduleDuration {
+    pub schedule_id: i64,
+    pub week: i64,
+    pub name: String,
+    pub finished: i64, // students with both a timeline entry and a log
+    pub avg_minutes: Option<f64>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TeacherBacklog {
+    pub tea_id: String,
+    pub tea_name: String,
+    pub unconfirmed: i64,
 }
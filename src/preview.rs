@@ -0,0 +1,154 @@
+// Thumbnails and previews for uploaded images and PDFs, cached in storage next to the blob.
+use image::{DynamicImage, GrayImage, ImageFormat, ImageReader, Limits, RgbImage};
+use sqlx::SqlitePool;
+use flate2::read::ZlibDecoder;
+use std::io::{self, Cursor, Read};
+
+use crate::db;
+use crate::models::SCAN_CLEAN;
+use crate::storage::{self, Storage};
+
+#[derive(Clone, Copy)]
+pub enum Derivative {
+    Thumb,
+    Preview,
+}
+
+impl Derivative {
+    pub const ALL: [Derivative; 2] = [Derivative::Thumb, Derivative::Preview];
+
+    fn name(self) -> &'static str {
+        match self {
+            Derivative::Thumb => "thumb",
+            Derivative::Preview => "preview",
+        }
+    }
+
+    fn max_side(self) -> u32 {
+        match self {
+            Derivative::Thumb => 256,
+            Derivative::Preview => 1280,
+        }
+    }
+}
+
+// Decoding a hostile image must not take the server down with it
+const MAX_DECODE_BYTES: u64 = 256 << 20;
+// Larger sources are not read into memory to be previewed
+const MAX_SOURCE_BYTES: i64 = 64 << 20;
+
+pub fn can_preview(mime: &str) -> bool {
+    matches!(
+        mime,
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp" | "application/pdf"
+    )
+}
+
+pub fn derived_key(sha256: &str, kind: Derivative) -> String {
+    format!("derived/{}/{}.jpg", storage::blob_key(sha256), kind.name())
+}
+
+// Returns a JPEG derivative, rendering and caching it on first request
+pub async fn derivative(storage: &dyn Storage, pool: &SqlitePool, file_id: i64, kind: Derivative) -> io::Result<Vec<u8>> {
+    let file = db::get_stored_file_by_id(pool, file_id).await.map_err(io::Error::other)?;
+    if file.scan_status != SCAN_CLEAN {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "file has not passed scanning"));
+    }
+    if !can_preview(&file.mime) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "no preview for this file type"));
+    }
+    let key = derived_key(&file.sha256, kind);
+    if storage.exists(&key).await? {
+        return storage.get(&key).await;
+    }
+
+    let (file, data) = storage::load_file(storage, pool, file_id, MAX_SOURCE_BYTES).await?;
+    let max_side = kind.max_side();
+    let bytes = tokio::task::spawn_blocking(move || render(&data, &file.mime, max_side))
+        .await
+        .map_err(io::Error::other)?
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    storage.put(&key, bytes.clone()).await?;
+    Ok(bytes)
+}
+
+fn render(data: &[u8], mime: &str, max_side: u32) -> Result<Vec<u8>, String> {
+    let img = if mime == "application/pdf" {
+        first_page_image(data)?
+    } else {
+        decode(data).map_err(|e| e.to_string())?
+    };
+    let small = DynamicImage::ImageRgb8(img.thumbnail(max_side, max_side).to_rgb8());
+    let mut out = Cursor::new(Vec::new());
+    small.write_to(&mut out, ImageFormat::Jpeg).map_err(|e| e.to_string())?;
+    Ok(out.into_inner())
+}
+
+pub fn decode(data: &[u8]) -> image::ImageResult<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+    reader.decode()
+}
+
+// Pure Rust tooling cannot rasterise a PDF page, so the largest image on the
+// first page stands in for it; that covers scans and exported screenshots.
+fn first_page_image(data: &[u8]) -> Result<DynamicImage, String> {
+    let doc = lopdf::Document::load_mem(data).map_err(|e| e.to_string())?;
+    let page_id = *doc.get_pages().values().next().ok_or("PDF has no pages")?;
+    let images = doc.get_page_images(page_id).map_err(|_| "no image on the first page")?;
+    let img = images
+        .iter()
+        .max_by_key(|img| img.width.saturating_mul(img.height))
+        .ok_or("no image on the first page")?;
+
+    let filters = img.filters.clone().unwrap_or_default();
+    if filters.iter().any(|f| f == "DCTDecode") {
+        return decode(img.content).map_err(|e| e.to_string());
+    }
+    if img.bits_per_component != Some(8) {
+        return Err("unsupported image depth in PDF".to_string());
+    }
+    let channels = match img.color_space.as_deref() {
+        Some("DeviceRGB") => 3,
+        Some("DeviceGray") => 1,
+        _ => return Err("unsupported image format in PDF".to_string()),
+    };
+    // The declared dimensions fix the sample size, so nothing beyond it is ever inflated
+    let (Ok(width), Ok(height)) = (u32::try_from(img.width), u32::try_from(img.height)) else {
+        return Err("invalid image size in PDF".to_string());
+    };
+    let expected = (width as u64)
+        .checked_mul(height as u64)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .filter(|&len| len <= MAX_DECODE_BYTES)
+        .ok_or("image in PDF is too large")?;
+    if img.content.len() as u64 > MAX_DECODE_BYTES {
+        return Err("image in PDF is too large".to_string());
+    }
+    let samples = match filters.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => img.content.to_vec(),
+        ["FlateDecode"] => {
+            let stream = doc.get_object(img.id).and_then(|o| o.as_stream()).map_err(|e| e.to_string())?;
+            if stream.dict.get(b"DecodeParms").is_ok() {
+                return Err("unsupported image encoding in PDF".to_string());
+            }
+            let mut samples = Vec::new();
+            ZlibDecoder::new(img.content)
+                .take(expected + 1)
+                .read_to_end(&mut samples)
+                .map_err(|e| e.to_string())?;
+            samples
+        }
+        _ => return Err("unsupported image encoding in PDF".to_string()),
+    };
+    if samples.len() as u64 != expected {
+        return Err("image data in PDF does not match its size".to_string());
+    }
+    let decoded = match channels {
+        3 => RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8),
+        _ => GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8),
+    };
+    decoded.ok_or_else(|| "unsupported image format in PDF".to_string())
+}
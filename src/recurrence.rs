@@ -0,0 +1,164 @@
+// Expansion of repeating meeting agendas into concrete occurrences.
+// A series starts on `date` and repeats every `repeat_interval` weeks until
+// `repeat_until` or for `repeat_count` occurrences, whichever ends first.
+use chrono::{Duration, NaiveDate};
+
+use crate::models::{MeetingAgenda, MeetingException, MeetingOccurrence, MEETING_APPROVED, MEETING_PENDING};
+
+// How far an open-ended series is expanded when checking for conflicts
+pub const MAX_HORIZON_DAYS: i64 = 366;
+
+pub fn step_days(agenda: &MeetingAgenda) -> i64 {
+    agenda.repeat_interval.max(1).saturating_mul(7)
+}
+
+// `date` moved by `days`, or None past the range of NaiveDate
+fn shift(date: NaiveDate, days: i64) -> Option<NaiveDate> {
+    Duration::try_days(days).and_then(|d| date.checked_add_signed(d))
+}
+
+// Date of the last occurrence, or None if the series never ends. A count
+// reaching past the calendar's range bounds nothing.
+pub fn last_date(agenda: &MeetingAgenda) -> Option<NaiveDate> {
+    if agenda.repeat != 1 {
+        return Some(agenda.date);
+    }
+    let by_count = agenda
+        .repeat_count
+        .and_then(|n| step_days(agenda).checked_mul(n.max(1) - 1))
+        .and_then(|days| shift(agenda.date, days));
+    match (agenda.repeat_until, by_count) {
+        (Some(until), Some(by_count)) => Some(until.min(by_count)),
+        (until, by_count) => until.or(by_count),
+    }
+}
+
+pub fn is_occurrence(agenda: &MeetingAgenda, date: NaiveDate) -> bool {
+    if date < agenda.date || last_date(agenda).is_some_and(|last| date > last) {
+        return false;
+    }
+    if agenda.repeat != 1 {
+        return date == agenda.date;
+    }
+    (date - agenda.date).num_days() % step_days(agenda) == 0
+}
+
+fn occurrence(agenda: &MeetingAgenda, original: NaiveDate) -> MeetingOccurrence {
+    MeetingOccurrence {
+        agenda_id: agenda.id.unwrap_or_default(),
+        title: agenda.title.clone(),
+        username: agenda.username.clone(),
+        room_id: agenda.room_id,
+        date: original,
+        start_time: agenda.start_time,
+        end_time: agenda.end_time,
+        original_date: original,
+        moved: false,
+        confirm: agenda.confirm,
+    }
+}
+
+// The occurrence an exception turns its original date into, if it still takes place
+pub fn apply_exception(agenda: &MeetingAgenda, exception: &MeetingException) -> Option<MeetingOccurrence> {
+    if exception.cancelled != 0 {
+        return None;
+    }
+    let mut occ = occurrence(agenda, exception.date);
+    occ.date = exception.new_date.unwrap_or(exception.date);
+    occ.start_time = exception.start_time.unwrap_or(agenda.start_time);
+    occ.end_time = exception.end_time.unwrap_or(agenda.end_time);
+    occ.moved = true;
+    Some(occ)
+}
+
+// Occurrences of `agenda` dated within [from, to], sorted by date and time.
+// `exceptions` may hold rows of other agendas; only matching ones are applied.
+pub fn expand(
+    agenda: &MeetingAgenda,
+    exceptions: &[MeetingException],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<MeetingOccurrence> {
+    let own: Vec<&MeetingException> = exceptions
+        .iter()
+        .filter(|e| Some(e.agenda_id) == agenda.id && is_occurrence(agenda, e.date))
+        .collect();
+    let mut out = Vec::new();
+
+    let last = last_date(agenda).unwrap_or(to).min(to);
+    let step = step_days(agenda);
+    let mut date = agenda.date;
+    if from > date {
+        // Jump to the first occurrence on or after `from`; past the calendar's end there is none
+        let skip = ((from - date).num_days() + step - 1) / step;
+        date = skip.checked_mul(step).and_then(|days| shift(date, days)).unwrap_or(NaiveDate::MAX);
+    }
+    while date <= last {
+        if !own.iter().any(|e| e.date == date) {
+            out.push(occurrence(agenda, date));
+        }
+        if agenda.repeat != 1 {
+            break;
+        }
+        match shift(date, step) {
+            Some(next) => date = next,
+            None => break,
+        }
+    }
+
+    out.extend(
+        own.iter()
+            .filter_map(|e| apply_exception(agenda, e))
+            .filter(|occ| occ.date >= from && occ.date <= to),
+    );
+    out.sort_by_key(|occ| (occ.date, occ.start_time));
+    out
+}
+
+// The whole series, bounded by MAX_HORIZON_DAYS when open-ended, with its
+// own exceptions applied
+pub fn series(agenda: &MeetingAgenda, exceptions: &[MeetingException]) -> Vec<MeetingOccurrence> {
+    let horizon = shift(agenda.date, MAX_HORIZON_DAYS).unwrap_or(NaiveDate::MAX);
+    let moved = exceptions
+        .iter()
+        .filter(|e| Some(e.agenda_id) == agenda.id)
+        .filter_map(|e| e.new_date);
+    let from = moved.clone().chain([agenda.date]).min().unwrap_or(agenda.date);
+    let to = moved
+        .chain([last_date(agenda).unwrap_or(horizon).min(horizon)])
+        .max()
+        .unwrap_or(horizon);
+    expand(agenda, exceptions, from, to)
+}
+
+// Whether the agenda's slots are taken: approved bookings always, pending
+// requests only when `include_pending` is set
+pub fn blocks(agenda: &MeetingAgenda, include_pending: bool) -> bool {
+    agenda.confirm == MEETING_APPROVED || (include_pending && agenda.confirm == MEETING_PENDING)
+}
+
+pub fn overlaps(a: &MeetingOccurrence, b: &MeetingOccurrence) -> bool {
+    a.date == b.date && a.start_time < b.end_time && b.start_time < a.end_time
+}
+
+// RFC 5545 RRULE for the series. UNTIL is written as floating local time to
+// match the floating DTSTART of the feed; COUNT is only kept when it is the
+// sole bound, since the two must not appear together.
+pub fn rrule(agenda: &MeetingAgenda) -> Option<String> {
+    if agenda.repeat != 1 {
+        return None;
+    }
+    let mut rule = "FREQ=WEEKLY".to_string();
+    if agenda.repeat_interval > 1 {
+        rule.push_str(&format!(";INTERVAL={}", agenda.repeat_interval));
+    }
+    match (agenda.repeat_until, agenda.repeat_count) {
+        (None, Some(count)) => rule.push_str(&format!(";COUNT={}", count.max(1))),
+        (Some(_), _) => {
+            let last = last_date(agenda).unwrap_or(agenda.date);
+            rule.push_str(&format!(";UNTIL={}T235959", last.format("%Y%m%d")));
+        }
+        (None, None) => {}
+    }
+    Some(rule)
+}
@@ -0,0 +1,92 @@
+// Content scanning for uploads, run on the temp file before it is committed to storage.
+use futures::future::BoxFuture;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::config::Config;
+
+pub enum ScanVerdict {
+    Clean,
+    Infected(String), // Signature name reported by the scanner
+    TooLarge,         // The scanner refused to read the whole file
+}
+
+pub trait Scanner: Send + Sync {
+    fn scan<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ScanVerdict>>;
+}
+
+pub fn init_scanner(config: &Config) -> Arc<dyn Scanner> {
+    if config.scanner == "clamav" {
+        Arc::new(ClamavScanner { socket: config.clamav_socket.clone(), max_bytes: config.clamav_stream_max })
+    } else {
+        Arc::new(NoopScanner)
+    }
+}
+
+pub struct NoopScanner;
+
+impl Scanner for NoopScanner {
+    fn scan<'a>(&'a self, _path: &'a Path) -> BoxFuture<'a, io::Result<ScanVerdict>> {
+        Box::pin(async { Ok(ScanVerdict::Clean) })
+    }
+}
+
+// Talks to clamd over its local socket with the INSTREAM command
+pub struct ClamavScanner {
+    socket: String,
+    max_bytes: i64, // clamd's StreamMaxLength
+}
+
+const CLAMAV_CHUNK: usize = 64 * 1024;
+
+impl Scanner for ClamavScanner {
+    fn scan<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ScanVerdict>> {
+        Box::pin(async move {
+            let mut file = tokio::fs::File::open(path).await?;
+            if file.metadata().await?.len() > self.max_bytes as u64 {
+                return Ok(ScanVerdict::TooLarge);
+            }
+            let mut stream = tokio::net::UnixStream::connect(&self.socket).await?;
+            stream.write_all(b"zINSTREAM\0").await?;
+
+            // Each chunk is prefixed by its length; a zero length ends the stream
+            let mut buf = vec![0u8; CLAMAV_CHUNK];
+            loop {
+                let n = file.read(&mut buf).await?;
+                let mut chunk = (n as u32).to_be_bytes().to_vec();
+                chunk.extend_from_slice(&buf[..n]);
+                match stream.write_all(&chunk).await {
+                    Ok(()) => {}
+                    // clamd hangs up once StreamMaxLength is passed; its reply says why
+                    Err(e) if matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) => break,
+                    Err(e) => return Err(e),
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+
+            let mut reply = Vec::new();
+            stream.read_to_end(&mut reply).await?;
+            parse_reply(&String::from_utf8_lossy(&reply))
+        })
+    }
+}
+
+// Replies look like "stream: OK", "stream: Eicar-Test-Signature FOUND"
+// or "INSTREAM size limit exceeded. ERROR"
+fn parse_reply(reply: &str) -> io::Result<ScanVerdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let status = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if status == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if status.starts_with("INSTREAM size limit exceeded") {
+        Ok(ScanVerdict::TooLarge)
+    } else if let Some(signature) = status.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else {
+        Err(io::Error::other(format!("clamd: {}", status)))
+    }
+}
@@ -0,0 +1,249 @@
+// Content fingerprints for spotting copied work: winnowing over normalised text
+// and code, difference hashes for images. Both are stored per blob sha256.
+use image::imageops::FilterType;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Cursor, Read};
+use std::sync::Arc;
+
+use crate::db;
+use crate::models::{FileFingerprint, FINGERPRINT_IMAGE, FINGERPRINT_NONE, FINGERPRINT_TEXT};
+use crate::preview;
+use crate::storage::{self, Storage};
+
+// Matches shorter than K characters are ignored; any match of at least
+// K + W - 1 characters is guaranteed to share a fingerprint.
+const K: usize = 25;
+const W: usize = 16;
+// Documents yielding fewer hashes than this are too short to judge
+const MIN_TEXT_HASHES: usize = 8;
+// Cap on text pulled out of a single archive or document
+const MAX_TEXT_BYTES: usize = 4 << 20;
+// Larger uploads are not read into memory to be fingerprinted
+const MAX_FINGERPRINT_BYTES: i64 = 64 << 20;
+
+pub async fn ensure_fingerprint(storage: &dyn Storage, pool: &SqlitePool, file_id: i64) -> io::Result<FileFingerprint> {
+    let file = db::get_stored_file_by_id(pool, file_id).await.map_err(io::Error::other)?;
+    if let Some(fp) = db::get_fingerprint(pool, &file.sha256).await.map_err(io::Error::other)? {
+        return Ok(fp);
+    }
+    // Oversized uploads are recorded as unfingerprinted rather than read in
+    let (kind, fingerprint) = if file.fsize > MAX_FINGERPRINT_BYTES {
+        (FINGERPRINT_NONE, Vec::new())
+    } else {
+        let (file, data) = storage::load_file(storage, pool, file_id, MAX_FINGERPRINT_BYTES).await?;
+        tokio::task::spawn_blocking(move || fingerprint(&data, &file.mime))
+            .await
+            .map_err(io::Error::other)?
+    };
+    db::set_fingerprint(pool, &file.sha256, kind, &fingerprint).await.map_err(io::Error::other)?;
+    db::get_fingerprint(pool, &file.sha256)
+        .await
+        .map_err(io::Error::other)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "fingerprint vanished"))
+}
+
+// Fingerprints new uploads in the background so the request does not wait on them
+pub fn fingerprint_later(storage: Arc<dyn Storage>, pool: SqlitePool, file_ids: Vec<i64>) {
+    actix_web::rt::spawn(async move {
+        for file_id in file_ids {
+            if let Err(e) = ensure_fingerprint(storage.as_ref(), &pool, file_id).await {
+                log::warn!("fingerprint for stored file {} failed: {}", file_id, e);
+            }
+        }
+    });
+}
+
+pub fn fingerprint(data: &[u8], mime: &str) -> (i64, Vec<u8>) {
+    if mime.starts_with("image/") {
+        if let Ok(img) = preview::decode(data) {
+            return (FINGERPRINT_IMAGE, dhash(&img).to_le_bytes().to_vec());
+        }
+        return (FINGERPRINT_NONE, Vec::new());
+    }
+    let text = match mime {
+        "application/pdf" => pdf_text(data),
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+            zip_text(data, |name| name == "word/document.xml").map(|xml| strip_tags(&xml))
+        }
+        "application/zip" => zip_text(data, |name| !name.ends_with('/')),
+        _ => plain_text(data),
+    };
+    match text.map(|t| winnow(&normalize(&t))) {
+        Some(hashes) if hashes.len() >= MIN_TEXT_HASHES => {
+            (FINGERPRINT_TEXT, hashes.iter().flat_map(|h| h.to_le_bytes()).collect())
+        }
+        _ => (FINGERPRINT_NONE, Vec::new()),
+    }
+}
+
+// Only the first MAX_TEXT_BYTES are read; a character cut off there is dropped
+fn plain_text(data: &[u8]) -> Option<String> {
+    let data = &data[..data.len().min(MAX_TEXT_BYTES)];
+    if data.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) => Some(text.to_string()),
+        Err(e) if e.error_len().is_none() => Some(String::from_utf8_lossy(&data[..e.valid_up_to()]).into_owned()),
+        Err(_) => None,
+    }
+}
+
+// Page by page, stopping once MAX_TEXT_BYTES have been collected
+fn pdf_text(data: &[u8]) -> Option<String> {
+    let doc = lopdf::Document::load_mem(data).ok()?;
+    let mut out = String::new();
+    for page in doc.get_pages().into_keys() {
+        if out.len() >= MAX_TEXT_BYTES {
+            break;
+        }
+        if let Ok(text) = doc.extract_text(&[page]) {
+            out.push_str(&text);
+        }
+    }
+    truncate(&mut out, MAX_TEXT_BYTES);
+    Some(out)
+}
+
+fn truncate(s: &mut String, max: usize) {
+    if s.len() > max {
+        let mut end = max;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+    }
+}
+
+// Concatenates the text members of a zip, skipping anything binary
+fn zip_text(data: &[u8], wanted: impl Fn(&str) -> bool) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).ok()?;
+    let mut out = String::new();
+    for i in 0..archive.len() {
+        let Ok(entry) = archive.by_index(i) else { continue };
+        if !wanted(entry.name()) || out.len() >= MAX_TEXT_BYTES {
+            continue;
+        }
+        let mut buf = Vec::new();
+        if entry.take((MAX_TEXT_BYTES - out.len()) as u64).read_to_end(&mut buf).is_ok() {
+            if let Some(text) = plain_text(&buf) {
+                out.push_str(&text);
+                out.push('\n');
+            }
+        }
+    }
+    Some(out)
+}
+
+fn strip_tags(xml: &str) -> String {
+    let mut out = String::with_capacity(xml.len() / 4);
+    let mut in_tag = false;
+    for c in xml.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => {
+                in_tag = false;
+                out.push(' ');
+            }
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+// Reformatting and renaming case should not hide a copy
+fn normalize(text: &str) -> Vec<char> {
+    text.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+// FNV-1a over each K-gram, then the rightmost minimum of every W-window
+fn winnow(chars: &[char]) -> Vec<u64> {
+    if chars.len() < K {
+        return Vec::new();
+    }
+    let hashes: Vec<u64> = chars
+        .windows(K)
+        .map(|gram| {
+            gram.iter().fold(0xcbf29ce484222325u64, |h, &c| (h ^ c as u64).wrapping_mul(0x100000001b3))
+        })
+        .collect();
+    let mut picked = Vec::new();
+    let mut last = usize::MAX;
+    for start in 0..hashes.len().saturating_sub(W - 1).max(1) {
+        let end = (start + W).min(hashes.len());
+        let (offset, _) = hashes[start..end]
+            .iter()
+            .enumerate()
+            .rev()
+            .min_by_key(|(_, h)| **h)
+            .unwrap();
+        if start + offset != last {
+            last = start + offset;
+            picked.push(hashes[last]);
+        }
+    }
+    picked.sort_unstable();
+    picked.dedup();
+    picked
+}
+
+fn dhash(img: &image::DynamicImage) -> u64 {
+    let small = image::imageops::resize(&img.to_luma8(), 9, 8, FilterType::Triangle);
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+pub fn text_hashes(fp: &FileFingerprint) -> Vec<u64> {
+    fp.data.chunks_exact(8).map(|b| u64::from_le_bytes(b.try_into().unwrap())).collect()
+}
+
+pub fn image_hash(fp: &FileFingerprint) -> Option<u64> {
+    fp.data.as_slice().try_into().ok().map(u64::from_le_bytes)
+}
+
+// Hashes seen in work from more than `max_students` different students are
+// treated as provided template code and left out of the comparison.
+pub fn common_hashes<'a>(docs: impl IntoIterator<Item = (&'a str, &'a [u64])>, max_students: usize) -> HashSet<u64> {
+    let mut owners: HashMap<u64, HashSet<&str>> = HashMap::new();
+    for (stu_id, hashes) in docs {
+        for h in hashes {
+            owners.entry(*h).or_default().insert(stu_id);
+        }
+    }
+    owners.into_iter().filter(|(_, s)| s.len() > max_students).map(|(h, _)| h).collect()
+}
+
+// Jaccard index of two sorted hash sets, with the number of shared hashes
+pub fn text_similarity(a: &[u64], b: &[u64]) -> (f64, usize) {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - shared;
+    if union == 0 {
+        return (0.0, 0);
+    }
+    (shared as f64 / union as f64, shared)
+}
+
+pub fn image_similarity(a: u64, b: u64) -> f64 {
+    1.0 - (a ^ b).count_ones() as f64 / 64.0
+}
@@ -0,0 +1,107 @@
+// CSV and XLSX reading and writing for bulk import/export. Everything is
+// handled as rows of strings; callers map header names to fields.
+use calamine::{open_workbook_from_rs, Reader, Xlsx};
+use rust_xlsxwriter::{Format, Workbook};
+use std::borrow::Cow;
+use std::io::Cursor;
+
+pub enum Sheet {
+    Csv,
+    Xlsx,
+}
+
+impl Sheet {
+    pub fn from_name(fname: &str) -> Option<Sheet> {
+        let ext = fname.rsplit('.').next()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "csv" => Some(Sheet::Csv),
+            "xlsx" => Some(Sheet::Xlsx),
+            _ => None,
+        }
+    }
+}
+
+// The first row is the header; blank rows are skipped
+pub fn read_rows(data: &[u8], kind: Sheet) -> Result<Vec<Vec<String>>, String> {
+    let rows = match kind {
+        Sheet::Csv => {
+            // Spreadsheet programs like to prepend a byte order mark
+            let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+            let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(data);
+            reader
+                .records()
+                .map(|r| r.map(|rec| rec.iter().map(|f| f.trim().to_string()).collect()))
+                .collect::<Result<Vec<Vec<String>>, _>>()
+                .map_err(|e| e.to_string())?
+        }
+        Sheet::Xlsx => {
+            let mut book: Xlsx<_> = open_workbook_from_rs(Cursor::new(data)).map_err(|e: calamine::XlsxError| e.to_string())?;
+            let range = book
+                .worksheet_range_at(0)
+                .ok_or("workbook has no sheets")?
+                .map_err(|e| e.to_string())?;
+            range.rows().map(|row| row.iter().map(|c| c.to_string().trim().to_string()).collect()).collect()
+        }
+    };
+    Ok(rows.into_iter().filter(|r: &Vec<String>| r.iter().any(|c| !c.is_empty())).collect())
+}
+
+// Spreadsheet programs evaluate CSV cells that look like formulas, so a
+// leading quote keeps user-entered text such as "=HYPERLINK(..)" inert
+fn escape_csv_cell(value: &str) -> Cow<'_, str> {
+    match value.chars().next() {
+        Some('=' | '+' | '-' | '@' | '\t' | '\r') if value.parse::<f64>().is_err() => Cow::Owned(format!("'{}", value)),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+pub fn write_csv(header: &[&str], rows: &[Vec<String>]) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(header).map_err(|e| e.to_string())?;
+    for row in rows {
+        writer.write_record(row.iter().map(|v| escape_csv_cell(v).into_owned())).map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+// Columns listed in `numeric` are written as numbers so the finance sheet can sum them.
+// Everything else goes through write_string, which never stores a formula.
+pub fn write_xlsx(header: &[&str], rows: &[Vec<String>], numeric: &[usize]) -> Result<Vec<u8>, String> {
+    let mut book = Workbook::new();
+    let sheet = book.add_worksheet();
+    let bold = Format::new().set_bold();
+    for (col, name) in header.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *name, &bold).map_err(|e| e.to_string())?;
+    }
+    for (i, row) in rows.iter().enumerate() {
+        for (col, value) in row.iter().enumerate() {
+            let number = if numeric.contains(&col) { value.parse::<f64>().ok() } else { None };
+            let cell = match number {
+                Some(n) => sheet.write_number(i as u32 + 1, col as u16, n),
+                None => sheet.write_string(i as u32 + 1, col as u16, value),
+            };
+            cell.map_err(|e| e.to_string())?;
+        }
+    }
+    book.save_to_buffer().map_err(|e| e.to_string())
+}
+
+// Maps the header row to column indexes of the wanted fields, case-insensitively
+pub fn header_index(header: &[String], wanted: &[&str]) -> Vec<Option<usize>> {
+    wanted
+        .iter()
+        .map(|w| header.iter().position(|h| h.trim().eq_ignore_ascii_case(w)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_formulas_are_neutralized() {
+        let rows = vec![vec!["=1+1".to_string(), "@SUM(A1)".to_string(), "-2.5".to_string(), "Scope".to_string()]];
+        let out = String::from_utf8(write_csv(&["a", "b", "c", "d"], &rows).unwrap()).unwrap();
+        assert_eq!(out, "a,b,c,d\n'=1+1,'@SUM(A1),-2.5,Scope\n");
+    }
+}
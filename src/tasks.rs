@@ -0,0 +1,63 @@
+// Periodic housekeeping run inside the server process
+use chrono::{Duration as Days, Local};
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+use crate::db;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+// Owners and custodians hear about an expiring calibration this many days ahead
+pub const CALIBRATION_REMIND_DAYS: i64 = 30;
+// Meeting requests left undecided this long are rejected
+pub const MEETING_PENDING_DAYS: i64 = 7;
+
+pub fn start(pool: SqlitePool) {
+    actix_web::rt::spawn(async move {
+        let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tick.tick().await;
+            if let Err(e) = calibration_sweep(&pool).await {
+                log::warn!("calibration sweep failed: {}", e);
+            }
+            if let Err(e) = meeting_expiry_sweep(&pool).await {
+                log::warn!("meeting expiry sweep failed: {}", e);
+            }
+        }
+    });
+}
+
+// Flags expired calibrations and sends one reminder per certificate
+async fn calibration_sweep(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    let today = now.date();
+    for item_id in db::flag_expired_calibrations(pool, today).await? {
+        log::info!("equipment {} is now due for calibration", item_id);
+    }
+    let due = db::list_calibrations_due(pool, today + Days::days(CALIBRATION_REMIND_DAYS), None).await?;
+    for cal in due.into_iter().filter(|c| c.reminded_at.is_none()) {
+        let message = if cal.expires_at < today {
+            format!("Calibration of {} ({}) expired on {}", cal.name, cal.serial, cal.expires_at)
+        } else {
+            format!("Calibration of {} ({}) expires on {}", cal.name, cal.serial, cal.expires_at)
+        };
+        let link = format!("/equipment/{}/maintenance", cal.item_id);
+        let mut recipients = vec![cal.owner_id.clone()];
+        recipients.extend(db::list_equipment_custodians(pool, cal.item_id).await?.into_iter().map(|c| c.user_id));
+        for user_id in recipients {
+            db::add_notification(pool, &user_id, "calibration_due", &message, &link).await?;
+        }
+        db::mark_calibration_reminded(pool, cal.maintenance_id, now).await?;
+    }
+    Ok(())
+}
+
+// Rejects stale meeting requests so they stop holding their slots
+async fn meeting_expiry_sweep(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let now = Local::now().naive_local();
+    for agenda in db::expire_pending_meetings(pool, now - Days::days(MEETING_PENDING_DAYS), now.date()).await? {
+        let message = format!("Your meeting request \"{}\" on {} expired without a decision", agenda.title, agenda.date);
+        let link = format!("/meeting_agenda/{}", agenda.id.unwrap_or_default());
+        db::add_notification(pool, &agenda.userid, "meeting_decision", &message, &link).await?;
+    }
+    Ok(())
+}
@@ -1,9 +1,16 @@
 use serde_json::json;
 use sqlx::SqlitePool;
 use actix_session::Session;
-use crate::config::PERMISSION_ADMIN;
-use actix_web::{HttpResponse, web};
+use crate::config::{Config, PERMISSION_ADMIN};
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
+use actix_web::{HttpRequest, HttpResponse, web};
 use crate::db;
+use crate::models::{Semester, StoredFile, SubCourse, SCAN_INFECTED, SCAN_TOO_LARGE};
+use crate::storage::{self, Blob, Storage, Upload};
+use actix_files::NamedFile;
+use mime_guess::mime;
+use chrono::{Datelike, Duration, NaiveDate};
+use std::net::IpAddr;
 
 pub async fn check_course_perm(
     db_pool: &web::Data<SqlitePool>,
@@ -46,3 +53,185 @@ pub async fn check_subcourse_perm(
         Err(e) => Err(HttpResponse::InternalServerError().json(json!({ "error": e.to_string() }))),
     }
 }
+
+// Get client IP. X-Forwarded-For is only believed when the connection comes
+// from a trusted proxy; it is read right to left, skipping our own proxies,
+// since the leftmost entries are whatever the client chose to send.
+pub fn client_ip(req: &HttpRequest, config: &Config) -> String {
+    let Some(peer) = req.peer_addr().map(|addr| addr.ip()) else {
+        return "0.0.0.0".to_string();
+    };
+    if !config.trusted_proxies.contains(&peer) {
+        return peer.to_string();
+    }
+    let forwarded = req
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    for hop in forwarded.rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) if config.trusted_proxies.contains(&ip) => continue,
+            Ok(ip) => return ip.to_string(),
+            Err(_) => break,
+        }
+    }
+    peer.to_string()
+}
+
+// Calendar date on which a subcourse meets in the given schedule week.
+// Mirrors get_default_log: schedule week = teaching week + lag_week.
+// weekday follows 1 = Monday ... 6 = Saturday, with 0 or 7 for Sunday.
+pub fn lab_date(semester: &Semester, subcourse: &SubCourse, schedule_week: i64) -> NaiveDate {
+    week_date(semester.start, subcourse.weekday, subcourse.lag_week, schedule_week)
+}
+
+pub fn week_date(semester_start: NaiveDate, weekday: i64, lag_week: i64, schedule_week: i64) -> NaiveDate {
+    let first_monday = semester_start - Duration::days(semester_start.weekday().num_days_from_monday() as i64);
+    let week_index = schedule_week - lag_week - 1;
+    first_monday + Duration::weeks(week_index) + Duration::days((weekday + 6) % 7)
+}
+
+// Inverse of week_date: the schedule week a given date falls in, counted from
+// the Monday of the semester's first week.
+pub fn schedule_week(semester_start: NaiveDate, lag_week: i64, date: NaiveDate) -> i64 {
+    let first_monday = semester_start - Duration::days(semester_start.weekday().num_days_from_monday() as i64);
+    (date - first_monday).num_days().div_euclid(7) + 1 + lag_week
+}
+
+// Local blobs go out through NamedFile, remote ones are relayed chunk by chunk
+pub async fn serve_stored_file(req: &HttpRequest, storage: &dyn Storage, pool: &SqlitePool, file_id: i64) -> HttpResponse {
+    match storage::open_file(storage, pool, file_id).await {
+        Ok((file, Blob::Path(path))) => match NamedFile::open_async(path).await {
+            Ok(named) => named
+                .set_content_type(file.mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM))
+                .set_content_disposition(attachment(&file.fname))
+                .into_response(req),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                HttpResponse::NotFound().json(json!({ "error": "File not found in storage" }))
+            }
+            Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+        },
+        Ok((file, Blob::Stream(stream))) => HttpResponse::Ok()
+            .content_type(file.mime)
+            .insert_header(attachment(&file.fname))
+            .streaming(stream),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().json(json!({ "error": "File not found in storage" }))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            HttpResponse::Forbidden().json(json!({ "error": e.to_string() }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+pub fn upload_error(e: std::io::Error) -> HttpResponse {
+    match e.kind() {
+        std::io::ErrorKind::FileTooLarge => HttpResponse::PayloadTooLarge().json(json!({ "error": "File too large" })),
+        _ => HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// Per-user quota, counted against every stored file the user has uploaded
+pub async fn check_quota(pool: &SqlitePool, config: &Config, uploader: &str, upload: &Upload) -> Result<(), HttpResponse> {
+    let used = db::stored_bytes_by_uploader(pool, uploader)
+        .await
+        .map_err(|e| HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })))?;
+    if used + upload.fsize > config.user_quota {
+        return Err(HttpResponse::InsufficientStorage().json(json!({
+            "error": "Storage quota exceeded",
+            "used": used,
+            "quota": config.user_quota,
+        })));
+    }
+    Ok(())
+}
+
+// Student uploads must match the course's allow-list, judged by content rather than extension
+pub async fn check_course_mime(pool: &SqlitePool, course_id: i64, upload: &Upload) -> Result<(), HttpResponse> {
+    let course = db::get_course_by_id(pool, course_id)
+        .await
+        .map_err(|e| HttpResponse::InternalServerError().json(json!({ "error": e.to_string() })))?;
+    if !storage::mime_allowed(&course.allowed_mime, &upload.mime) {
+        return Err(HttpResponse::UnsupportedMediaType().json(json!({
+            "error": format!("File type {} is not allowed in this course", upload.mime),
+        })));
+    }
+    Ok(())
+}
+
+// An ASCII `filename` for old clients plus an RFC 5987 `filename*` carrying the real UTF-8 name
+pub fn attachment(fname: &str) -> ContentDisposition {
+    let fname = storage::sanitize_filename(fname);
+    let fallback: String = fname.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+    let mut parameters = vec![DispositionParam::Filename(fallback)];
+    if !fname.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: fname.into_bytes(),
+        }));
+    }
+    ContentDisposition { disposition: DispositionType::Attachment, parameters }
+}
+
+// Infected uploads stay quarantined for review but the request itself is refused,
+// as are files the scanner would not read in full
+pub fn check_scan(file: &StoredFile) -> Result<(), Box<HttpResponse>> {
+    if file.scan_status == SCAN_INFECTED {
+        return Err(Box::new(HttpResponse::UnprocessableEntity().json(json!({
+            "error": "File failed the virus scan",
+            "signature": file.scan_result,
+        }))));
+    }
+    if file.scan_status == SCAN_TOO_LARGE {
+        return Err(Box::new(HttpResponse::PayloadTooLarge().json(json!({ "error": "File too large for the virus scanner" }))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attachment_ascii_name() {
+        assert_eq!(attachment("report.pdf").to_string(), r#"attachment; filename="report.pdf""#);
+    }
+
+    #[test]
+    fn schedule_week_matches_week_date() {
+        // Semester starting on a Wednesday: the Monday before it opens week 1
+        let start = NaiveDate::from_ymd_opt(2024, 9, 4).unwrap();
+        for week in 1..=18 {
+            for weekday in 0..7 {
+                let date = week_date(start, weekday, 1, week);
+                assert_eq!(schedule_week(start, 1, date), week);
+            }
+        }
+        assert_eq!(schedule_week(start, 0, NaiveDate::from_ymd_opt(2024, 9, 2).unwrap()), 1);
+        assert_eq!(schedule_week(start, 0, NaiveDate::from_ymd_opt(2024, 9, 9).unwrap()), 2);
+    }
+
+    #[test]
+    fn attachment_sanitizes_paths() {
+        assert_eq!(attachment("../../etc/passwd").to_string(), r#"attachment; filename="passwd""#);
+        assert_eq!(attachment("C:\\Users\\li\\a.txt").to_string(), r#"attachment; filename="a.txt""#);
+        assert_eq!(attachment("a\r\nSet-Cookie: x.txt").to_string(), r#"attachment; filename="aSet-Cookie x.txt""#);
+        assert_eq!(attachment("..").to_string(), r#"attachment; filename="unnamed""#);
+    }
+
+    #[test]
+    fn attachment_non_ascii_name() {
+        assert_eq!(
+            attachment("实验报告.pdf").to_string(),
+            "attachment; filename=\"____.pdf\"; filename*=UTF-8''%E5%AE%9E%E9%AA%8C%E6%8A%A5%E5%91%8A.pdf"
+        );
+    }
+
+    #[test]
+    fn attachment_quotes_are_removed() {
+        assert_eq!(attachment("say \"hi\".txt").to_string(), r#"attachment; filename="say hi.txt""#);
+    }
+}